@@ -1,5 +1,6 @@
 use std::rc::Rc;
 
+use syntax::ast;
 use super::expr::*;
 
 pub trait EhtVisitor {
@@ -50,7 +51,7 @@ pub trait EhtVisitor {
                 self.visit_expr(lhs);
                 self.visit_expr(rhs);
             }
-            ExprKind::PrefixIncDec(..) => unimplemented!(),
+            ExprKind::PrefixIncDec(_, lhs) |
             ExprKind::PostfixIncDec(lhs, _) => self.visit_expr(lhs),
             ExprKind::Assign(lhs, rhs) |
             ExprKind::NonblockAssign(lhs, rhs) |
@@ -75,7 +76,8 @@ pub trait EhtVisitor {
     fn do_visit_stmt(&mut self, stmt: &mut Stmt) {
         match &mut stmt.value {
             StmtKind::Empty => (),
-            StmtKind::TimingCtrl(_ctrl, stmt) => {
+            StmtKind::TimingCtrl(ctrl, stmt) => {
+                self.visit_timing_ctrl(ctrl);
                 self.visit_stmt(stmt);
             },
             StmtKind::If { cond, success, failure, .. } => {
@@ -96,6 +98,22 @@ pub trait EhtVisitor {
                 for expr in update { self.visit_expr(expr); }
                 self.visit_stmt(body);
             },
+            StmtKind::While(cond, body) |
+            StmtKind::Repeat(cond, body) |
+            StmtKind::DoWhile(body, cond) => {
+                self.visit_expr(cond);
+                self.visit_stmt(body);
+            },
+            StmtKind::Forever(body) => self.visit_stmt(body),
+            StmtKind::Foreach { array, body, .. } => {
+                self.visit_expr(array);
+                self.visit_stmt(body);
+            },
+            StmtKind::Return(_, expr) => {
+                if let Some(expr) = expr { self.visit_expr(expr); }
+            },
+            StmtKind::Break(_) |
+            StmtKind::Continue(_) => (),
             StmtKind::Assert { expr, success, failure, .. } => {
                 self.visit_expr(expr);
                 if let Some(stmt) = success { self.visit_stmt(stmt); }
@@ -119,4 +137,8 @@ pub trait EhtVisitor {
     fn visit_stmt(&mut self, stmt: &mut Stmt) {
         self.do_visit_stmt(stmt);
     }
+
+    /// Timing controls are not elaborated, so they are left alone unless a visitor needs to
+    /// rename references within them.
+    fn visit_timing_ctrl(&mut self, _ctrl: &mut ast::TimingCtrl) {}
 }
@@ -17,12 +17,14 @@
 
 use syntax::ast::{self, *};
 use syntax::tokens::*;
+use syntax::ast_visit::AstVisitor;
 use source::*;
 use number::{LogicValue, LogicVec, Int};
 
 use num::{BigUint, Zero, ToPrimitive, FromPrimitive};
 use std::cmp;
-use std::collections::HashMap;
+use std::panic;
+use std::collections::{HashMap, HashSet};
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -31,9 +33,16 @@ use super::ty::{IntTy, Struct};
 use super::hier::{self, Ty, Val, HierItem, HierScope};
 use super::ty;
 use super::expr;
+use super::systf::{self, SysFunc, SysFuncKind};
 
 use opts::Opts;
 
+/// Maximum depth of nested constant function calls, which catches infinite recursion.
+const MAX_CONST_CALL_DEPTH: usize = 256;
+
+/// Maximum number of iterations of a single loop in a constant function.
+const MAX_CONST_LOOP_ITERATIONS: usize = 1 << 20;
+
 pub fn elaborate(diag: &DiagMgr, items: &Vec<Vec<Item>>, opts: &Opts) -> hier::Source {
     let mut elaborator = Elaborator::new(diag, opts);
     elaborator.elaborate(items);
@@ -52,6 +61,16 @@ struct Elaborator<'a> {
     scopes: Vec<HierScope>,
     genblk: usize,
 
+    /// Design units being instantiated, innermost last.
+    designs: Vec<Rc<hier::DesignDecl>>,
+    /// Names of instances and generate blocks being elaborated, outermost first. Design units
+    /// instantiated again with the same parameters are not elaborated again, so this is the path
+    /// of their first instance.
+    inst_path: Vec<String>,
+    /// Pairs of instantiated and instantiating design units already warned about for having
+    /// different timescales.
+    timescale_warned: HashSet<(String, String)>,
+
     units: Vec<HierScope>,
 
     /// All packages
@@ -60,6 +79,167 @@ struct Elaborator<'a> {
     /// All elaborated structures.
     structs: Vec<Rc<Struct>>,
     enums: Vec<Rc<ty::Enum>>,
+
+    /// Constant functions being evaluated, innermost last.
+    const_frames: Vec<ConstFrame>,
+
+    /// Return type of the function whose body is being elaborated, which is void for tasks. None
+    /// outside functions and tasks.
+    ret_ty: Option<Ty>,
+    /// Number of loops enclosing the statement being elaborated.
+    loop_depth: usize,
+}
+
+/// State of a constant function call being evaluated.
+struct ConstFrame {
+    func: Rc<hier::FuncDecl>,
+    /// Current values of ports and local variables, keyed by their declarations.
+    vars: HashMap<*const hier::DataDecl, Val>,
+    /// Current value of the implicit variable named after the function.
+    ret: Option<Val>,
+}
+
+/// How execution continues after a statement of a constant function.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConstFlow {
+    Next,
+    Break,
+    Continue,
+    Return,
+}
+
+/// Extract `width` bits starting from bit `offset` of an integral value.
+fn select_bits(val: &LogicVec, offset: usize, width: usize) -> LogicVec {
+    let mut val = val.clone();
+    val.signed = false;
+    val.l_shr(&LogicVec::from_biguint(32, false, BigUint::from_usize(offset).unwrap()));
+    val.extend_or_trunc(width)
+}
+
+/// Replace `part.width()` bits starting from bit `offset` of an integral value.
+fn replace_bits(val: &LogicVec, offset: usize, part: &LogicVec) -> LogicVec {
+    let shift = LogicVec::from_biguint(32, false, BigUint::from_usize(offset).unwrap());
+    let mut mask = LogicVec::fill(part.width(), false, LogicValue::One).extend_or_trunc(val.width());
+    mask <<= &shift;
+    let mut part = part.clone();
+    part.signed = false;
+    let mut part = part.extend_or_trunc(val.width());
+    part <<= &shift;
+
+    let mut result = val.clone();
+    result &= &!mask;
+    result |= &part;
+    result
+}
+
+/// Convert a value to the type of the variable that it is assigned to.
+fn fit_val(val: Val, ty: &Ty) -> Val {
+    match (val, ty) {
+        (Val::Int(val), Ty::Int(intty)) => {
+            let mut val = val.extend_or_trunc(intty.width());
+            if intty.two_state() {
+                val = val.force_two_state();
+            }
+            val.signed = intty.sign();
+            Val::Int(val)
+        }
+        (val, _) => val,
+    }
+}
+
+/// Check if an expression can be bound to an output, inout or ref port.
+fn is_assignable(expr: &expr::Expr) -> bool {
+    match &expr.value {
+        expr::ExprKind::HierName(_) => true,
+        expr::ExprKind::Select(parent, _) |
+        expr::ExprKind::Member(parent, _) => is_assignable(parent),
+        expr::ExprKind::Concat(list) => list.iter().all(is_assignable),
+        _ => false,
+    }
+}
+
+/// Check if an argument of the given type can be bound to a port. Integral and real values
+/// convert to each other and string literals convert to integral values. Other types must match
+/// exactly, except for the bounds of unpacked arrays.
+fn is_arg_compatible(port: &Ty, arg: &Ty) -> bool {
+    match (port, arg) {
+        (Ty::Int(_), Ty::Int(_)) |
+        (Ty::Int(_), Ty::Real(_)) |
+        (Ty::Int(_), Ty::FixStr(_)) |
+        (Ty::Real(_), Ty::Int(_)) |
+        (Ty::Real(_), Ty::Real(_)) => true,
+        (Ty::Array(port, pl, pr), Ty::Array(arg, al, ar)) => {
+            (pl - pr).abs() == (al - ar).abs() && is_arg_compatible(port, arg)
+        }
+        (Ty::Array(..), _) | (_, Ty::Array(..)) => false,
+        _ => port == arg,
+    }
+}
+
+/// Rewrite a foreach loop into nested for loops, iterating from the left bound to the right bound
+/// of each dimension that has a loop variable.
+fn lower_foreach(span: Span, vars: Vec<Option<(Rc<hier::DataDecl>, i32, i32)>>, body: expr::Stmt) -> expr::StmtKind {
+    let int_ty = Ty::Int(IntTy::SimpleVec(32, true, true));
+    let constant = |value: i32| expr::Expr {
+        value: expr::ExprKind::Const(Val::Int(LogicVec::from_integer(value))),
+        span,
+        ty: int_ty.clone(),
+    };
+    if vars.iter().all(Option::is_none) {
+        return expr::StmtKind::SeqBlock(vec![body]);
+    }
+    let mut stmt = body;
+    for (decl, left, right) in vars.into_iter().flatten().rev() {
+        let var = || Box::new(expr::Expr {
+            value: expr::ExprKind::HierName(HierId::Name(None, Box::new(decl.name.clone()))),
+            span,
+            ty: decl.ty.clone(),
+        });
+        let (op, incdec) = if left <= right { (BinaryOp::Leq, IncDec::Inc) } else { (BinaryOp::Geq, IncDec::Dec) };
+        let init = expr::Expr {
+            value: expr::ExprKind::Assign(var(), Box::new(constant(left))),
+            span,
+            ty: decl.ty.clone(),
+        };
+        let cond = expr::Expr {
+            value: expr::ExprKind::Binary(var(), op, Box::new(constant(right))),
+            span,
+            ty: Ty::Int(IntTy::SimpleVec(1, false, false)),
+        };
+        let update = expr::Expr {
+            value: expr::ExprKind::PostfixIncDec(var(), incdec),
+            span,
+            ty: decl.ty.clone(),
+        };
+        stmt = expr::Stmt {
+            label: None,
+            value: expr::StmtKind::For {
+                ty: Some(Box::new(decl.ty.clone())),
+                init: vec![init],
+                cond: Some(Box::new(cond)),
+                update: vec![update],
+                body: Box::new(stmt),
+            },
+        };
+    }
+    stmt.value
+}
+
+/// Bind names in a parameter override given on the command line to parameters of the toplevel
+/// module.
+struct OverrideBinder<'a> {
+    params: &'a [Ident],
+}
+
+impl<'a> AstVisitor for OverrideBinder<'a> {
+    fn visit_hier_name(&mut self, id: &mut HierId) {
+        if let HierId::Name(None, name) = id {
+            if let Some(param) = self.params.iter().find(|param| param.value == name.value) {
+                name.symbol = param.symbol;
+            }
+        }
+        self.do_visit_hier_name(id);
+    }
 }
 
 impl<'a> Elaborator<'a> {
@@ -72,10 +252,19 @@ impl<'a> Elaborator<'a> {
             scopes: Vec::new(),
             genblk: 0,
 
+            designs: Vec::new(),
+            inst_path: Vec::new(),
+            timescale_warned: HashSet::new(),
+
             units: Vec::new(),
             pkgs: HashMap::new(),
             structs: Vec::new(),
             enums: Vec::new(),
+
+            const_frames: Vec::new(),
+
+            ret_ty: None,
+            loop_depth: 0,
         }
     }
 
@@ -113,6 +302,7 @@ impl<'a> Elaborator<'a> {
         let genblk_saved = self.genblk;
         self.genblk = 0;
         self.scopes.push(HierScope::new());
+        self.designs.push(Rc::clone(decl));
 
         // Add instantiated parameters to the scope.
         for decl in param.param.iter() {
@@ -128,11 +318,12 @@ impl<'a> Elaborator<'a> {
                     let ty = self.eval_ty(ty);
                     for assign in list {
                         assert!(assign.dim.len() == 0);
-                        // Default net type to wire. Technically we don't need to do this but some
-                        // SystemVerilog implementation incorrectly assumes the net port type to
-                        // be var when a data type is present.
+                        // Default net type to the one set by `default_nettype, or wire if
+                        // implicit nets are not allowed. Technically we don't need to do this but
+                        // some SystemVerilog implementation incorrectly assumes the net port type
+                        // to be var when a data type is present.
                         let netty = if let NetPortType::Default = net {
-                            NetPortType::Builtin(NetTy::Wire)
+                            NetPortType::Builtin(decl.ast.default_nettype.unwrap_or(NetTy::Wire))
                         } else {
                             net.clone()
                         };
@@ -181,6 +372,7 @@ impl<'a> Elaborator<'a> {
             self.elaborate_item(item);
         }
 
+        self.designs.pop();
         let scope = self.scopes.pop().unwrap();
         self.genblk = genblk_saved;
         let mut inst_list = decl.instances.borrow_mut();
@@ -202,6 +394,31 @@ impl<'a> Elaborator<'a> {
         inst_list.push((Rc::clone(&param), inst));
     }
 
+    /// Warn if a design unit instantiated from the current one has a different timescale. Each
+    /// pair of design units is only reported once.
+    fn check_timescale(&mut self, decl: &hier::DesignDecl, span: Span) {
+        let parent = self.designs.last().unwrap();
+        if parent.ast.timescale == decl.ast.timescale {
+            return;
+        }
+        let key = (decl.ast.name.value.clone(), parent.ast.name.value.clone());
+        if !self.timescale_warned.insert(key) {
+            return;
+        }
+        let describe = |decl: &hier::DesignDecl| match decl.ast.timescale {
+            Some(timescale) => format!("timescale {}", timescale),
+            None => "no timescale".to_owned(),
+        };
+        self.diag.report_span(
+            Severity::Warning,
+            format!(
+                "{} has {} but is instantiated in {} which has {}",
+                decl.ast.name, describe(decl), parent.ast.name, describe(parent)
+            ),
+            span
+        );
+    }
+
     pub fn elaborate_instantiation(&mut self, inst: &HierInstantiation) {
         let item = match self.resolve(&inst.name) {
             HierItem::Design(item) => item,
@@ -398,6 +615,8 @@ impl<'a> Elaborator<'a> {
                 intf: intf_list,
             };
 
+            self.check_timescale(&item, inst.name.span);
+
             // Search for existing instances.
             let design_inst = hier::DesignInstHandle(Rc::clone(&item), 'outer2: loop {
                 for (inst_map, _) in item.instances.borrow().iter() {
@@ -406,7 +625,9 @@ impl<'a> Elaborator<'a> {
                     }
                 }
                 let param = Rc::new(map);
+                self.inst_path.push(inst.name.value.clone());
                 self.instantiate_design(&item, Rc::clone(&param));
+                self.inst_path.pop();
                 break param;
             });
 
@@ -433,13 +654,35 @@ impl<'a> Elaborator<'a> {
         self.genblk = 0;
         self.scopes.push(HierScope::new());
 
+        let opts = self.opts;
+        let mut declared = Vec::new();
         if let Some(param) = &module.ast.param {
             for param in param {
                 // Evaluate the type of this parameter
                 let ty = param.ty.as_ref().map(|ty| self.eval_ty(ty));
                 for assign in &param.list {
+                    // Use the value given on the command line if there is one
+                    let overridden = opts.params.iter().find(|(name, _)| name.value == assign.name.value);
+                    let overridden = match overridden {
+                        Some((name, _)) if param.kw == Keyword::Localparam => {
+                            self.diag.report_error(
+                                format!("{} is a localparam and cannot be overridden", name),
+                                name.span
+                            );
+                            None
+                        }
+                        Some((_, expr)) => {
+                            // The resolver has not seen the override, so bind names in it to
+                            // parameters declared before this one.
+                            let mut expr = expr.clone();
+                            OverrideBinder { params: &declared }.visit_expr(&mut expr);
+                            Some(expr)
+                        }
+                        None => None,
+                    };
+                    declared.push(assign.name.clone());
                     // It's an error if there's no initialiser
-                    let expr = if let Some(v) = assign.init.as_ref() { v } else {
+                    let expr = if let Some(v) = overridden.as_ref().or(assign.init.as_deref()) { v } else {
                         self.diag.report_error(
                             "parameter of top-level module has no default assignment",
                             assign.name.span
@@ -465,6 +708,33 @@ impl<'a> Elaborator<'a> {
             }
         }
 
+        // Check that all overrides refer to existing parameters
+        for (name, _) in &opts.params {
+            let found = module.ast.param.iter().flatten().any(|param| {
+                param.list.iter().any(|assign| assign.name.value == name.value)
+            });
+            if found {
+                continue;
+            }
+            // Parameters in the module body can only be overridden when there is no parameter
+            // port list, which instantiations do not support either.
+            let body_kw = module.ast.items.iter().find_map(|item| match item {
+                Item::ParamDecl(decl) if decl.list.iter().any(|assign| assign.name.value == name.value) => {
+                    Some(decl.kw)
+                }
+                _ => None,
+            });
+            let msg = match body_kw {
+                Some(Keyword::Localparam) => format!("{} is a localparam and cannot be overridden", name),
+                Some(_) => format!(
+                    "parameter {} is declared in the body of toplevel module {} and cannot be overridden",
+                    name, module.ast.name
+                ),
+                None => format!("toplevel module {} has no parameter named {}", module.ast.name, name),
+            };
+            self.diag.report_error(msg, name.span);
+        }
+
         // Now tear down the temporary scope and use its content to build a parameter map.
         // This avoids having to clone the values.
         let items = self.scopes.pop().unwrap().items;
@@ -499,7 +769,9 @@ impl<'a> Elaborator<'a> {
                     break 'outer2;
                 }
             }
+            self.inst_path.push(module.ast.name.value.clone());
             self.instantiate_design(&module, Rc::new(map));
+            self.inst_path.pop();
             break;
         };
     }
@@ -534,21 +806,39 @@ impl<'a> Elaborator<'a> {
             }
             Item::FuncDecl(decl) => {
                 let ty = self.eval_ty(&decl.ty);
-                self.add_to_scope(&decl.name, HierItem::FuncDecl(Rc::new(hier::FuncDecl {
+                let ports = self.elaborate_tf_ports(&decl.ports);
+                // The function is declared before its body is elaborated, so that it can call
+                // itself.
+                let index = self.scopes.last().unwrap().items.len();
+                let func = Rc::new(hier::FuncDecl {
                     lifetime: decl.lifetime,
-                    ty: ty,
+                    ty: ty.clone(),
                     name: decl.name.clone(),
-                    ports: decl.ports.clone(),
-                    stmts: decl.stmts.clone(),
-                })));
+                    ports,
+                    body: Vec::new(),
+                });
+                self.add_to_scope(&decl.name, HierItem::FuncDecl(Rc::clone(&func)));
+                let body = self.elaborate_tf_body(&func.ports, ty, &decl.stmts);
+                drop(func);
+                if let HierItem::FuncDecl(func) = &mut self.scopes.last_mut().unwrap().items[index] {
+                    Rc::get_mut(func).unwrap().body = body;
+                }
             }
             Item::TaskDecl(decl) => {
-                self.add_to_scope(&decl.name, HierItem::TaskDecl(Rc::new(hier::TaskDecl {
+                let ports = self.elaborate_tf_ports(&decl.ports);
+                let index = self.scopes.last().unwrap().items.len();
+                let task = Rc::new(hier::TaskDecl {
                     lifetime: decl.lifetime,
                     name: decl.name.clone(),
-                    ports: decl.ports.clone(),
-                    stmts: decl.stmts.clone(),
-                })));
+                    ports,
+                    body: Vec::new(),
+                });
+                self.add_to_scope(&decl.name, HierItem::TaskDecl(Rc::clone(&task)));
+                let body = self.elaborate_tf_body(&task.ports, Ty::Void, &decl.stmts);
+                drop(task);
+                if let HierItem::TaskDecl(task) = &mut self.scopes.last_mut().unwrap().items[index] {
+                    Rc::get_mut(task).unwrap().body = body;
+                }
             }
             // Package import are already resolved by resolver - discard it.
             Item::PkgImport(_) => (),
@@ -762,6 +1052,11 @@ impl<'a> Elaborator<'a> {
                     let genblk_saved = self.genblk;
                     self.genblk = 0;
                     self.scopes.push(HierScope::new());
+                    let block_name = match &gen.block.name {
+                        Some(name) => name.value.clone(),
+                        None => format!("genblk{}", genblk_saved),
+                    };
+                    self.inst_path.push(format!("{}[{}]", block_name, val));
 
                     // The genvar is automatically converted to a localparam within the block
                     let genvar_item = HierItem::Param(Rc::new(hier::ParamDecl {
@@ -777,6 +1072,7 @@ impl<'a> Elaborator<'a> {
                     }
 
                     let scope = self.scopes.pop().unwrap();
+                    self.inst_path.pop();
                     self.genblk = genblk_saved;
                     let genblk = Rc::new(hier::GenBlock {
                         name: None,
@@ -820,47 +1116,78 @@ impl<'a> Elaborator<'a> {
                 };
 
                 if let Some(block) = block {
-                    let genblk_saved = self.genblk;
-                    self.genblk = 0;
-                    self.scopes.push(HierScope::new());
-                    for item in &block.items {
-                        self.elaborate_item(item);
-                    }
-                    let scope = self.scopes.pop().unwrap();
-                    self.genblk = genblk_saved;
-                    let decl = HierItem::GenBlock(Rc::new(hier::GenBlock {
-                        name: block.name.as_ref().map(|name| Ident::clone(name)),
-                        scope,
-                        id: Some(genblk_saved),
-                    }));
-                    if let Some(v) = &block.name {
-                        self.add_to_scope(v, decl);
-                    } else {
-                        self.add_item(decl);
+                    self.elaborate_gen_block(block);
+                }
+            }
+            Item::CaseGen(casegen) => {
+                self.genblk += 1;
+
+                // First figure out which block to instantiate. Items are compared using case
+                // equality, and the default item is used if none matches.
+                let val = self.eval_const_int(&casegen.expr);
+                let mut block = None;
+                'case_outer: for (arm, item_block) in &casegen.items {
+                    for expr in arm {
+                        let item = self.eval_const_int(expr);
+                        let width = cmp::max(val.width(), item.width());
+                        if val.extend_or_trunc(width).case_eq(&item.extend_or_trunc(width), false, false) {
+                            block = Some(item_block);
+                            break 'case_outer;
+                        }
                     }
                 }
+                let block = block.or_else(|| {
+                    casegen.items.iter().find(|(arm, _)| arm.is_empty()).map(|(_, block)| block)
+                });
+
+                if let Some(block) = block {
+                    self.elaborate_gen_block(block);
+                }
             }
             // GenBlock(Box<GenBlock>),
             Item::SysTfCall(call) => {
                 // Evaluate all arguments
-                let mut arguments = match &call.args {
-                    None => Vec::new(),
-                    Some(list) => list.ordered.iter().map(|expr| self.eval_expr(expr.as_ref().unwrap())).collect(),
-                };
+                let mut arguments = Vec::new();
+                if let Some(list) = &call.args {
+                    for arg in &list.ordered {
+                        match arg {
+                            Some(arg) => arguments.push((arg.span, self.eval_expr(arg).1)),
+                            None => self.diag.report_error(
+                                format!("${} cannot have empty arguments", call.task.value),
+                                call.task.span
+                            ),
+                        }
+                    }
+                }
                 let severity = match call.task.as_str() {
                     "fatal" => {
-                        // For fatal task, we don't care about finish number in elaboration.
-                        if !arguments.is_empty() { arguments.remove(0); }
-                        Severity::Error
+                        // For fatal task, we don't care about finish number in elaboration, but
+                        // it must still be valid.
+                        if !arguments.is_empty() {
+                            let (span, finish_number) = arguments.remove(0);
+                            let valid = match finish_number {
+                                Val::Int(v) => v.get_two_state().and_then(|v| v.to_i32()).is_some_and(|v| (0..=2).contains(&v)),
+                                _ => false,
+                            };
+                            if !valid {
+                                self.diag.report_error("finish number must be 0, 1 or 2", span);
+                            }
+                        }
+                        Severity::Fatal
                     }
                     "error" => Severity::Error,
                     "warning" => Severity::Warning,
                     "info" => Severity::Info,
                     _ => unreachable!(),
                 };
-                // TODO: Arguments of display
-                let formatted = if let Some((_, Val::FixStr(str))) = arguments.get(0) { str } else { "" };
-                self.diag.report_span(severity, formatted, call.task.span);
+                let mut formatted = systf::format_args(self.diag, &arguments, &self.inst_path.join("."));
+                if formatted.is_empty() {
+                    formatted = format!("${} called", call.task.value);
+                }
+                if severity == Severity::Fatal {
+                    self.diag.report_fatal(formatted, call.span);
+                }
+                self.diag.report_span(severity, formatted, call.span);
             }
             Item::ModportDecl(_, list) => {
                 for (name, decl) in list {
@@ -902,6 +1229,33 @@ impl<'a> Elaborator<'a> {
         }
     }
 
+    /// Elaborate the generate block chosen by a conditional generate construct.
+    fn elaborate_gen_block(&mut self, block: &GenBlock) {
+        let genblk_saved = self.genblk;
+        self.genblk = 0;
+        self.scopes.push(HierScope::new());
+        self.inst_path.push(match &block.name {
+            Some(name) => name.value.clone(),
+            None => format!("genblk{}", genblk_saved),
+        });
+        for item in &block.items {
+            self.elaborate_item(item);
+        }
+        self.inst_path.pop();
+        let scope = self.scopes.pop().unwrap();
+        self.genblk = genblk_saved;
+        let decl = HierItem::GenBlock(Rc::new(hier::GenBlock {
+            name: block.name.as_ref().map(|name| Ident::clone(name)),
+            scope,
+            id: Some(genblk_saved),
+        }));
+        if let Some(v) = &block.name {
+            self.add_to_scope(v, decl);
+        } else {
+            self.add_item(decl);
+        }
+    }
+
     pub fn elaborate(&mut self, items: &Vec<Vec<Item>>) {
         self.scopes.push(HierScope::new());
 
@@ -945,6 +1299,54 @@ impl<'a> Elaborator<'a> {
         self.elaborate_toplevel(toplevel);
     }
 
+    /// Elaborate ports of a function or task.
+    fn elaborate_tf_ports(&mut self, ports: &[ast::PortDecl]) -> Vec<hier::TfPortDecl> {
+        let mut list = Vec::new();
+        for port in ports {
+            let (dir, ty, assigns) = match port {
+                ast::PortDecl::Data(dir, _, ty, assigns) => (*dir, ty, assigns),
+                // Ports of subroutines are always parsed as data ports.
+                _ => unreachable!(),
+            };
+            let ty = self.eval_ty(ty);
+            for assign in assigns {
+                let mut port_ty = ty.clone();
+                for (left, right) in self.eval_const_unpacked_dim(&assign.dim).into_iter().rev() {
+                    port_ty = Ty::Array(Box::new(port_ty), left, right);
+                }
+                let init = assign.init.as_ref().map(|expr| Box::new(self.type_check_assign(expr, &port_ty)));
+                list.push(hier::TfPortDecl {
+                    dir,
+                    ty: port_ty,
+                    name: assign.name.clone(),
+                    init,
+                });
+            }
+        }
+        list
+    }
+
+    /// Elaborate the body of a function or task. `ret_ty` is the return type of the function, or
+    /// void for tasks.
+    fn elaborate_tf_body(&mut self, ports: &[hier::TfPortDecl], ret_ty: Ty, stmts: &[ast::Stmt]) -> Vec<expr::Stmt> {
+        self.scopes.push(HierScope::new());
+        for port in ports {
+            self.add_to_scope(&port.name, HierItem::DataDecl(Rc::new(hier::DataDecl {
+                lifetime: ast::Lifetime::Automatic,
+                ty: port.ty.clone(),
+                name: port.name.clone(),
+                init: None,
+            })));
+        }
+        let saved_ret_ty = self.ret_ty.replace(ret_ty);
+        let saved_loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let body = self.elaborate_block(stmts);
+        self.ret_ty = saved_ret_ty;
+        self.loop_depth = saved_loop_depth;
+        self.scopes.pop();
+        body
+    }
+
     // Elaborate a statement block. This will handle the declaration that are only possible to place at the begin of the block.
     fn elaborate_block(&mut self, stmts: &[ast::Stmt]) -> Vec<expr::Stmt> {
         let mut vec = Vec::new();
@@ -994,6 +1396,13 @@ impl<'a> Elaborator<'a> {
         vec
     }
 
+    fn elaborate_loop_body(&mut self, body: &ast::Stmt) -> expr::Stmt {
+        self.loop_depth += 1;
+        let body = self.elaborate_stmt(body);
+        self.loop_depth -= 1;
+        body
+    }
+
     fn elaborate_stmt(&mut self, stmt: &ast::Stmt) -> expr::Stmt {
         let kind = match &stmt.value {
             ast::StmtKind::Empty => expr::StmtKind::Empty,
@@ -1050,7 +1459,7 @@ impl<'a> Elaborator<'a> {
                 let init = init.iter().map(|expr| self.type_check(expr)).collect();
                 let cond = cond.as_ref().map(|expr| Box::new(self.type_check_bool(expr)));
                 let update = update.iter().map(|expr| self.type_check(expr)).collect();
-                let body = Box::new(self.elaborate_stmt(body));
+                let body = Box::new(self.elaborate_loop_body(body));
                 if ty.is_some() {
                     self.scopes.pop();
                 }
@@ -1059,6 +1468,88 @@ impl<'a> Elaborator<'a> {
                     init, cond, update, body
                 }
             },
+            ast::StmtKind::While(cond, body) => {
+                let cond = Box::new(self.type_check_bool(cond));
+                let body = Box::new(self.elaborate_loop_body(body));
+                expr::StmtKind::While(cond, body)
+            }
+            ast::StmtKind::DoWhile(body, cond) => {
+                let body = Box::new(self.elaborate_loop_body(body));
+                let cond = Box::new(self.type_check_bool(cond));
+                expr::StmtKind::DoWhile(body, cond)
+            }
+            ast::StmtKind::Repeat(count, body) => {
+                // The repeat count is self-determined.
+                let count = Box::new(self.type_check_int(count));
+                let body = Box::new(self.elaborate_loop_body(body));
+                expr::StmtKind::Repeat(count, body)
+            }
+            ast::StmtKind::Forever(body) => {
+                expr::StmtKind::Forever(Box::new(self.elaborate_loop_body(body)))
+            }
+            ast::StmtKind::Foreach(array, vars, body) => {
+                let array = self.type_check(array);
+                let dims = systf::dims(&array.ty);
+                if vars.len() > dims.len() {
+                    self.diag.report_error(
+                        format!("foreach loop has {} loop variables but the array only has {} dimensions", vars.len(), dims.len()),
+                        array.span
+                    );
+                }
+                // The scope for loop variables
+                self.scopes.push(HierScope::new());
+                let vars = vars.iter().zip(dims).map(|(var, (left, right))| var.as_ref().map(|name| {
+                    let decl = Rc::new(hier::DataDecl {
+                        lifetime: ast::Lifetime::Automatic,
+                        ty: Ty::Int(IntTy::SimpleVec(32, true, true)),
+                        name: name.clone(),
+                        init: None,
+                    });
+                    self.add_to_scope(name, HierItem::DataDecl(Rc::clone(&decl)));
+                    (decl, left, right)
+                })).collect();
+                let body = self.elaborate_loop_body(body);
+                self.scopes.pop();
+                if self.opts.lower_foreach {
+                    lower_foreach(array.span, vars, body)
+                } else {
+                    expr::StmtKind::Foreach { array: Box::new(array), vars, body: Box::new(body) }
+                }
+            }
+            ast::StmtKind::Return(span, expr) => {
+                let expr = match (&self.ret_ty, expr) {
+                    (None, _) => {
+                        self.diag.report_error("return statement must be within a function or task", *span);
+                        None
+                    }
+                    (Some(Ty::Void), Some(expr)) => {
+                        self.diag.report_error("void functions and tasks cannot return a value", expr.span);
+                        None
+                    }
+                    (Some(Ty::Void), None) => None,
+                    (Some(_), None) => {
+                        self.diag.report_error("return statement must have a value in a non-void function", *span);
+                        None
+                    }
+                    (Some(ty), Some(expr)) => {
+                        let ty = ty.clone();
+                        Some(Box::new(self.type_check_assign(expr, &ty)))
+                    }
+                };
+                expr::StmtKind::Return(*span, expr)
+            }
+            ast::StmtKind::Break(span) => {
+                if self.loop_depth == 0 {
+                    self.diag.report_error("break statement must be within a loop", *span);
+                }
+                expr::StmtKind::Break(*span)
+            }
+            ast::StmtKind::Continue(span) => {
+                if self.loop_depth == 0 {
+                    self.diag.report_error("continue statement must be within a loop", *span);
+                }
+                expr::StmtKind::Continue(*span)
+            }
             ast::StmtKind::Assert { kind, expr, success, failure } => {
                 let expr = Box::new(self.type_check_bool(expr));
                 let success = success.as_ref().map(|stmt| Box::new(self.elaborate_stmt(stmt)));
@@ -1662,6 +2153,209 @@ impl<'a> Elaborator<'a> {
         pattern
     }
 
+    /// Type check the argument of a query system function, which can be either a type or an
+    /// expression, and get the type being queried.
+    fn type_check_query_arg(&mut self, arg: &Expr) -> Ty {
+        let conv = self.type_check(arg);
+        match conv.ty {
+            // If it is a type, then we will evaluate it and use the type.
+            Ty::Type => {
+                if let Val::Type(ty) = self.eval_checked_expr(&conv) {
+                    ty
+                } else {
+                    unreachable!();
+                }
+            }
+            // Otherwise use its type
+            ty => ty,
+        }
+    }
+
+    /// Type check a call to a constant system function. Queries are folded into constants
+    /// immediately as they only depend on types.
+    fn type_check_sys_func(&mut self, func: &SysFunc, call: &ast::SysTfCall, span: Span) -> expr::Expr {
+        let mut args = Vec::new();
+        if let Some(list) = &call.args {
+            for arg in &list.ordered {
+                match arg {
+                    Some(arg) => args.push(arg),
+                    None => self.diag.report_fatal(format!("${} cannot have empty arguments", func.name), span),
+                }
+            }
+        }
+        let (min, max) = func.arity();
+        if args.len() < min || args.len() > max {
+            let msg = if min == max {
+                format!("${} must have exactly {} arguments", func.name, min)
+            } else {
+                format!("${} must have {} to {} arguments", func.name, min, max)
+            };
+            self.diag.report_fatal(msg, call.task.span);
+        }
+
+        let int_ty = Ty::Int(IntTy::SimpleVec(32, false, true));
+        match func.kind {
+            SysFuncKind::SignCast(sign) => {
+                // The result should be same as signed'(xxx) or unsigned'(xxx)
+                let conv = self.type_check_int(args[0]);
+                let ty = match conv.ty {
+                    Ty::Int(ref ty) => Ty::Int(IntTy::SimpleVec(ty.width(), ty.two_state(), sign)),
+                    _ => unreachable!(),
+                };
+                expr::Expr {
+                    value: expr::ExprKind::SignCast(sign, Box::new(conv)),
+                    span,
+                    ty,
+                }
+            }
+            SysFuncKind::TypeQuery(query) => {
+                let ty = self.type_check_query_arg(args[0]);
+                let val = match query(&ty) {
+                    Some(v) => v as i32,
+                    None => self.diag.report_fatal(
+                        format!("${} cannot be applied to this type", func.name),
+                        args[0].span
+                    ),
+                };
+                expr::Expr {
+                    value: expr::ExprKind::Const(Val::Int(LogicVec::from_integer(val))),
+                    span,
+                    ty: int_ty,
+                }
+            }
+            SysFuncKind::DimQuery(query) => {
+                let ty = self.type_check_query_arg(args[0]);
+                let dim = if args.len() > 1 { self.eval_expr_i32(args[1]) } else { 1 };
+                let dims = systf::dims(&ty);
+                // Querying a dimension that does not exist gives X
+                let val = if dim >= 1 && dim as usize <= dims.len() {
+                    let (left, right) = dims[dim as usize - 1];
+                    LogicVec::from_integer(query(left, right))
+                } else {
+                    LogicVec::fill(32, true, LogicValue::X)
+                };
+                expr::Expr {
+                    value: expr::ExprKind::Const(Val::Int(val)),
+                    span,
+                    ty: int_ty,
+                }
+            }
+            SysFuncKind::IntFunc(_) => {
+                let arg = self.type_check_int(args[0]);
+                expr::Expr {
+                    value: expr::ExprKind::SysTfCall(Box::new(call.task.clone()), vec![Some(arg)]),
+                    span,
+                    ty: int_ty,
+                }
+            }
+        }
+    }
+
+    /// Type check a call to a function or task, binding its arguments to the ports.
+    fn type_check_func_call(&mut self, func_expr: &Expr, args: Option<&Args>, span: Span) -> expr::Expr {
+        let id = match &func_expr.value {
+            ExprKind::HierName(id) => id,
+            _ => self.diag.report_fatal("this is not a function or task", func_expr.span),
+        };
+        let (hier, func_conv) = self.type_check_hier_id(id, func_expr.span);
+        let args = match hier {
+            Some(HierItem::FuncDecl(decl)) => self.bind_call_args(&decl.name, &decl.ports, args, span),
+            Some(HierItem::TaskDecl(decl)) => self.bind_call_args(&decl.name, &decl.ports, args, span),
+            _ => self.diag.report_fatal("this is not a function or task", func_expr.span),
+        };
+        let ty = func_conv.ty.clone();
+        expr::Expr {
+            value: expr::ExprKind::FuncCall {
+                expr: Box::new(func_conv),
+                args,
+            },
+            span,
+            ty,
+        }
+    }
+
+    /// Bind arguments of a call to ports of a function or task. The result contains one argument
+    /// for each port in declaration order, with default values filled in for omitted arguments.
+    fn bind_call_args(
+        &mut self, name: &Ident, ports: &[hier::TfPortDecl], args: Option<&Args>, span: Span
+    ) -> Vec<Option<expr::Expr>> {
+        // Match arguments to ports by position first, and then by name.
+        let mut actuals: Vec<Option<&Expr>> = vec![None; ports.len()];
+        let mut bound = vec![false; ports.len()];
+        if let Some(args) = args {
+            // An empty argument list is parsed as a single empty argument.
+            let ordered = match &args.ordered[..] {
+                [None] => &[],
+                ordered => ordered,
+            };
+            if ordered.len() > ports.len() {
+                self.diag.report_error(
+                    format!(
+                        "too many arguments to {}, expected at most {} but found {}",
+                        name, ports.len(), ordered.len()
+                    ),
+                    span
+                );
+            }
+            for (index, arg) in ordered.iter().take(ports.len()).enumerate() {
+                actuals[index] = arg.as_deref();
+                bound[index] = true;
+            }
+            for (port, arg) in &args.named {
+                let index = match ports.iter().position(|formal| formal.name.value == port.value) {
+                    Some(index) => index,
+                    None => {
+                        self.diag.report_error(format!("{} has no port named {}", name, port), port.span);
+                        continue;
+                    }
+                };
+                if bound[index] {
+                    self.diag.report_error(format!("port {} is bound to more than one argument", port), port.span);
+                    continue;
+                }
+                actuals[index] = arg.as_deref();
+                bound[index] = true;
+            }
+        }
+
+        let mut list = Vec::new();
+        for (port, actual) in ports.iter().zip(actuals) {
+            let actual = match (actual, &port.init) {
+                (Some(actual), _) => actual,
+                (None, Some(default)) => {
+                    list.push(Some(expr::Expr::clone(default)));
+                    continue;
+                }
+                (None, None) => {
+                    self.diag.report_error(format!("missing argument for port {} of {}", port.name, name), span);
+                    list.push(None);
+                    continue;
+                }
+            };
+            let conv = match port.dir {
+                PortDir::Input => self.type_check_assign(actual, &port.ty),
+                dir => {
+                    let conv = self.type_check(actual);
+                    if !is_assignable(&conv) {
+                        self.diag.report_error(
+                            format!("argument for {} port {} must be a variable", dir, port.name),
+                            actual.span
+                        );
+                    }
+                    conv
+                }
+            };
+            if !is_arg_compatible(&port.ty, &conv.ty) {
+                self.diag.report_error(
+                    format!("argument type is not compatible with the type of port {}", port.name),
+                    actual.span
+                );
+            }
+            list.push(Some(conv));
+        }
+        list
+    }
+
     /// Perform self-determined type checks and convert expression into an post-elaboration
     /// expression.
     pub fn self_type_check(&mut self, expr: &Expr) -> expr::Expr {
@@ -1797,114 +2491,24 @@ impl<'a> Elaborator<'a> {
                         self.diag.report_error("system task calls should not contain named arguments", call.task.span);
                     }
                 }
-                match call.task.as_str() {
-                    "signed" => {
-                        // The result should be same as signed'(xxx)
-                        let arg_checked = if let Some(args) = &call.args {
-                            args.ordered.len() == 1
-                        } else {
-                            false
-                        };
-                        if !arg_checked {
-                            self.diag.report_fatal("$signed must have exactly 1 arguments", call.task.span);
-                        }
-                        let arg = call.args.as_ref().unwrap().ordered[0].as_ref().unwrap();
-                        let conv = self.type_check_int(arg);
-                        let myty = match conv.ty {
-                            Ty::Int(ref ty) => Ty::Int(IntTy::SimpleVec(ty.width(), ty.two_state(), true)),
-                            _ => unreachable!(),
-                        };
-                        expr::Expr {
-                            value: expr::ExprKind::SignCast(true, Box::new(conv)),
-                            span: expr.span,
-                            ty: myty,
-                        }
-                    }
-                    "clog2" => {
-                        let args = if let Some(args) = &call.args {
-                            args.ordered.iter().map(|v| v.as_ref().map(|v| self.type_check(v))).collect()
-                        } else {
-                            unimplemented!()
-                        };
-                        expr::Expr {
-                            value: expr::ExprKind::SysTfCall(Box::new(call.task.clone()), args),
-                            span: expr.span,
-                            ty: Ty::Int(IntTy::SimpleVec(32, false, true)),
-                        }
-                    }
-                    "bits" => {
-                        let arg_checked = if let Some(args) = &call.args {
-                            args.ordered.len() == 1
-                        } else {
-                            false
-                        };
-                        if !arg_checked {
-                            self.diag.report_fatal("$bits must have exactly 1 arguments", call.task.span);
-                        }
-                        let arg = call.args.as_ref().unwrap().ordered[0].as_ref().unwrap();
-                        let conv = self.type_check(arg);
-                        let ty = match conv.ty {
-                            // If it is a type, then we will evaluate it and use the type.
-                            Ty::Type => {
-                                if let Val::Type(ty) = self.eval_checked_expr(&conv) {
-                                    ty
-                                } else {
-                                    unreachable!();
-                                }
-                            }
-                            // Otherwise use its type
-                            ty => ty,
-                        };
-                        let sz = match ty {
-                            Ty::Int(ty) => ty.width() as i32,
-                            _ => unimplemented!(),
-                        };
-                        expr::Expr {
-                            value: expr::ExprKind::Const(Val::Int(LogicVec::from(32, true, sz.into()))),
-                            span: expr.span,
-                            ty: Ty::Int(IntTy::SimpleVec(32, false, true)),
-                        }
-                    }
-                    _ => {
-                        eprintln!("{:?} unimplemented", call.task);
-                        let args = if let Some(args) = &call.args {
-                            args.ordered.iter().map(|v| v.as_ref().map(|v| self.type_check(v))).collect()
-                        } else {
-                            unimplemented!()
-                        };
-                        expr::Expr {
-                            value: expr::ExprKind::SysTfCall(Box::new(call.task.clone()), args),
-                            span: expr.span,
-                            ty: Ty::Void,
-                        }
-                    }
-                }
-            }
-            ExprKind::FuncCall { expr: ref func_expr, ref args, .. } => {
-                // Named argument is not currently supported
-                if let Some(args) = &args {
-                    if !args.named.is_empty() {
-                        self.diag.report_error("named arguments not yet supported", expr.span);
-                    }
+                if let Some(func) = systf::find(call.task.as_str()) {
+                    return self.type_check_sys_func(func, call, expr.span);
                 }
-                let func_expr = self.type_check(func_expr);
-                let args = if let Some(args) = &args {
+                eprintln!("{:?} unimplemented", call.task);
+                let args = if let Some(args) = &call.args {
                     args.ordered.iter().map(|v| v.as_ref().map(|v| self.type_check(v))).collect()
                 } else {
-                    Vec::new()
+                    unimplemented!()
                 };
-
-                let ty = func_expr.ty.clone();
-
                 expr::Expr {
-                    value: expr::ExprKind::FuncCall {
-                        expr: Box::new(func_expr),
-                        args
-                    },
+                    value: expr::ExprKind::SysTfCall(Box::new(call.task.clone()), args),
                     span: expr.span,
-                    ty,
+                    ty: Ty::Void,
                 }
             }
+            ExprKind::FuncCall { expr: ref func_expr, ref args, .. } => {
+                self.type_check_func_call(func_expr, args.as_deref(), expr.span)
+            }
             // ConstCast(Box<Expr>),
             ExprKind::SignCast(sign, ref inside) => {
                 let inside = self.type_check_int(inside);
@@ -2156,7 +2760,15 @@ impl<'a> Elaborator<'a> {
                     }
                 }
             }
-            // PrefixIncDec(IncDec, Option<Box<AttrInst>>, Box<Expr>),
+            ExprKind::PrefixIncDec(incdec, _, ref lhs) => {
+                let lhs = self.type_check_int(lhs);
+                let ty = lhs.ty.clone();
+                expr::Expr {
+                    value: expr::ExprKind::PrefixIncDec(incdec, Box::new(lhs)),
+                    span: expr.span,
+                    ty: ty,
+                }
+            }
             ExprKind::PostfixIncDec(ref lhs, _, incdec) => {
                 // TODO: Maybe we want to check lvalue here?
                 let lhs = self.type_check_int(lhs);
@@ -2185,7 +2797,30 @@ impl<'a> Elaborator<'a> {
                     ty: Ty::Void,
                 }
             }
-            // BinaryAssign(Box<Expr>, BinaryOp, Box<Expr>),
+            ExprKind::BinaryAssign(ref lhs, op, ref rhs) => {
+                let lhs = self.type_check_int(lhs);
+                // The operation is carried out in the context of the target, except that the
+                // shift amount is self-determined and unsigned.
+                let rhs = match op {
+                    BinaryOp::Shl |
+                    BinaryOp::LShr |
+                    BinaryOp::AShr => {
+                        let mut rhs = self.type_check_int(rhs);
+                        let rhs_ctx = match &rhs.ty {
+                            Ty::Int(subty) => (false, subty.width()),
+                            _ => unreachable!(),
+                        };
+                        self.insert_cast(&mut rhs, rhs_ctx);
+                        rhs
+                    }
+                    _ => self.type_check_assign(rhs, &lhs.ty),
+                };
+                expr::Expr {
+                    value: expr::ExprKind::BinaryAssign(Box::new(lhs), op, Box::new(rhs)),
+                    span: expr.span,
+                    ty: Ty::Void,
+                }
+            }
             ExprKind::Paren(ref expr) => {
                 let conv = self.self_type_check(expr);
                 let ty = conv.ty.clone();
@@ -2440,10 +3075,10 @@ impl<'a> Elaborator<'a> {
                 }
             }
             // Binary(Box<Expr>, BinaryOp, Option<Box<AttrInst>>, Box<Expr>),
-            // PrefixIncDec(IncDec, Option<Box<AttrInst>>, Box<Expr>),
+            expr::ExprKind::PrefixIncDec(..) |
             expr::ExprKind::PostfixIncDec(..) => (),
             // Assign(Box<Expr>, Box<Expr>),
-            // BinaryAssign(Box<Expr>, BinaryOp, Box<Expr>),
+            expr::ExprKind::BinaryAssign(..) => (),
             expr::ExprKind::Paren(ref mut expr) => {
                 self.propagate_size(expr, ctx);
                 return;
@@ -2555,6 +3190,36 @@ impl<'a> Elaborator<'a> {
                             HierItem::GenVar(ref genvar) => {
                                 Val::Int(LogicVec::from_integer(*genvar.value.borrow()))
                             },
+                            // Parameters are folded unless they are selected from
+                            HierItem::Param(ref decl) => decl.init.clone(),
+                            HierItem::DataPort(ref decl) => {
+                                self.diag.report_fatal(format!("{} is not a constant", decl.name), expr.span);
+                            }
+                            HierItem::NetDecl(ref decl) => {
+                                self.diag.report_fatal(format!("{} is not a constant", decl.name), expr.span);
+                            }
+                            // Variables of constant functions being evaluated
+                            HierItem::DataDecl(ref decl) => {
+                                let frame = self.const_frames.last();
+                                match frame.and_then(|frame| frame.vars.get(&Rc::as_ptr(decl))) {
+                                    Some(val) => val.clone(),
+                                    None => self.diag.report_fatal(
+                                        format!("{} is not a constant", decl.name),
+                                        expr.span
+                                    ),
+                                }
+                            }
+                            HierItem::FuncDecl(ref decl) => {
+                                match self.const_frames.last() {
+                                    Some(frame) if Rc::ptr_eq(&frame.func, decl) && frame.ret.is_some() => {
+                                        frame.ret.clone().unwrap()
+                                    }
+                                    _ => self.diag.report_fatal(
+                                        format!("function {} is not called", decl.name),
+                                        expr.span
+                                    ),
+                                }
+                            }
                             _ => unimplemented!(),
                         }
                     }
@@ -2579,23 +3244,37 @@ impl<'a> Elaborator<'a> {
             }
             // MultConcat(Box<Expr>, Box<Expr>),
             // AssignPattern(Option<Box<DataType>>, AssignPattern),
-            // Select(Box<Expr>, Dim),
+            expr::ExprKind::Select(parent, dim) => {
+                let val = match self.eval_checked_expr(parent) {
+                    Val::Int(val) => val,
+                    _ => unimplemented!(),
+                };
+                let (offset, width) = self.eval_select_bits(&parent.ty, dim);
+                Val::Int(match offset {
+                    Some(offset) => select_bits(&val, offset, width),
+                    None => LogicVec::fill(width, false, LogicValue::X),
+                })
+            }
             // Member(Box<Expr>, Ident),
             expr::ExprKind::SysTfCall(task, args) => {
-                match task.as_str() {
-                    "clog2" => (),
-                    v => unimplemented!("{:?}", v),
-                }
-                let args: Vec<_> = args.iter().map(|v| self.eval_checked_expr(v.as_ref().unwrap())).collect();
-                if let Val::Int(vec) = &args[0] {
-                    Val::Int(LogicVec::from_biguint(
-                        32, true,
-                        BigUint::from_usize((vec.get_two_state().unwrap().to_usize().unwrap() as f64).log2().ceil() as usize).unwrap()
-                    ))
+                let func = match systf::find(task.as_str()) {
+                    Some(SysFunc { kind: SysFuncKind::IntFunc(func), .. }) => func,
+                    _ => self.diag.report_fatal(
+                        format!("${} cannot be used in constant expressions", task.value),
+                        task.span
+                    ),
+                };
+                if let Val::Int(val) = self.eval_checked_expr(args[0].as_ref().unwrap()) {
+                    Val::Int(func(&val))
                 } else {
                     unreachable!();
                 }
             }
+            expr::ExprKind::FuncCall { expr: func_expr, args } => {
+                // Arguments are evaluated in the scope of the caller.
+                let args = args.iter().map(|arg| arg.as_ref().map(|arg| self.eval_checked_expr(arg))).collect();
+                self.eval_const_call(func_expr, args, expr.span)
+            }
             // ConstCast(Box<Expr>),
             expr::ExprKind::SignCast(sign, inside) => {
                 let inside_val = self.eval_checked_expr(inside);
@@ -2644,7 +3323,12 @@ impl<'a> Elaborator<'a> {
                             _ => unreachable!(),
                         }
                     }
-                    UnaryOp::Not => unimplemented!(),
+                    UnaryOp::Not => {
+                        match val {
+                            Val::Int(val) => Val::Int(!val),
+                            _ => unreachable!(),
+                        }
+                    }
                     UnaryOp::LNot => {
                         match val {
                             Val::Int(val) => {
@@ -2936,4 +3620,491 @@ impl<'a> Elaborator<'a> {
             }
         }).collect()
     }
+    //
+    // The following section handles constant function evaluation
+    //
+
+    /// Find the offset and width of bits selected from an integral value. The offset is None if
+    /// the selection is out of range or the index is unknown.
+    fn eval_select_bits(&mut self, ty: &Ty, dim: &expr::Dim) -> (Option<usize>, usize) {
+        let (element_width, ub, lb) = match ty {
+            Ty::Int(IntTy::Array(element, ub, lb)) => (element.width(), *ub, *lb),
+            Ty::Int(intty) => (1, intty.width() as i32 - 1, 0),
+            _ => self.diag.report_fatal("only integral values can be selected in constant expressions", dim.span),
+        };
+        let descending = ub >= lb;
+        // Find the index of the least significant element selected.
+        let (index, count) = match &dim.value {
+            expr::DimKind::Value(index) => (self.eval_select_index(index), 1),
+            expr::DimKind::Range(a, b) => (Some(*b), (cmp::max(*a, *b) - cmp::min(*a, *b)) as usize + 1),
+            expr::DimKind::PlusRange(base, width) => {
+                let base = self.eval_select_index(base);
+                (base.map(|base| if descending { base } else { base + *width as i32 - 1 }), *width)
+            }
+            expr::DimKind::MinusRange(base, width) => {
+                let base = self.eval_select_index(base);
+                (base.map(|base| if descending { base - *width as i32 + 1 } else { base }), *width)
+            }
+        };
+        let len = (cmp::max(ub, lb) - cmp::min(ub, lb)) as usize + 1;
+        let offset = index.and_then(|index| {
+            let offset = if descending { index - lb } else { lb - index };
+            if offset < 0 || offset as usize + count > len {
+                None
+            } else {
+                Some(offset as usize * element_width)
+            }
+        });
+        (offset, count * element_width)
+    }
+
+    fn eval_select_index(&mut self, expr: &expr::Expr) -> Option<i32> {
+        match self.eval_checked_expr(expr) {
+            Val::Int(val) => val.get_two_state().and_then(|val| val.to_i32()),
+            _ => None,
+        }
+    }
+
+    /// Evaluate a call to a constant function.
+    fn eval_const_call(&mut self, func_expr: &expr::Expr, args: Vec<Option<Val>>, span: Span) -> Val {
+        let id = match &func_expr.value {
+            expr::ExprKind::HierName(id) => id,
+            _ => self.diag.report_fatal("this function call cannot be evaluated", func_expr.span),
+        };
+        let func = match self.type_check_hier_id(id, func_expr.span).0 {
+            Some(HierItem::FuncDecl(func)) => func,
+            _ => self.diag.report_fatal("this is not a function", func_expr.span),
+        };
+        if func.ty == Ty::Void {
+            self.diag.report_fatal(
+                format!("void function {} cannot be used in constant expressions", func.name),
+                span
+            );
+        }
+        self.call_const_func(id, &func, args, span).unwrap()
+    }
+
+    /// Execute a function in constant evaluation, and return the function's return value.
+    fn call_const_func(
+        &mut self, id: &HierId, func: &Rc<hier::FuncDecl>, args: Vec<Option<Val>>, span: Span
+    ) -> Option<Val> {
+        if self.const_frames.len() >= MAX_CONST_CALL_DEPTH {
+            self.diag.report_fatal(
+                format!(
+                    "constant function {} exceeds maximum recursion depth of {}",
+                    func.name, MAX_CONST_CALL_DEPTH
+                ),
+                span
+            );
+        }
+
+        // Names in the function body are resolved in the scope of the function declaration.
+        let saved = self.enter_subroutine_scope(id);
+        let depth = self.const_frames.len();
+        self.scopes.push(HierScope::new());
+        self.const_frames.push(ConstFrame {
+            func: Rc::clone(func),
+            vars: HashMap::new(),
+            ret: None,
+        });
+
+        // If anything goes wrong within the function body, also point to the call that started
+        // the evaluation.
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| self.exec_const_func(func, args, span)));
+        if let Err(err) = result {
+            if depth == 0 && err.downcast_ref::<Severity>().is_some() {
+                self.diag.report_span(Severity::Info, format!("in call to constant function {}", func.name), span);
+            }
+            panic::resume_unwind(err);
+        }
+
+        let frame = self.const_frames.pop().unwrap();
+        self.scopes.pop();
+        self.leave_subroutine_scope(saved);
+        frame.ret
+    }
+
+    /// Switch to the scope in which the function or task named by `id` is declared. Returns the
+    /// scopes removed, which should be given back to `leave_subroutine_scope`.
+    fn enter_subroutine_scope(&mut self, id: &HierId) -> (Vec<HierScope>, bool) {
+        match id {
+            HierId::Name(Some(Scope::Name(None, pkg)), _) => {
+                let saved = self.scopes.split_off(1);
+                self.scopes.push(self.pkgs[&pkg.value].scope.clone());
+                (saved, true)
+            }
+            HierId::Name(None, name) => {
+                let depth = self.scopes.iter().rposition(|scope| scope.symbols.contains_key(&name.symbol)).unwrap();
+                (self.scopes.split_off(depth + 1), false)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Restore the scopes after `enter_subroutine_scope`.
+    fn leave_subroutine_scope(&mut self, (saved, has_pkg_scope): (Vec<HierScope>, bool)) {
+        if has_pkg_scope {
+            self.scopes.pop();
+        }
+        self.scopes.extend(saved);
+    }
+
+    /// Bind arguments to ports of a constant function and execute its body.
+    fn exec_const_func(&mut self, func: &hier::FuncDecl, args: Vec<Option<Val>>, span: Span) {
+        // Arguments are already bound to ports, with default values filled in.
+        for (port, arg) in func.ports.iter().zip(args) {
+            if port.dir != PortDir::Input {
+                self.diag.report_fatal("constant functions can only have input ports", port.name.span);
+            }
+            if let Ty::Array(..) = port.ty {
+                self.diag.report_fatal(
+                    "unpacked array ports are not supported in constant functions",
+                    port.name.span
+                );
+            }
+            let val = match arg {
+                Some(val) => val,
+                None => self.diag.report_fatal(format!("missing argument for port {}", port.name), span),
+            };
+            self.declare_const_var(&port.name, &port.ty, val);
+        }
+
+        if func.ty != Ty::Void {
+            let val = self.const_default(&func.ty, func.name.span);
+            self.const_frames.last_mut().unwrap().ret = Some(val);
+        }
+        for stmt in &func.body {
+            if self.exec_const_stmt(stmt) == ConstFlow::Return {
+                break;
+            }
+        }
+    }
+
+    /// Get the initial value of a variable in a constant function.
+    fn const_default(&self, ty: &Ty, span: Span) -> Val {
+        match ty {
+            Ty::Int(intty) => {
+                let val = if intty.two_state() { LogicValue::Zero } else { LogicValue::X };
+                Val::Int(LogicVec::fill(intty.width(), intty.sign(), val))
+            }
+            Ty::Real(_) => Val::Real(0.0),
+            _ => self.diag.report_fatal("variables of this type are not supported in constant functions", span),
+        }
+    }
+
+    /// Declare a variable of a constant function in the current scope.
+    fn declare_const_var(&mut self, name: &Ident, ty: &Ty, val: Val) {
+        let decl = Rc::new(hier::DataDecl {
+            lifetime: ast::Lifetime::Automatic,
+            ty: ty.clone(),
+            name: name.clone(),
+            init: None,
+        });
+        self.bind_const_var(&decl, val);
+    }
+
+    /// Bring a variable declared in the body of a constant function into the current scope.
+    fn bind_const_var(&mut self, decl: &Rc<hier::DataDecl>, val: Val) {
+        self.const_frames.last_mut().unwrap().vars.insert(Rc::as_ptr(decl), fit_val(val, &decl.ty));
+        self.add_to_scope(&decl.name, HierItem::DataDecl(Rc::clone(decl)));
+    }
+
+    /// Assign to a variable of a constant function, or part of it.
+    fn assign_const_var(&mut self, lhs: &expr::Expr, val: Val) {
+        match &lhs.value {
+            expr::ExprKind::HierName(HierId::Name(None, name)) => {
+                let hier = self.resolve(name);
+                let frame = self.const_frames.last_mut().unwrap();
+                match hier {
+                    HierItem::DataDecl(ref decl) if frame.vars.contains_key(&Rc::as_ptr(decl)) => {
+                        frame.vars.insert(Rc::as_ptr(decl), fit_val(val, &decl.ty));
+                    }
+                    HierItem::FuncDecl(ref decl) if Rc::ptr_eq(&frame.func, decl) && frame.ret.is_some() => {
+                        frame.ret = Some(fit_val(val, &decl.ty));
+                    }
+                    _ => self.diag.report_fatal(
+                        format!("{} cannot be assigned in a constant function", name),
+                        lhs.span
+                    ),
+                }
+            }
+            expr::ExprKind::Select(parent, dim) => {
+                let old = match self.eval_checked_expr(parent) {
+                    Val::Int(val) => val,
+                    _ => self.diag.report_fatal("only integral variables can be partially assigned", lhs.span),
+                };
+                let part = match val {
+                    Val::Int(val) => val,
+                    _ => self.diag.report_fatal("value assigned to a part-select must be integral", lhs.span),
+                };
+                let new = match self.eval_select_bits(&parent.ty, dim) {
+                    (Some(offset), width) => replace_bits(&old, offset, &part.extend_or_trunc(width)),
+                    // Writes outside of the range have no effect.
+                    (None, _) => old,
+                };
+                self.assign_const_var(parent, Val::Int(new));
+            }
+            _ => self.diag.report_fatal("unsupported assignment target in constant function", lhs.span),
+        }
+    }
+
+    /// Evaluate an integral constant expression.
+    fn eval_const_int(&mut self, expr: &Expr) -> LogicVec {
+        match self.eval_expr(expr).1 {
+            Val::Int(val) => val,
+            _ => self.diag.report_fatal("this expression must evaluate to integral number", expr.span),
+        }
+    }
+
+    /// Evaluate a type checked integral expression within a constant function.
+    fn eval_checked_int(&mut self, expr: &expr::Expr) -> LogicVec {
+        match self.eval_checked_expr(expr) {
+            Val::Int(val) => val,
+            _ => self.diag.report_fatal("this expression must evaluate to integral number", expr.span),
+        }
+    }
+
+    /// Count an iteration of a loop in a constant function, stopping elaboration if there are too
+    /// many of them.
+    fn count_const_iteration(&self, iterations: &mut usize, span: Span) {
+        *iterations += 1;
+        if *iterations > MAX_CONST_LOOP_ITERATIONS {
+            self.diag.report_fatal(
+                format!("loop in constant function exceeds {} iterations", MAX_CONST_LOOP_ITERATIONS),
+                span
+            );
+        }
+    }
+
+    /// Execute the body of a foreach loop in a constant function for all values of the given loop
+    /// variables, the first one changing slowest. Returns how execution continues if the loop is
+    /// left early.
+    fn exec_const_foreach(
+        &mut self, vars: &[Option<(Rc<hier::DataDecl>, i32, i32)>], body: &expr::Stmt,
+        iterations: &mut usize, span: Span,
+    ) -> Option<ConstFlow> {
+        let (decl, left, right) = match vars.split_first() {
+            None => {
+                self.count_const_iteration(iterations, span);
+                return self.exec_const_loop_body(body);
+            }
+            Some((None, rest)) => return self.exec_const_foreach(rest, body, iterations, span),
+            Some((Some(var), _)) => var.clone(),
+        };
+        let step = if left <= right { 1 } else { -1 };
+        let mut index = left;
+        loop {
+            self.const_frames.last_mut().unwrap().vars.insert(
+                Rc::as_ptr(&decl),
+                Val::Int(LogicVec::from_integer(index))
+            );
+            let flow = self.exec_const_foreach(&vars[1..], body, iterations, span);
+            if flow.is_some() || index == right {
+                return flow;
+            }
+            index += step;
+        }
+    }
+
+    /// Execute the body of a loop in a constant function. Returns None if the loop goes on, or how
+    /// execution continues after the loop if it is left.
+    fn exec_const_loop_body(&mut self, body: &expr::Stmt) -> Option<ConstFlow> {
+        match self.exec_const_stmt(body) {
+            ConstFlow::Break => Some(ConstFlow::Next),
+            ConstFlow::Return => Some(ConstFlow::Return),
+            _ => None,
+        }
+    }
+
+    /// Execute a statement of a constant function. Errors already reported when the body was
+    /// elaborated, such as misplaced `return` or `break`, are not reported again.
+    fn exec_const_stmt(&mut self, stmt: &expr::Stmt) -> ConstFlow {
+        let mut flow = ConstFlow::Next;
+        match &stmt.value {
+            expr::StmtKind::Empty => (),
+            expr::StmtKind::If { cond, success, failure, .. } => {
+                if self.eval_checked_int(cond).to_bool() == LogicValue::One {
+                    flow = self.exec_const_stmt(success);
+                } else if let Some(failure) = failure {
+                    flow = self.exec_const_stmt(failure);
+                }
+            }
+            expr::StmtKind::Case { kw, expr, items, .. } => {
+                let val = self.eval_checked_int(expr);
+                let ignore_x = *kw == CaseKw::Casex;
+                let ignore_z = *kw != CaseKw::Case;
+                let item = items.iter().find(|(conds, _)| conds.iter().any(|cond| {
+                    let cond = self.eval_checked_int(cond);
+                    let width = cmp::max(val.width(), cond.width());
+                    val.extend_or_trunc(width).case_eq(&cond.extend_or_trunc(width), ignore_x, ignore_z)
+                })).or_else(|| items.iter().find(|(conds, _)| conds.is_empty()));
+                if let Some((_, stmt)) = item {
+                    flow = self.exec_const_stmt(stmt);
+                }
+            }
+            expr::StmtKind::For { ty, init, cond, update, body } => {
+                if let Some(ty) = ty {
+                    self.scopes.push(HierScope::new());
+                    for expr in init {
+                        if let expr::ExprKind::Assign(lhs, _) = &expr.value {
+                            if let expr::ExprKind::HierName(HierId::Name(None, name)) = &lhs.value {
+                                let val = self.const_default(ty, name.span);
+                                self.declare_const_var(name, ty, val);
+                            }
+                        }
+                    }
+                }
+                for expr in init {
+                    self.exec_const_expr(expr);
+                }
+                let mut iterations = 0;
+                while cond.as_ref().is_none_or(|cond| self.eval_checked_int(cond).to_bool() == LogicValue::One) {
+                    self.count_const_iteration(&mut iterations, cond.as_ref().map_or(Span::none(), |cond| cond.span));
+                    if let Some(next) = self.exec_const_loop_body(body) {
+                        flow = next;
+                        break;
+                    }
+                    for expr in update {
+                        self.exec_const_expr(expr);
+                    }
+                }
+                if ty.is_some() {
+                    self.scopes.pop();
+                }
+            }
+            expr::StmtKind::While(cond, body) => {
+                let mut iterations = 0;
+                while self.eval_checked_int(cond).to_bool() == LogicValue::One {
+                    self.count_const_iteration(&mut iterations, cond.span);
+                    if let Some(next) = self.exec_const_loop_body(body) {
+                        flow = next;
+                        break;
+                    }
+                }
+            }
+            expr::StmtKind::DoWhile(body, cond) => {
+                let mut iterations = 0;
+                loop {
+                    self.count_const_iteration(&mut iterations, cond.span);
+                    if let Some(next) = self.exec_const_loop_body(body) {
+                        flow = next;
+                        break;
+                    }
+                    if self.eval_checked_int(cond).to_bool() != LogicValue::One { break }
+                }
+            }
+            expr::StmtKind::Repeat(count, body) => {
+                // A count that is negative or contains X or Z executes the body zero times.
+                let mut iterations = 0;
+                let times = self.eval_checked_int(count);
+                let times = times.get_two_state().and_then(|v| v.to_usize()).unwrap_or(0);
+                for _ in 0..times {
+                    self.count_const_iteration(&mut iterations, count.span);
+                    if let Some(next) = self.exec_const_loop_body(body) {
+                        flow = next;
+                        break;
+                    }
+                }
+            }
+            expr::StmtKind::Forever(body) => {
+                let span = self.const_frames.last().unwrap().func.name.span;
+                let mut iterations = 0;
+                loop {
+                    self.count_const_iteration(&mut iterations, span);
+                    if let Some(next) = self.exec_const_loop_body(body) {
+                        flow = next;
+                        break;
+                    }
+                }
+            }
+            expr::StmtKind::Foreach { array, vars, body } => {
+                self.scopes.push(HierScope::new());
+                for (decl, _, _) in vars.iter().flatten() {
+                    self.bind_const_var(decl, Val::Int(LogicVec::from_integer(0)));
+                }
+                let mut iterations = 0;
+                if let Some(next) = self.exec_const_foreach(vars, body, &mut iterations, array.span) {
+                    flow = next;
+                }
+                self.scopes.pop();
+            }
+            expr::StmtKind::SeqBlock(list) => {
+                self.scopes.push(HierScope::new());
+                for stmt in list {
+                    flow = self.exec_const_stmt(stmt);
+                    if flow != ConstFlow::Next { break }
+                }
+                self.scopes.pop();
+            }
+            expr::StmtKind::Return(_, expr) => {
+                if let Some(expr) = expr {
+                    let val = self.eval_checked_expr(expr);
+                    let frame = self.const_frames.last_mut().unwrap();
+                    frame.ret = Some(fit_val(val, &frame.func.ty));
+                }
+                flow = ConstFlow::Return;
+            }
+            expr::StmtKind::Break(_) => flow = ConstFlow::Break,
+            expr::StmtKind::Continue(_) => flow = ConstFlow::Continue,
+            expr::StmtKind::Expr(expr) => self.exec_const_expr(expr),
+            expr::StmtKind::DataDecl(decl) => {
+                if let Ty::Array(..) = decl.ty {
+                    self.diag.report_fatal(
+                        "unpacked array variables are not supported in constant functions",
+                        decl.name.span
+                    );
+                }
+                let val = match &decl.init {
+                    Some(init) => self.eval_checked_expr(init),
+                    None => self.const_default(&decl.ty, decl.name.span),
+                };
+                self.bind_const_var(decl, val);
+            }
+            expr::StmtKind::TimingCtrl(..) => {
+                let func = &self.const_frames.last().unwrap().func;
+                self.diag.report_fatal("timing controls are not allowed in constant functions", func.name.span);
+            }
+            expr::StmtKind::Assert { expr, .. } => {
+                self.diag.report_fatal("assertions are not supported in constant functions", expr.span);
+            }
+        }
+        flow
+    }
+
+    /// Execute an expression statement within a constant function.
+    fn exec_const_expr(&mut self, expr: &expr::Expr) {
+        match &expr.value {
+            expr::ExprKind::Assign(lhs, rhs) => {
+                let val = self.eval_checked_expr(rhs);
+                self.assign_const_var(lhs, val);
+            }
+            expr::ExprKind::BinaryAssign(lhs, op, rhs) => {
+                // The right hand side is already converted to the context of the target.
+                let value = expr::Expr {
+                    value: expr::ExprKind::Binary(lhs.clone(), *op, rhs.clone()),
+                    span: expr.span,
+                    ty: lhs.ty.clone(),
+                };
+                let val = self.eval_checked_expr(&value);
+                self.assign_const_var(lhs, val);
+            }
+            expr::ExprKind::PrefixIncDec(incdec, lhs) |
+            expr::ExprKind::PostfixIncDec(lhs, incdec) => {
+                let val = match self.eval_checked_expr(lhs) {
+                    Val::Int(mut val) => {
+                        val += if *incdec == IncDec::Inc { 1 } else { -1 };
+                        val
+                    }
+                    _ => self.diag.report_fatal("only integral variables can be incremented", lhs.span),
+                };
+                self.assign_const_var(lhs, Val::Int(val));
+            }
+            expr::ExprKind::NonblockAssign(..) => {
+                self.diag.report_fatal("nonblocking assignments are not allowed in constant functions", expr.span);
+            }
+            _ => {
+                self.eval_checked_expr(expr);
+            }
+        }
+    }
 }
@@ -69,6 +69,8 @@ pub enum ExprKind {
     // Subroutine calls
     /// Call to system task
     SysTfCall(Box<Spanned<String>>, Vec<Option<Expr>>),
+    /// Call to function or task. There is one argument for each port in declaration order, with
+    /// default values filled in. An argument is None only if it is missing, which is an error.
     FuncCall {
         expr: Box<Expr>,
         args: Vec<Option<Expr>>,
@@ -164,6 +166,20 @@ pub enum StmtKind {
         update: Vec<Expr>,
         body: Box<Stmt>,
     },
+    While(Box<Expr>, Box<Stmt>),
+    DoWhile(Box<Stmt>, Box<Expr>),
+    Repeat(Box<Expr>, Box<Stmt>),
+    Forever(Box<Stmt>),
+    Foreach {
+        array: Box<Expr>,
+        /// Loop variables, each with the left and right bound of the dimension it iterates over.
+        /// Skipped dimensions are None.
+        vars: Vec<Option<(Rc<hier::DataDecl>, i32, i32)>>,
+        body: Box<Stmt>,
+    },
+    Return(Span, Option<Box<Expr>>),
+    Break(Span),
+    Continue(Span),
     Assert {
         kind: (),
         expr: Box<Expr>,
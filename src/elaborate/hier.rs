@@ -54,23 +54,33 @@ pub struct NetDecl {
     pub init: Option<Box<Expr>>,
 }
 
-/// Partially resolved function declaration
+/// Resolved port of a function or task
+#[derive(Debug, Clone)]
+pub struct TfPortDecl {
+    pub dir: ast::PortDir,
+    pub ty: Ty,
+    pub name: Ident,
+    /// Default value, which is type checked in the scope of the declaration.
+    pub init: Option<Box<Expr>>,
+}
+
+/// Resolved function declaration
 #[derive(Debug)]
 pub struct FuncDecl {
     pub lifetime: ast::Lifetime,
     pub ty: Ty,
     pub name: Ident,
-    pub ports: Vec<ast::PortDecl>,
-    pub stmts: Vec<ast::Stmt>,
+    pub ports: Vec<TfPortDecl>,
+    pub body: Vec<Stmt>,
 }
 
-/// Partially resolved function declaration
+/// Resolved task declaration
 #[derive(Debug)]
 pub struct TaskDecl {
     pub lifetime: ast::Lifetime,
     pub name: Ident,
-    pub ports: Vec<ast::PortDecl>,
-    pub stmts: Vec<ast::Stmt>,
+    pub ports: Vec<TfPortDecl>,
+    pub body: Vec<Stmt>,
 }
 
 /// Un-instantiated module during resolution and elaboration
@@ -0,0 +1,390 @@
+//! Replace `return`, `break` and `continue` with flag variables, as Verilog-2005 has no jump
+//! statements. A jump sets its flag, statements following it are only executed if no flag is
+//! set, and loops stop once a `return` or `break` flag is set. Jumps that skip no statements,
+//! such as a `return` at the end of a function, are replaced without introducing a flag.
+//!
+//! `for` loops that can be left early are turned into `while` loops, so that their update is
+//! not executed after leaving the loop.
+
+use std::mem;
+use std::collections::HashSet;
+
+use syntax::ast::*;
+use syntax::ast_visit::AstVisitor;
+use super::reconstruct::reconstruct_usize;
+
+/// Lower jump statements within the body of a function, task or procedural block. `func` is the
+/// function whose result is set by `return`, and `names` contains names that flag variables must
+/// not clash with, in addition to names used within the body.
+pub fn jump_elim(stmts: &mut Vec<Stmt>, func: Option<&Ident>, mut names: HashSet<String>) {
+    let mut finder = JumpFinder {
+        names: &mut names,
+        jumps: Jumps::default(),
+    };
+    for stmt in stmts.iter_mut() {
+        finder.visit_stmt(stmt);
+    }
+    if !finder.jumps.any() {
+        return;
+    }
+
+    let mut elim = JumpEliminator {
+        func: func.cloned(),
+        names,
+        ret: None,
+        brk: None,
+        cont: None,
+    };
+    // Nothing follows the body, so a `return` at its end skips no statements.
+    let tail = Jumps { ret: true, ..Jumps::default() };
+    let body = elim.lower_list(mem::take(stmts), tail).0;
+
+    // Flags are declared and cleared before the body, as functions can be static.
+    let flags: Vec<Ident> = elim.ret.iter().chain(&elim.brk).chain(&elim.cont).cloned().collect();
+    if flags.is_empty() {
+        *stmts = body;
+        return;
+    }
+    let mut list = vec![new_stmt(StmtKind::DataDecl(Box::new(DataDecl {
+        attr: None,
+        has_const: false,
+        lifetime: Lifetime::Static,
+        ty: Spanned::new_unspanned(DataTypeKind::IntVec(IntVecTy::Logic, Signing::Unsigned, Vec::new())),
+        list: flags.iter().map(|name| DeclAssign {
+            name: name.clone(),
+            dim: Vec::new(),
+            init: None,
+        }).collect(),
+    })))];
+    list.extend(flags.iter().map(|name| set_flag(name, 0)));
+    list.extend(body);
+    *stmts = list;
+}
+
+/// Build a statement from its kind.
+fn new_stmt(value: StmtKind) -> Stmt {
+    Stmt {
+        label: None,
+        attr: None,
+        value,
+    }
+}
+
+fn name_expr(name: &Ident) -> Expr {
+    Spanned::new_unspanned(ExprKind::HierName(HierId::Name(None, Box::new(name.clone()))))
+}
+
+fn assign_stmt(lhs: Expr, rhs: Expr) -> Stmt {
+    let span = rhs.span;
+    new_stmt(StmtKind::Expr(Box::new(Spanned::new(ExprKind::Assign(Box::new(lhs), Box::new(rhs)), span))))
+}
+
+fn set_flag(name: &Ident, value: usize) -> Stmt {
+    assign_stmt(name_expr(name), reconstruct_usize(value))
+}
+
+/// Build a condition that holds if none of the given flags is set, and the condition if any.
+fn none_set(flags: &[&Ident], cond: Option<Box<Expr>>) -> Box<Expr> {
+    let mut list = flags.iter().map(|name| {
+        Box::new(Spanned::new_unspanned(ExprKind::Unary(UnaryOp::LNot, None, Box::new(name_expr(name)))))
+    }).chain(cond.map(|cond| Box::new(Spanned::new(ExprKind::Paren(cond.clone()), cond.span))));
+    let first = list.next().unwrap();
+    list.fold(first, |acc, expr| Box::new(Spanned::new_unspanned(ExprKind::Binary(acc, BinaryOp::LAnd, None, expr))))
+}
+
+/// Kinds of jump statements that may be executed.
+#[derive(Default, Clone, Copy)]
+struct Jumps {
+    ret: bool,
+    brk: bool,
+    cont: bool,
+}
+
+impl Jumps {
+    fn any(self) -> bool {
+        self.ret || self.brk || self.cont
+    }
+
+    fn or(self, other: Jumps) -> Jumps {
+        Jumps {
+            ret: self.ret || other.ret,
+            brk: self.brk || other.brk,
+            cont: self.cont || other.cont,
+        }
+    }
+}
+
+/// Find out which jump statements are used, and collect all names used.
+struct JumpFinder<'a> {
+    names: &'a mut HashSet<String>,
+    jumps: Jumps,
+}
+
+impl<'a> AstVisitor for JumpFinder<'a> {
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        match &stmt.value {
+            StmtKind::Return(..) => self.jumps.ret = true,
+            StmtKind::Break(_) => self.jumps.brk = true,
+            StmtKind::Continue(_) => self.jumps.cont = true,
+            _ => (),
+        }
+        if let Some(label) = &stmt.label {
+            self.names.insert(label.value.clone());
+        }
+        self.do_visit_stmt(stmt);
+    }
+
+    fn visit_data_decl(&mut self, decl: &mut DataDecl) {
+        self.visit_ty(&mut decl.ty);
+        for assign in &mut decl.list {
+            self.names.insert(assign.name.value.clone());
+            if let Some(init) = &mut assign.init {
+                self.visit_expr(init);
+            }
+        }
+    }
+
+    fn visit_hier_name(&mut self, id: &mut HierId) {
+        match id {
+            HierId::Name(_, name) => { self.names.insert(name.value.clone()); }
+            HierId::Member(id, _) => self.visit_hier_name(id),
+            HierId::Select(id, dim) => {
+                self.visit_hier_name(id);
+                self.visit_dim(dim);
+            }
+            _ => (),
+        }
+    }
+}
+
+struct JumpEliminator {
+    func: Option<Ident>,
+    /// Names that flags must not clash with.
+    names: HashSet<String>,
+    /// Flags, which are only created once a jump needs them.
+    ret: Option<Ident>,
+    brk: Option<Ident>,
+    cont: Option<Ident>,
+}
+
+/// Get a flag, creating it with a name not used elsewhere if it does not exist yet.
+fn flag(flag: &mut Option<Ident>, names: &mut HashSet<String>, name: &str) -> Stmt {
+    let name = flag.get_or_insert_with(|| {
+        let mut candidate = name.to_owned();
+        let mut suffix = 0;
+        while names.contains(&candidate) {
+            suffix += 1;
+            candidate = format!("{}_{}", name, suffix);
+        }
+        names.insert(candidate.clone());
+        Ident::new_unspanned(candidate)
+    });
+    set_flag(name, 1)
+}
+
+impl JumpEliminator {
+    /// Flags set by the given kinds of jumps.
+    fn flags(&self, jumps: Jumps) -> Vec<&Ident> {
+        let mut flags = Vec::new();
+        if jumps.ret { flags.extend(&self.ret) }
+        if jumps.brk { flags.extend(&self.brk) }
+        if jumps.cont { flags.extend(&self.cont) }
+        flags
+    }
+
+    /// Lower a list of statements, guarding statements that follow a possible jump. `tail` holds
+    /// the kinds of jumps that would skip no statements if executed after the list.
+    fn lower_list(&mut self, list: Vec<Stmt>, tail: Jumps) -> (Vec<Stmt>, Jumps) {
+        let mut out = Vec::new();
+        let mut iter = list.into_iter().peekable();
+        while let Some(mut stmt) = iter.next() {
+            let stmt_tail = if iter.peek().is_none() { tail } else { Jumps::default() };
+            let jumps = self.lower_stmt(&mut stmt, stmt_tail);
+            // Jumps that skip nothing are lowered to empty statements, which need not be kept.
+            if let StmtKind::Empty = stmt.value {
+                continue;
+            }
+            out.push(stmt);
+            if jumps.any() {
+                let (rest, rest_jumps) = self.lower_list(iter.collect(), tail);
+                if !rest.is_empty() {
+                    let cond = none_set(&self.flags(jumps), None);
+                    let rest = Box::new(new_stmt(StmtKind::SeqBlock(rest)));
+                    out.push(new_stmt(StmtKind::If(None, cond, rest, None)));
+                }
+                return (out, jumps.or(rest_jumps));
+            }
+        }
+        (out, Jumps::default())
+    }
+
+    /// Lower the body of a loop, clearing the `continue` flag at the end of each iteration.
+    /// Returns the jumps that leave the loop.
+    fn lower_loop_body(&mut self, body: &mut Box<Stmt>) -> Jumps {
+        // Only a `continue` at the end of the body skips nothing, as other jumps skip remaining
+        // iterations.
+        let jumps = self.lower_stmt(body, Jumps { cont: true, ..Jumps::default() });
+        if jumps.cont {
+            let inner = mem::replace(&mut **body, new_stmt(StmtKind::Empty));
+            **body = new_stmt(StmtKind::SeqBlock(vec![inner, set_flag(self.cont.as_ref().unwrap(), 0)]));
+        }
+        Jumps {
+            cont: false,
+            ..jumps
+        }
+    }
+
+    /// Lower a statement, and return which kinds of jumps it may execute that need a flag. `tail`
+    /// holds the kinds of jumps that would skip no statements if executed after this one.
+    fn lower_stmt(&mut self, stmt: &mut Stmt, tail: Jumps) -> Jumps {
+        match &mut stmt.value {
+            StmtKind::Return(span, expr) => {
+                let mut list = Vec::new();
+                if let (Some(func), Some(expr)) = (&self.func, expr.take()) {
+                    list.push(assign_stmt(Spanned::new(name_expr(func).value, *span), *expr));
+                }
+                if tail.ret {
+                    stmt.value = match list.pop() {
+                        Some(assign) => assign.value,
+                        None => StmtKind::Empty,
+                    };
+                    return Jumps::default();
+                }
+                list.push(flag(&mut self.ret, &mut self.names, "return_flag"));
+                stmt.value = StmtKind::SeqBlock(list);
+                Jumps { ret: true, ..Jumps::default() }
+            }
+            StmtKind::Break(_) => {
+                stmt.value = flag(&mut self.brk, &mut self.names, "break_flag").value;
+                Jumps { brk: true, ..Jumps::default() }
+            }
+            StmtKind::Continue(_) => {
+                if tail.cont {
+                    stmt.value = StmtKind::Empty;
+                    return Jumps::default();
+                }
+                stmt.value = flag(&mut self.cont, &mut self.names, "continue_flag").value;
+                Jumps { cont: true, ..Jumps::default() }
+            }
+            StmtKind::SeqBlock(list) => {
+                let (new_list, jumps) = self.lower_list(mem::take(list), tail);
+                *list = new_list;
+                jumps
+            }
+            StmtKind::TimingCtrl(_, body) => self.lower_stmt(body, tail),
+            StmtKind::If(_, _, t, f) => {
+                let jumps = self.lower_stmt(t, tail);
+                match f {
+                    Some(f) => jumps.or(self.lower_stmt(f, tail)),
+                    None => jumps,
+                }
+            }
+            StmtKind::Case { items, .. } => {
+                items.iter_mut().fold(Jumps::default(), |jumps, (_, stmt)| jumps.or(self.lower_stmt(stmt, tail)))
+            }
+            StmtKind::While(cond, body) => {
+                let jumps = self.lower_loop_body(body);
+                self.guard_loop(jumps, cond);
+                self.after_loop(stmt, jumps)
+            }
+            StmtKind::DoWhile(body, cond) => {
+                let jumps = self.lower_loop_body(body);
+                self.guard_loop(jumps, cond);
+                self.after_loop(stmt, jumps)
+            }
+            StmtKind::Foreach(_, _, body) => {
+                // Foreach loops cannot be lowered anyway, so only report jumps within the body.
+                let jumps = self.lower_loop_body(body);
+                self.after_loop(stmt, jumps)
+            }
+            StmtKind::Repeat(_, body) => {
+                let jumps = self.lower_loop_body(body);
+                if jumps.any() {
+                    // The count cannot be changed, so remaining iterations do nothing.
+                    let cond = none_set(&self.flags(jumps), None);
+                    let inner = mem::replace(body, Box::new(new_stmt(StmtKind::Empty)));
+                    **body = new_stmt(StmtKind::If(None, cond, inner, None));
+                }
+                self.after_loop(stmt, jumps)
+            }
+            StmtKind::Forever(body) => {
+                let jumps = self.lower_loop_body(body);
+                if jumps.any() {
+                    let cond = none_set(&self.flags(jumps), None);
+                    let body = mem::replace(body, Box::new(new_stmt(StmtKind::Empty)));
+                    stmt.value = StmtKind::While(cond, body);
+                }
+                self.after_loop(stmt, jumps)
+            }
+            StmtKind::For { .. } => {
+                let (ty, init, cond, update, mut body) = match mem::replace(&mut stmt.value, StmtKind::Empty) {
+                    StmtKind::For { ty, init, cond, update, body } => (ty, init, cond, update, body),
+                    _ => unreachable!(),
+                };
+                let jumps = self.lower_loop_body(&mut body);
+                if !jumps.any() {
+                    stmt.value = StmtKind::For { ty, init, cond, update, body };
+                    return jumps;
+                }
+
+                // Turn into a while loop, with the update only executed if the loop goes on.
+                let flags = self.flags(jumps);
+                let mut list = Vec::new();
+                if let Some(ty) = ty {
+                    let names = init.iter().filter_map(|expr| match &expr.value {
+                        ExprKind::Assign(lhs, _) => match &lhs.value {
+                            ExprKind::HierName(HierId::Name(None, name)) => Some(DeclAssign {
+                                name: (**name).clone(),
+                                dim: Vec::new(),
+                                init: None,
+                            }),
+                            _ => None,
+                        },
+                        _ => None,
+                    }).collect();
+                    list.push(new_stmt(StmtKind::DataDecl(Box::new(DataDecl {
+                        attr: None,
+                        has_const: false,
+                        lifetime: Lifetime::Automatic,
+                        ty: *ty,
+                        list: names,
+                    }))));
+                }
+                list.extend(init.into_iter().map(|expr| new_stmt(StmtKind::Expr(Box::new(expr)))));
+                let update = update.into_iter().map(|expr| new_stmt(StmtKind::Expr(Box::new(expr)))).collect();
+                let update = new_stmt(StmtKind::If(
+                    None,
+                    none_set(&flags, None),
+                    Box::new(new_stmt(StmtKind::SeqBlock(update))),
+                    None
+                ));
+                let body = Box::new(new_stmt(StmtKind::SeqBlock(vec![*body, update])));
+                list.push(new_stmt(StmtKind::While(none_set(&flags, cond), body)));
+                stmt.value = StmtKind::SeqBlock(list);
+                self.after_loop(stmt, jumps)
+            }
+            _ => Jumps::default(),
+        }
+    }
+
+    /// Add flags that leave a loop to its condition.
+    fn guard_loop(&self, jumps: Jumps, cond: &mut Box<Expr>) {
+        if jumps.any() {
+            let old = mem::replace(cond, Box::new(reconstruct_usize(1)));
+            *cond = none_set(&self.flags(jumps), Some(old));
+        }
+    }
+
+    /// Clear the `break` flag after a loop that may be left by it. Returns jumps that propagate
+    /// out of the loop.
+    fn after_loop(&self, stmt: &mut Stmt, jumps: Jumps) -> Jumps {
+        if jumps.brk {
+            let lp = mem::replace(stmt, new_stmt(StmtKind::Empty));
+            *stmt = new_stmt(StmtKind::SeqBlock(vec![lp, set_flag(self.brk.as_ref().unwrap(), 0)]));
+        }
+        Jumps {
+            ret: jumps.ret,
+            ..Jumps::default()
+        }
+    }
+}
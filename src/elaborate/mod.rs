@@ -4,9 +4,12 @@ pub mod ty;
 pub mod expr;
 pub mod hier;
 mod reconstruct;
+mod verilog;
+mod jump_elim;
 mod elaborate;
+mod systf;
 pub mod eht_visit;
 
 pub use self::resolve::resolve;
 pub use self::elaborate::elaborate;
-pub use self::reconstruct::reconstruct;
+pub use self::reconstruct::{reconstruct, reconstruct_verilog};
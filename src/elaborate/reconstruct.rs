@@ -1,20 +1,45 @@
 //! Reconstruct AST from elaborated constructs.
 
-use std::collections::HashMap;
+use std::cmp;
+use std::mem;
+use std::cell::RefCell;
+use std::collections::{HashMap, BTreeMap, BTreeSet};
+
+use num::ToPrimitive;
 
 use syntax::ast::{self, *};
 use syntax::tokens::*;
-use source::Span;
+use source::{Span, DiagMgr};
 use number::{LogicVec, LogicNumber, LogicValue};
 use super::ty::{Ty, IntTy, Struct, Enum};
 use super::expr::{self, Val};
 use super::hier::{self, HierItem};
+use super::verilog;
 
-pub fn reconstruct(source: &hier::Source) -> Vec<Vec<Item>> {
+/// Reconstruct AST from the elaborated design. If `inline_types` is set, types are declared in
+/// each design unit using them instead of in a global types package.
+pub fn reconstruct(source: &hier::Source, inline_types: bool) -> Vec<Vec<Item>> {
     let mut reconstructor = Reconstructor {
         source,
         global_qualify: true,
+        inline_types,
+        used_types: RefCell::new(BTreeSet::new()),
+        simple_ty_map: HashMap::new(),
+        verilog: None,
+    };
+    reconstructor.reconstruct()
+}
+
+/// Reconstruct AST that only uses Verilog-2005 constructs. Packages and typedefs are not
+/// reconstructed, and all constructs that cannot be lowered are reported as errors.
+pub fn reconstruct_verilog(diag: &DiagMgr, source: &hier::Source) -> Vec<Vec<Item>> {
+    let mut reconstructor = Reconstructor {
+        source,
+        global_qualify: false,
+        inline_types: false,
+        used_types: RefCell::new(BTreeSet::new()),
         simple_ty_map: HashMap::new(),
+        verilog: Some(diag),
     };
     reconstructor.reconstruct()
 }
@@ -40,15 +65,145 @@ pub fn reconstruct_i32(val: i32) -> Expr {
     }
 }
 
+/// Whether a packed type has multiple dimensions. Verilog-2005 only has a single packed dimension,
+/// so these types are flattened.
+fn is_multi_dim(ty: &IntTy) -> bool {
+    match ty {
+        IntTy::Array(element, ..) => !matches!(**element, IntTy::Logic(..)),
+        _ => false,
+    }
+}
+
+/// Get the width of an integral type.
+fn int_width(ty: &Ty) -> usize {
+    match ty {
+        Ty::Int(subty) => subty.width(),
+        _ => unreachable!(),
+    }
+}
+
+/// Get the value of a constant index, if it is one.
+fn const_index(expr: &expr::Expr) -> Option<i32> {
+    match expr.value {
+        expr::ExprKind::Const(Val::Int(ref val)) => val.get_two_state().and_then(|v| v.to_i32()),
+        _ => None,
+    }
+}
+
+/// If the expression is a constant part-select of a simple vector, possibly with its sign changed,
+/// get the vector and the LSB of the part. These are created when flattening packed structs.
+fn flat_part_select(expr: &expr::Expr) -> Option<(&expr::Expr, i32)> {
+    match expr.value {
+        expr::ExprKind::Select(ref parent, Spanned { value: expr::DimKind::Range(ub, lb), .. }) => {
+            match parent.ty {
+                Ty::Int(IntTy::SimpleVec(..)) => Some((parent, cmp::min(ub, lb))),
+                _ => None,
+            }
+        }
+        expr::ExprKind::SignCast(_, ref inner) => flat_part_select(inner),
+        _ => None,
+    }
+}
+
+/// Build `lhs op rhs`, parenthesising operands that are not primaries.
+fn binary(lhs: Expr, op: BinaryOp, rhs: Expr) -> Expr {
+    let paren = |expr: Expr| match expr.value {
+        ExprKind::Literal(_) |
+        ExprKind::HierName(_) |
+        ExprKind::Paren(_) => expr,
+        _ => Spanned::new(ExprKind::Paren(Box::new(expr)), Span::none()),
+    };
+    Spanned::new(ExprKind::Binary(Box::new(paren(lhs)), op, None, Box::new(paren(rhs))), Span::none())
+}
+
+/// Position of a select from a multi-dimensional packed array: the expression being selected, the
+/// offset of the selected part relative to LSB as a constant and a list of (negated, expression)
+/// terms, and the width of the part.
+type PackedSelect<'a> = (&'a expr::Expr, i32, Vec<(bool, Expr)>, usize);
+
+/// A type declared in the global types package. Types are ordered so that each type only depends
+/// on types before it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum GlobalTy {
+    Enum(usize),
+    Struct(usize),
+    Simple(usize),
+}
+
 struct Reconstructor<'a> {
     source: &'a hier::Source,
     /// Whether reference to structs and enums need to be qualified by "global_types::"
     global_qualify: bool,
-    /// Map from a type to an global_types:: identifier
-    simple_ty_map: HashMap<Ty, Ident>,
+    /// Whether types are declared in each design unit using them instead, as there are no
+    /// packages when they are inlined.
+    inline_types: bool,
+    /// Types used by the design unit being reconstructed, if they are inlined.
+    used_types: RefCell<BTreeSet<GlobalTy>>,
+    /// Map from a type to the index of its global_types:: identifier
+    simple_ty_map: HashMap<Ty, usize>,
+    /// When set, only Verilog-2005 constructs are generated and constructs that cannot be lowered
+    /// are reported here.
+    verilog: Option<&'a DiagMgr>,
 }
 
 impl<'a> Reconstructor<'a> {
+    /// Report a construct that cannot be lowered to Verilog-2005.
+    fn unsupported(&self, what: &str, span: Span) {
+        self.verilog.unwrap().report_error(format!("{} cannot be lowered to Verilog-2005", what), span);
+    }
+
+    /// Get the scope to qualify a reference to a type of the global types package with. When
+    /// types are inlined, the reference is recorded instead.
+    fn global_scope(&self, ty: GlobalTy) -> Option<Scope> {
+        if self.inline_types {
+            self.used_types.borrow_mut().insert(ty);
+            None
+        } else if self.global_qualify {
+            Some(Scope::Name(None, Box::new(Ident::new_unspanned("global_types".to_owned()))))
+        } else {
+            None
+        }
+    }
+
+    /// Reconstruct the name and definition of a type of the global types package.
+    fn global_typedef(&mut self, ty: GlobalTy) -> (Ident, DataType) {
+        match ty {
+            GlobalTy::Enum(index) => {
+                let prefix = format!("enum_{}", index);
+                let enu = self.reconstruct_enum(&self.source.enums[index], &prefix);
+                (Ident::new_unspanned(prefix), Spanned::new_unspanned(DataTypeKind::Enum(enu, Vec::new())))
+            }
+            GlobalTy::Struct(index) => {
+                let struc = self.reconstruct_struct(&self.source.structs[index]);
+                let name = Ident::new_unspanned(format!("struct_{}", index));
+                (name, Spanned::new_unspanned(DataTypeKind::Aggr(struc, Vec::new())))
+            }
+            GlobalTy::Simple(index) => {
+                let ty = self.simple_ty_map.iter().find(|(_, v)| **v == index).unwrap().0.clone();
+                // Simple types never have unpacked dimensions.
+                let (ty, _) = self.reconstruct_ty(&ty, Span::none());
+                (Ident::new_unspanned(format!("type_{}", index)), ty)
+            }
+        }
+    }
+
+    /// Reconstruct all types used since `used_types` was last cleared, including types used by
+    /// them, in dependency order.
+    fn inline_typedefs(&mut self) -> Vec<(Ident, DataType)> {
+        let mut done = BTreeMap::new();
+        loop {
+            // Reconstructing a type may record more types, which come before it.
+            let next = self.used_types.borrow().iter().find(|ty| !done.contains_key(*ty)).cloned();
+            let ty = match next {
+                Some(ty) => ty,
+                None => break,
+            };
+            let typedef = self.global_typedef(ty);
+            done.insert(ty, typedef);
+        }
+        done.into_values().collect()
+    }
+
     // Given a LogicVec, reconstruct the corresponding corresponding constant value.
     pub fn reconstruct_const(&self, val: &LogicVec, span: Span) -> Expr {
         Spanned::new(ExprKind::Literal(Spanned::new(TokenKind::IntegerLiteral(
@@ -104,7 +259,29 @@ impl<'a> Reconstructor<'a> {
         }
     }
 
+    /// Reconstruct an integral type for Verilog-2005, which only has signing and a single packed
+    /// dimension. Multi-dimensional packed arrays are flattened into a simple vector.
+    fn reconstruct_ty_int_verilog(&self, ty: &IntTy, span: Span) -> DataType {
+        let range = match ty {
+            IntTy::Logic(..) => None,
+            IntTy::Array(_, ub, lb) if !is_multi_dim(ty) => Some((*ub, *lb)),
+            IntTy::Struct(_) => {
+                self.unsupported("packed struct type", span);
+                Some((ty.width() as i32 - 1, 0))
+            }
+            _ => Some((ty.width() as i32 - 1, 0)),
+        };
+        let dim = range.into_iter().map(|(ub, lb)| {
+            Spanned::new(DimKind::Range(Box::new(reconstruct_i32(ub)), Box::new(reconstruct_i32(lb))), span)
+        }).collect();
+        let sign = if ty.sign() { Signing::Signed } else { Signing::Unsigned };
+        Spanned::new(DataTypeKind::Implicit(sign, dim), span)
+    }
+
     pub fn reconstruct_ty_int(&self, ty: &IntTy, span: Span) -> DataType {
+        if self.verilog.is_some() {
+            return self.reconstruct_ty_int_verilog(ty, span);
+        }
         let mut inner = ty;
         let mut dim = Vec::new();
         while let IntTy::Array(element, ub, lb) = inner {
@@ -141,24 +318,18 @@ impl<'a> Reconstructor<'a> {
                 )
             }
             IntTy::Struct(struc) => {
+                let index = self.source.structs.iter().position(|x| x == struc).unwrap();
                 DataTypeKind::HierName(
-                    if self.global_qualify {
-                        Some(Scope::Name(None, Box::new(Ident::new_unspanned("global_types".to_owned()))))
-                    } else {
-                        None
-                    },
-                    Ident::new_unspanned(format!("struct_{}", self.source.structs.iter().position(|x| x == struc).unwrap())),
+                    self.global_scope(GlobalTy::Struct(index)),
+                    Ident::new_unspanned(format!("struct_{}", index)),
                     dim
                 )
             }
             IntTy::Enum(enu) => {
+                let index = self.source.enums.iter().position(|x| x == enu).unwrap();
                 DataTypeKind::HierName(
-                    if self.global_qualify {
-                        Some(Scope::Name(None, Box::new(Ident::new_unspanned("global_types".to_owned()))))
-                    } else {
-                        None
-                    },
-                    Ident::new_unspanned(format!("enum_{}", self.source.enums.iter().position(|x| x == enu).unwrap())),
+                    self.global_scope(GlobalTy::Enum(index)),
+                    Ident::new_unspanned(format!("enum_{}", index)),
                     dim
                 )
             }
@@ -170,6 +341,11 @@ impl<'a> Reconstructor<'a> {
         let kind = match ty {
             Ty::Type => DataTypeKind::Type,
             Ty::Int(subty) => return (self.reconstruct_ty_int(subty, span), Vec::new()),
+            Ty::String |
+            Ty::FixStr(_) if self.verilog.is_some() => {
+                self.unsupported("string type", span);
+                DataTypeKind::Implicit(Signing::Unsigned, Vec::new())
+            }
             Ty::String => DataTypeKind::String,
             Ty::FixStr(_) => DataTypeKind::String, // TODO: May need to fix this
             Ty::Array(base, ub, lb) => {
@@ -189,20 +365,21 @@ impl<'a> Reconstructor<'a> {
     pub fn reconstruct_ty_simple(&mut self, ty: &Ty) -> DataType {
         let (mut ast_ty, dim) = self.reconstruct_ty(ty, Span::none());
         assert!(dim.len() == 0);
+        // There are no typedefs in Verilog-2005, so the type is used directly.
+        if self.verilog.is_some() {
+            return ast_ty;
+        }
         match ast_ty.value {
             // These are already simple types
             DataTypeKind::IntAtom(_, None) |
             DataTypeKind::Real(_) |
             DataTypeKind::HierName(..) => (),
             _ => {
-                if !self.simple_ty_map.contains_key(&ty) {
-                    let ident = Ident::new_unspanned(format!("type_{}", self.simple_ty_map.len()));
-                    self.simple_ty_map.insert(ty.clone(), ident);
-                }
-                let ident = self.simple_ty_map[&ty].clone();
+                let len = self.simple_ty_map.len();
+                let index = *self.simple_ty_map.entry(ty.clone()).or_insert(len);
                 ast_ty.value = DataTypeKind::HierName(
-                    Some(Scope::Name(None, Box::new(Ident::new_unspanned("global_types".to_owned())))),
-                    ident,
+                    self.global_scope(GlobalTy::Simple(index)),
+                    Ident::new_unspanned(format!("type_{}", index)),
                     Vec::new(),
                 );
             }
@@ -212,6 +389,11 @@ impl<'a> Reconstructor<'a> {
 
     pub fn reconstruct_val_int(&mut self, ty: &IntTy, val: &LogicVec, span: Span) -> Expr {
         match ty {
+            // The constant already has correct width and signedness, and there is no way to
+            // express other types in Verilog-2005.
+            _ if self.verilog.is_some() => {
+                self.reconstruct_const(val, span)
+            }
             IntTy::SimpleVec(_, false, _) => {
                 self.reconstruct_const(val, span)
             }
@@ -272,7 +454,241 @@ impl<'a> Reconstructor<'a> {
         Spanned::new(kind, dim.span)
     }
 
+    /// Reconstruct an operand in a context-determined position. Width casts inserted during
+    /// elaboration to extend these operands are implicit in Verilog, so they are dropped.
+    pub fn reconstruct_operand(&mut self, expr: &expr::Expr) -> ast::Expr {
+        if self.verilog.is_some() {
+            if let expr::ExprKind::WidthCast(width, ref rhs) = expr.value {
+                if int_width(&rhs.ty) <= width {
+                    return self.reconstruct_expr(rhs);
+                }
+            }
+        }
+        self.reconstruct_expr(expr)
+    }
+
+    /// Reconstruct a cast to given width and optionally signedness using Verilog-2005 constructs.
+    fn reconstruct_cast_verilog(&mut self, width: usize, sign: Option<bool>, rhs: &expr::Expr, span: Span) -> ast::ExprKind {
+        let rhs_width = int_width(&rhs.ty);
+        let rhs_sign = match rhs.ty {
+            Ty::Int(ref subty) => subty.sign(),
+            _ => unreachable!(),
+        };
+        // Constants can be converted directly
+        if let expr::ExprKind::Const(Val::Int(ref val)) = rhs.value {
+            let mut val = val.extend_or_trunc(width);
+            val.signed = sign.unwrap_or(rhs_sign);
+            return self.reconstruct_const(&val, span).value;
+        }
+        // The argument of a sign conversion is self-determined, so widening within the operand
+        // needs to be kept.
+        let sign_change = sign.is_some_and(|sign| sign != rhs_sign);
+        let mut ast_expr = if sign_change { self.reconstruct_expr(rhs) } else { self.reconstruct_operand(rhs) };
+        if width < rhs_width {
+            self.unsupported("narrowing cast", span);
+        } else if width > rhs_width {
+            // Extend the operand by adding a zero of the target width. The zero has the same
+            // signedness as the operand so it is extended in the same way as SystemVerilog does.
+            let zero = self.reconstruct_const(&LogicVec::from(width, rhs_sign, 0.into()), Span::none());
+            let sum = binary(ast_expr, BinaryOp::Add, zero);
+            ast_expr = Spanned::new(ExprKind::Paren(Box::new(sum)), span);
+        }
+        match sign {
+            Some(sign) if sign_change => {
+                // Parentheses are redundant within the call.
+                if let ExprKind::Paren(inner) = ast_expr.value {
+                    ast_expr = *inner;
+                }
+                ast::ExprKind::SysTfCall(Box::new(SysTfCall {
+                    task: Spanned::new(if sign { "signed" } else { "unsigned" }.to_owned(), span),
+                    args: Some(Args {
+                        ordered: vec![Some(Box::new(ast_expr))],
+                        named: Vec::new(),
+                    }),
+                }))
+            }
+            _ => ast_expr.value,
+        }
+    }
+
+    /// Compute the position of a select from a multi-dimensional packed array within the flattened
+    /// vector.
+    fn packed_select_offset<'b>(&mut self, expr: &'b expr::Expr) -> Option<PackedSelect<'b>> {
+        let (parent, dim) = match expr.value {
+            expr::ExprKind::Select(ref parent, ref dim) => (parent, dim),
+            _ => return None,
+        };
+        let parent_ty = match parent.ty {
+            Ty::Int(ref subty) => subty,
+            _ => return None,
+        };
+        let (base, mut offset, mut terms) = match self.packed_select_offset(parent) {
+            Some((base, offset, terms, _)) => (base, offset, terms),
+            None => match flat_part_select(parent) {
+                // Selects from a part of a flattened struct are relative to the struct.
+                Some((base, lsb)) => (base, lsb, Vec::new()),
+                None if is_multi_dim(parent_ty) => (&**parent, 0, Vec::new()),
+                None => return None,
+            },
+        };
+
+        let (element_width, ub, lb) = match parent_ty {
+            IntTy::Array(element, ub, lb) => (element.width() as i32, *ub, *lb),
+            subty => (1, subty.width() as i32 - 1, 0),
+        };
+        let descending = ub >= lb;
+
+        // Find out the lowest element selected, as an optional expression plus a constant, and
+        // the number of elements selected.
+        let (index, adjust, count) = match dim.value {
+            expr::DimKind::Value(ref index) => (Some(&**index), 0, 1),
+            expr::DimKind::Range(a, b) => {
+                let low = if descending { cmp::min(a, b) } else { cmp::max(a, b) };
+                (None, low, (a - b).unsigned_abs() as usize + 1)
+            }
+            expr::DimKind::PlusRange(ref index, width) => {
+                (Some(&**index), if descending { 0 } else { width as i32 - 1 }, width)
+            }
+            expr::DimKind::MinusRange(ref index, width) => {
+                (Some(&**index), if descending { 1 - width as i32 } else { 0 }, width)
+            }
+        };
+        let (index, adjust) = match index.map(|index| (const_index(index), index)) {
+            Some((Some(value), _)) => (None, adjust + value),
+            Some((None, index)) => (Some(index), adjust),
+            None => (None, adjust),
+        };
+
+        // The offset is (index - lb) * width for descending ranges, (lb - index) * width otherwise.
+        offset += (if descending { adjust - lb } else { lb - adjust }) * element_width;
+        if let Some(index) = index {
+            let mut ast_index = self.reconstruct_expr(index);
+            if element_width != 1 {
+                ast_index = binary(ast_index, BinaryOp::Mul, reconstruct_usize(element_width as usize));
+            }
+            terms.push((!descending, ast_index));
+        }
+        Some((base, offset, terms, count * element_width as usize))
+    }
+
+    /// Reconstruct a select from a multi-dimensional packed array. As these arrays are flattened
+    /// in Verilog-2005, nested selects are folded into a single bit-select or part-select.
+    fn reconstruct_packed_select(&mut self, expr: &expr::Expr) -> Option<ast::ExprKind> {
+        let (base, offset, terms, width) = self.packed_select_offset(expr)?;
+        let dim = if terms.is_empty() {
+            if width == 1 {
+                DimKind::Value(Box::new(reconstruct_i32(offset)))
+            } else {
+                DimKind::Range(Box::new(reconstruct_i32(offset + width as i32 - 1)), Box::new(reconstruct_i32(offset)))
+            }
+        } else {
+            let mut ast_offset = if offset != 0 { Some(reconstruct_i32(offset)) } else { None };
+            for (negate, term) in terms {
+                ast_offset = Some(match (ast_offset, negate) {
+                    (None, false) => term,
+                    (None, true) => Spanned::new(ExprKind::Unary(UnaryOp::Sub, None, Box::new(term)), Span::none()),
+                    (Some(lhs), false) => binary(lhs, BinaryOp::Add, term),
+                    (Some(lhs), true) => binary(lhs, BinaryOp::Sub, term),
+                });
+            }
+            let ast_offset = Box::new(ast_offset.unwrap());
+            if width == 1 {
+                DimKind::Value(ast_offset)
+            } else {
+                DimKind::PlusRange(ast_offset, Box::new(reconstruct_usize(width)))
+            }
+        };
+        let ast_base = self.reconstruct_expr(base);
+        match ast_base.value {
+            ast::ExprKind::HierName(name) => {
+                let spanned = Spanned::new(name, ast_base.span);
+                let dim = Spanned::new(dim, expr.span);
+                Some(ast::ExprKind::HierName(HierId::Select(Box::new(spanned), Box::new(dim))))
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Reconstruct expressions that need special treatment in Verilog-2005. Returns None if the
+    /// expression can be reconstructed as usual.
+    fn reconstruct_expr_verilog(&mut self, expr: &expr::Expr) -> Option<ast::ExprKind> {
+        let kind = match expr.value {
+            expr::ExprKind::AssignPattern(_, ref pattern) => {
+                match pattern {
+                    expr::AssignPattern::Simple(list) => {
+                        let ast_list = list.iter().map(|item| self.reconstruct_expr(item)).collect();
+                        ast::ExprKind::Concat(ast_list, None)
+                    }
+                    _ => unimplemented!(),
+                }
+            }
+            expr::ExprKind::Select(..) => return self.reconstruct_packed_select(expr),
+            expr::ExprKind::Member(..) => {
+                self.unsupported("member access", expr.span);
+                return None;
+            }
+            expr::ExprKind::TypeCast(ref ty, ref rhs) => {
+                match **ty {
+                    Ty::Int(ref subty) => self.reconstruct_cast_verilog(subty.width(), Some(subty.sign()), rhs, expr.span),
+                    _ => {
+                        self.unsupported("cast to non-integral type", expr.span);
+                        return None;
+                    }
+                }
+            }
+            expr::ExprKind::SignCast(sign, ref rhs) => {
+                self.reconstruct_cast_verilog(int_width(&rhs.ty), Some(sign), rhs, expr.span)
+            }
+            expr::ExprKind::WidthCast(width, ref rhs) => {
+                self.reconstruct_cast_verilog(width, None, rhs, expr.span)
+            }
+            expr::ExprKind::Unary(op @ UnaryOp::Add, ref rhs) |
+            expr::ExprKind::Unary(op @ UnaryOp::Sub, ref rhs) |
+            expr::ExprKind::Unary(op @ UnaryOp::Not, ref rhs) => {
+                ast::ExprKind::Unary(op, None, Box::new(self.reconstruct_operand(rhs)))
+            }
+            expr::ExprKind::Binary(ref lhs, op, ref rhs) => {
+                let ast_lhs = self.reconstruct_operand(lhs);
+                let ast_rhs = match op {
+                    // Right hand side of these operators are self-determined
+                    BinaryOp::Power |
+                    BinaryOp::Shl |
+                    BinaryOp::LShr |
+                    BinaryOp::AShr => self.reconstruct_expr(rhs),
+                    _ => self.reconstruct_operand(rhs),
+                };
+                ast::ExprKind::Binary(Box::new(ast_lhs), op, None, Box::new(ast_rhs))
+            }
+            expr::ExprKind::Assign(ref lhs, ref rhs) => {
+                let ast_lhs = self.reconstruct_expr(lhs);
+                let ast_rhs = self.reconstruct_operand(rhs);
+                ast::ExprKind::Assign(Box::new(ast_lhs), Box::new(ast_rhs))
+            }
+            expr::ExprKind::NonblockAssign(ref lhs, ref rhs) => {
+                let ast_lhs = self.reconstruct_expr(lhs);
+                let ast_rhs = self.reconstruct_operand(rhs);
+                ast::ExprKind::NonblockAssign(Box::new(ast_lhs), Box::new(ast_rhs))
+            }
+            expr::ExprKind::Paren(ref inner) => {
+                ast::ExprKind::Paren(Box::new(self.reconstruct_operand(inner)))
+            }
+            expr::ExprKind::Cond(ref cond, ref t, ref f) => {
+                let ast_cond = self.reconstruct_expr(cond);
+                let ast_t = self.reconstruct_operand(t);
+                let ast_f = self.reconstruct_operand(f);
+                ast::ExprKind::Cond(Box::new(ast_cond), None, Box::new(ast_t), Box::new(ast_f))
+            }
+            _ => return None,
+        };
+        Some(kind)
+    }
+
     pub fn reconstruct_expr(&mut self, expr: &expr::Expr) -> ast::Expr {
+        if self.verilog.is_some() {
+            if let Some(kind) = self.reconstruct_expr_verilog(expr) {
+                return Spanned::new(kind, expr.span);
+            }
+        }
         let kind = match expr.value {
             expr::ExprKind::Const(ref val) => {
                 self.reconstruct_val(&expr.ty, val, expr.span).value
@@ -377,7 +793,9 @@ impl<'a> Reconstructor<'a> {
                 let ast_rhs = self.reconstruct_expr(rhs);
                 ast::ExprKind::Binary(Box::new(ast_lhs), op, None, Box::new(ast_rhs))
             }
-            expr::ExprKind::PrefixIncDec(..) => unimplemented!(),
+            expr::ExprKind::PrefixIncDec(op, ref lhs) => {
+                ast::ExprKind::PrefixIncDec(op, None, Box::new(self.reconstruct_expr(lhs)))
+            }
             expr::ExprKind::PostfixIncDec(ref lhs, op) => {
                 ast::ExprKind::PostfixIncDec(Box::new(self.reconstruct_expr(lhs)), None, op)
             }
@@ -391,7 +809,11 @@ impl<'a> Reconstructor<'a> {
                 let ast_rhs = self.reconstruct_expr(rhs);
                 ast::ExprKind::NonblockAssign(Box::new(ast_lhs), Box::new(ast_rhs))
             }
-            expr::ExprKind::BinaryAssign(..) => unimplemented!(),
+            expr::ExprKind::BinaryAssign(ref lhs, op, ref rhs) => {
+                let ast_lhs = self.reconstruct_expr(lhs);
+                let ast_rhs = self.reconstruct_expr(rhs);
+                ast::ExprKind::BinaryAssign(Box::new(ast_lhs), op, Box::new(ast_rhs))
+            }
             expr::ExprKind::Paren(ref expr) => {
                 let ast_expr = self.reconstruct_expr(expr);
                 ast::ExprKind::Paren(Box::new(ast_expr))
@@ -421,9 +843,9 @@ impl<'a> Reconstructor<'a> {
                 ast::StmtKind::If(*uniq, Box::new(cond), Box::new(t), f.map(Box::new))
             },
             expr::StmtKind::Case { uniq, kw, expr, items } => {
-                let expr = self.reconstruct_expr(expr);
+                let expr = self.reconstruct_operand(expr);
                 let items = items.iter().map(|(conds, stmt)| {
-                    let conds = conds.iter().map(|cond| self.reconstruct_expr(cond)).collect();
+                    let conds = conds.iter().map(|cond| self.reconstruct_operand(cond)).collect();
                     let stmt = self.reconstruct_stmt(stmt);
                     (conds, stmt)
                 }).collect();
@@ -445,6 +867,35 @@ impl<'a> Reconstructor<'a> {
                     init, cond, update, body
                 }
             },
+            expr::StmtKind::While(cond, body) => {
+                let cond = Box::new(self.reconstruct_expr(cond));
+                let body = Box::new(self.reconstruct_stmt(body));
+                ast::StmtKind::While(cond, body)
+            }
+            expr::StmtKind::DoWhile(body, cond) => {
+                let body = Box::new(self.reconstruct_stmt(body));
+                let cond = Box::new(self.reconstruct_expr(cond));
+                ast::StmtKind::DoWhile(body, cond)
+            }
+            expr::StmtKind::Repeat(count, body) => {
+                let count = Box::new(self.reconstruct_operand(count));
+                let body = Box::new(self.reconstruct_stmt(body));
+                ast::StmtKind::Repeat(count, body)
+            }
+            expr::StmtKind::Forever(body) => {
+                ast::StmtKind::Forever(Box::new(self.reconstruct_stmt(body)))
+            }
+            expr::StmtKind::Foreach { array, vars, body } => {
+                let array = Box::new(self.reconstruct_expr(array));
+                let vars = vars.iter().map(|var| var.as_ref().map(|(decl, _, _)| decl.name.clone())).collect();
+                let body = Box::new(self.reconstruct_stmt(body));
+                ast::StmtKind::Foreach(array, vars, body)
+            }
+            expr::StmtKind::Return(span, expr) => {
+                ast::StmtKind::Return(*span, expr.as_ref().map(|expr| Box::new(self.reconstruct_expr(expr))))
+            }
+            expr::StmtKind::Break(span) => ast::StmtKind::Break(*span),
+            expr::StmtKind::Continue(span) => ast::StmtKind::Continue(*span),
             expr::StmtKind::Assert { kind, expr, success, failure } => {
                 let expr = Box::new(self.reconstruct_expr(expr));
                 let success = success.as_ref().map(|stmt| Box::new(self.reconstruct_stmt(stmt)));
@@ -465,8 +916,8 @@ impl<'a> Reconstructor<'a> {
                 ast::StmtKind::Expr(Box::new(expr))
             }
             expr::StmtKind::DataDecl(decl) => {
-                let (ty, dim) = self.reconstruct_ty(&decl.ty, Span::none());
-                let init = decl.init.as_ref().map(|expr| Box::new(self.reconstruct_expr(expr)));
+                let (ty, dim) = self.reconstruct_ty(&decl.ty, decl.name.span);
+                let init = decl.init.as_ref().map(|expr| Box::new(self.reconstruct_operand(expr)));
                 ast::StmtKind::DataDecl(Box::new(DataDecl {
                     attr: None,
                     has_const: false,
@@ -487,8 +938,22 @@ impl<'a> Reconstructor<'a> {
         }
     }
 
+    fn reconstruct_tf_ports(&mut self, ports: &[hier::TfPortDecl]) -> Vec<PortDecl> {
+        ports.iter().map(|port| {
+            let (ty, dim) = self.reconstruct_ty(&port.ty, port.name.span);
+            let init = port.init.as_ref().map(|expr| Box::new(self.reconstruct_operand(expr)));
+            PortDecl::Data(port.dir, NetPortType::Default, Box::new(ty), vec![DeclAssign {
+                name: port.name.clone(),
+                dim,
+                init,
+            }])
+        }).collect()
+    }
+
     pub fn reconstruct_item(&mut self, item: &HierItem, list: &mut Vec<Item>) {
         match item {
+            // Type parameters have all been substituted, and Verilog-2005 does not have them.
+            HierItem::Param(decl) if self.verilog.is_some() && decl.ty == Ty::Type => (),
             HierItem::Param(decl) => {
                 let (ty, dim) = if let Ty::FixStr(_) = decl.ty {
                     // parameter string is not well supported in many tools, so we themselves
@@ -514,8 +979,8 @@ impl<'a> Reconstructor<'a> {
                 list.push(Item::Typedef(None, Box::new(ty), Box::new(decl.name.clone()), dim));
             }
             HierItem::DataDecl(decl) => {
-                let (ty, dim) = self.reconstruct_ty(&decl.ty, Span::none());
-                let init = decl.init.as_ref().map(|expr| Box::new(self.reconstruct_expr(expr)));
+                let (ty, dim) = self.reconstruct_ty(&decl.ty, decl.name.span);
+                let init = decl.init.as_ref().map(|expr| Box::new(self.reconstruct_operand(expr)));
                 list.push(Item::DataDecl(Box::new(DataDecl {
                     attr: None,
                     has_const: false,
@@ -529,8 +994,8 @@ impl<'a> Reconstructor<'a> {
                 })));
             }
             HierItem::NetDecl(decl) => {
-                let (ty, dim) = self.reconstruct_ty(&decl.ty, Span::none());
-                let init = decl.init.as_ref().map(|expr| Box::new(self.reconstruct_expr(expr)));
+                let (ty, dim) = self.reconstruct_ty(&decl.ty, decl.name.span);
+                let init = decl.init.as_ref().map(|expr| Box::new(self.reconstruct_operand(expr)));
                 list.push(Item::NetDecl(Box::new(NetDecl {
                     attr: None,
                     net: decl.net,
@@ -542,6 +1007,9 @@ impl<'a> Reconstructor<'a> {
                     }]
                 })));
             }
+            HierItem::FuncDecl(decl) if self.verilog.is_some() && decl.ty == Ty::Void => {
+                self.unsupported("void function", decl.name.span);
+            }
             HierItem::FuncDecl(decl) => {
                 let (ty, dim) = self.reconstruct_ty(&decl.ty, Span::none());
                 assert!(dim.is_empty());
@@ -550,8 +1018,8 @@ impl<'a> Reconstructor<'a> {
                     lifetime: decl.lifetime,
                     ty,
                     name: decl.name.clone(),
-                    ports: decl.ports.clone(),
-                    stmts: decl.stmts.clone(),
+                    ports: self.reconstruct_tf_ports(&decl.ports),
+                    stmts: decl.body.iter().map(|stmt| self.reconstruct_stmt(stmt)).collect(),
                 })));
             }
             HierItem::TaskDecl(decl) => {
@@ -559,8 +1027,8 @@ impl<'a> Reconstructor<'a> {
                     attr: None,
                     lifetime: decl.lifetime,
                     name: decl.name.clone(),
-                    ports: decl.ports.clone(),
-                    stmts: decl.stmts.clone(),
+                    ports: self.reconstruct_tf_ports(&decl.ports),
+                    stmts: decl.body.iter().map(|stmt| self.reconstruct_stmt(stmt)).collect(),
                 })));
             }
             HierItem::ContinuousAssign(expr) => {
@@ -588,7 +1056,7 @@ impl<'a> Reconstructor<'a> {
                             Spanned::new(DimKind::Range(Box::new(ub_expr), Box::new(lb_expr)), Span::none())
                         }).collect(),
                         ports: PortConn::Ordered(decl.port.iter().map(|port| {
-                            (None, port.as_ref().map(|port| Box::new(self.reconstruct_expr(port))))
+                            (None, port.as_ref().map(|port| Box::new(self.reconstruct_operand(port))))
                         }).collect()),
                     }]
                 })))
@@ -631,7 +1099,7 @@ impl<'a> Reconstructor<'a> {
                 let element_name = enu.elements.borrow()[*index].0.clone();
                 let expr = Box::new(Spanned::new_unspanned(ExprKind::HierName(
                     HierId::Name(
-                        Some(Scope::Name(None, Box::new(Ident::new_unspanned("global_types".to_owned())))),
+                        self.global_scope(GlobalTy::Enum(enum_index)),
                         Box::new(Ident::new_unspanned(format!("enum_{}_{}", enum_index, element_name)))
                     )
                 )));
@@ -651,13 +1119,23 @@ impl<'a> Reconstructor<'a> {
     }
 
     pub fn reconstruct_instantiation(&mut self, decl: &hier::DesignDecl, inst: &hier::DesignInstantiation) -> Item {
+        if self.verilog.is_some() && decl.ast.kw != Keyword::Module {
+            self.unsupported(&format!("{}", decl.ast.kw), decl.ast.name.span);
+        }
+        let outer_types = mem::take(&mut *self.used_types.borrow_mut());
+
         // Reconstruct all parameters
         let mut params = Vec::new();
+        // Some parameters may not be reconstructed, so count them separately.
+        let mut param_count = 0;
         for item in &inst.scope.items {
-            if let HierItem::Param(_) = item {
-                self.reconstruct_item(item, &mut params);
-            } else {
-                break;
+            match item {
+                // Localparams are not overridable so they can be placed in the body instead.
+                HierItem::Param(decl) if decl.kw == Keyword::Parameter => {
+                    param_count += 1;
+                    self.reconstruct_item(item, &mut params);
+                }
+                _ => break,
             }
         }
         let params: Vec<_> = params.into_iter().map(|x| {
@@ -666,10 +1144,13 @@ impl<'a> Reconstructor<'a> {
 
         // Reconstruct all ports
         let mut ports = Vec::new();
-        for item in inst.scope.items.iter().skip(params.len()) {
+        for item in inst.scope.items.iter().skip(param_count) {
             match item {
                 HierItem::DataPort(decl) => {
-                    let (ty, dim) = self.reconstruct_ty(&decl.ty, Span::none());
+                    if self.verilog.is_some() && decl.init.is_some() {
+                        self.unsupported("default port value", decl.name.span);
+                    }
+                    let (ty, dim) = self.reconstruct_ty(&decl.ty, decl.name.span);
                     ports.push(PortDecl::Data(decl.dir, decl.net.clone(), Box::new(ty), vec![DeclAssign {
                         name: decl.name.clone(),
                         dim,
@@ -677,6 +1158,9 @@ impl<'a> Reconstructor<'a> {
                     }]));
                 }
                 HierItem::InterfacePort(decl) => {
+                    if self.verilog.is_some() {
+                        self.unsupported("interface port", decl.name.span);
+                    }
                     let intf = Some(Box::new(decl.inst.get_instance().name.clone()));
                     let modport = decl.modport.as_ref().map(|modport| Box::new(modport.name.clone()));
                     let dim = decl.dim.iter().map(|(ub, lb)| {
@@ -696,10 +1180,24 @@ impl<'a> Reconstructor<'a> {
 
         // Now chunk them into params, port and items
         let mut list = Vec::new();
-        for item in inst.scope.items.iter().skip(params.len() + ports.len()) {
+        for item in inst.scope.items.iter().skip(param_count + ports.len()) {
             self.reconstruct_item(&item, &mut list);
         }
 
+        // Inlined types are declared as localparams before all parameters, so they are also
+        // visible to ports.
+        let typedefs = self.inline_typedefs();
+        *self.used_types.borrow_mut() = outer_types;
+        let params: Vec<_> = typedefs.into_iter().map(|(name, ty)| ParamDecl {
+            kw: Keyword::Localparam,
+            ty: Some(Box::new(Spanned::new_unspanned(DataTypeKind::Type))),
+            list: vec![DeclAssign {
+                name,
+                dim: Vec::new(),
+                init: Some(Box::new(Spanned::new_unspanned(ExprKind::Type(Box::new(ty))))),
+            }],
+        }).chain(params).collect();
+
         Item::DesignDecl(Box::new(DesignDecl {
             attr: None,
             kw: decl.ast.kw,
@@ -709,10 +1207,27 @@ impl<'a> Reconstructor<'a> {
             param: if !params.is_empty() { Some(params) } else { None },
             port: ports,
             items: list,
+            default_nettype: decl.ast.default_nettype,
+            timescale: decl.ast.timescale,
         }))
     }
 
     pub fn reconstruct(&mut self) -> Vec<Vec<Item>> {
+        // There are no packages in Verilog-2005. Everything used by design units is either
+        // substituted during elaboration or reported by the lowering.
+        if let Some(diag) = self.verilog {
+            let mut units = vec![Vec::new()];
+            for unit in &self.source.units {
+                let mut list = Vec::new();
+                for item in &unit.items {
+                    self.reconstruct_item(item, &mut list);
+                }
+                verilog::lower(diag, &mut list);
+                units.push(list);
+            }
+            return units;
+        }
+
         // Packages
         let mut list: Vec<_> = self.source.pkgs.iter().map(|(_, decl)| {
             let mut list = Vec::new();
@@ -733,37 +1248,36 @@ impl<'a> Reconstructor<'a> {
             for item in &unit.items {
                 self.reconstruct_item(item, &mut list);
             }
+            // Items outside design units see types declared in the compilation unit.
+            let typedefs = self.inline_typedefs().into_iter().map(|(name, ty)| {
+                Item::Typedef(None, Box::new(ty), Box::new(name), Vec::new())
+            });
+            self.used_types.borrow_mut().clear();
+            list.splice(0..0, typedefs);
             units.push(list);
         }
 
         // Build global types package, insert before all packages
-        list.insert(0, {
-            self.global_qualify = false;
-            let mut types = Vec::new();
-            types.extend(self.source.enums.iter().enumerate().map(|(index, enu)| {
-                let prefix = format!("enum_{}", index);
-                let enu = self.reconstruct_enum(enu, &prefix);
-                let ty = Spanned::new(DataTypeKind::Enum(enu, Vec::new()), Span::none());
-                let name = Ident::new(prefix, Span::none());
-                Item::Typedef(None, Box::new(ty), Box::new(name), Vec::new())
-            }));
-            types.extend(self.source.structs.iter().enumerate().map(|(index, struc)| {
-                let struc = self.reconstruct_struct(struc);
-                let ty = Spanned::new(DataTypeKind::Aggr(struc, Vec::new()), Span::none());
-                let name = Ident::new(format!("struct_{}", index), Span::none());
-                Item::Typedef(None, Box::new(ty), Box::new(name), Vec::new())
-            }));
-            types.extend(self.simple_ty_map.iter().map(|(ty, name)| {
-                let (ty, dim) = self.reconstruct_ty(ty, Span::none());
-                Item::Typedef(None, Box::new(ty), Box::new(name.clone()), dim)
-            }));
-            Item::PkgDecl(Box::new(PkgDecl {
+        self.global_qualify = false;
+        let mut types = Vec::new();
+        if !self.inline_types {
+            let mut all: Vec<_> = (0..self.source.enums.len()).map(GlobalTy::Enum).collect();
+            all.extend((0..self.source.structs.len()).map(GlobalTy::Struct));
+            all.extend((0..self.simple_ty_map.len()).map(GlobalTy::Simple));
+            for ty in all {
+                let (name, ty) = self.global_typedef(ty);
+                types.push(Item::Typedef(None, Box::new(ty), Box::new(name), Vec::new()));
+            }
+        }
+        // Omit the package if nothing needs it.
+        if !types.is_empty() {
+            list.insert(0, Item::PkgDecl(Box::new(PkgDecl {
                 attr: None,
                 lifetime: Lifetime::Static,
                 name: Ident::new_unspanned("global_types".to_owned()),
                 items: types
-            }))
-        });
+            })));
+        }
         units.insert(0, list);
 
         units
@@ -88,6 +88,11 @@ struct Resolver<'a> {
     pkg_ref: HashMap<SymbolId, Rc<String>>,
 
     scopes: Vec<Scope>,
+
+    /// Type of implicit nets in the current design unit, or None if they are not allowed.
+    default_nettype: Option<NetTy>,
+    /// Declarations of implicit nets to be inserted before the item being visited.
+    implicit_nets: Vec<Item>,
 }
 
 impl<'a> Resolver<'a> {
@@ -98,6 +103,8 @@ impl<'a> Resolver<'a> {
             pkg: HashMap::new(),
             pkg_ref: HashMap::new(),
             scopes: Vec::new(),
+            default_nettype: Some(NetTy::Wire),
+            implicit_nets: Vec::new(),
         }
     }
 
@@ -201,6 +208,62 @@ impl<'a> Resolver<'a> {
         Some(ret.1)
     }
 
+    /// Visit a list of items. Implicit nets introduced by an item are declared just before it.
+    fn visit_items(&mut self, items: &mut Vec<Item>) {
+        let mut list = Vec::with_capacity(items.len());
+        for mut item in items.drain(..) {
+            self.visit_item(&mut item);
+            list.append(&mut self.implicit_nets);
+            list.push(item);
+        }
+        *items = list;
+    }
+
+    /// Check if an expression, which is the target of a continuous assignment or connected to a
+    /// port, refers to undeclared identifiers. These are declared as implicit nets.
+    fn declare_implicit_net(&mut self, expr: &Expr) {
+        match &expr.value {
+            ExprKind::HierName(HierId::Name(None, name)) => {
+                let declared = self.scopes.iter().any(|scope| {
+                    scope.map.contains_key(&name.value) || scope.weak.contains_key(&name.value)
+                });
+                if declared {
+                    return;
+                }
+                let mut name = Ident::clone(name);
+                let netty = match self.default_nettype {
+                    Some(v) => v,
+                    None => {
+                        self.diag.report_error(
+                            format!("name {} is not declared and implicit nets are not allowed by `default_nettype none", name),
+                            name.span
+                        );
+                        self.add_to_scope(&mut name, SymbolKind::Error);
+                        return;
+                    }
+                };
+                self.add_to_scope(&mut name, SymbolKind::Var);
+                let span = name.span;
+                self.implicit_nets.push(Item::NetDecl(Box::new(NetDecl {
+                    attr: None,
+                    net: netty,
+                    ty: Spanned::new(DataTypeKind::Implicit(Signing::Unsigned, Vec::new()), span),
+                    list: vec![DeclAssign {
+                        name,
+                        dim: Vec::new(),
+                        init: None,
+                    }],
+                })));
+            }
+            ExprKind::Concat(list, None) => {
+                for expr in list {
+                    self.declare_implicit_net(expr);
+                }
+            }
+            _ => (),
+        }
+    }
+
     /// Read a list of PortDecl and generate a list of ports
     fn port_list(ports: &Vec<PortDecl>) -> Vec<String> {
         let mut port_list = Vec::new();
@@ -364,6 +427,7 @@ impl<'a> AstVisitor for Resolver<'a> {
 
                 // Introduce new scope
                 self.scopes.push(Scope::new());
+                self.default_nettype = decl.default_nettype;
 
                 for import in &mut decl.pkg_import {
                     self.visit_import(import);
@@ -413,9 +477,7 @@ impl<'a> AstVisitor for Resolver<'a> {
                     self.visit_port_decl(port)
                 }
 
-                for item in &mut decl.items {
-                    self.visit_item(item)
-                }
+                self.visit_items(&mut decl.items);
 
                 // Leave the namespace
                 self.scopes.pop();
@@ -519,7 +581,13 @@ impl<'a> AstVisitor for Resolver<'a> {
                 self.add_to_scope(target, SymbolKind::Type);
                 return;
             }
-            Item::ContinuousAssign(_) |
+            Item::ContinuousAssign(list) => {
+                for expr in list.iter() {
+                    if let ExprKind::Assign(lhs, _) = &expr.value {
+                        self.declare_implicit_net(lhs);
+                    }
+                }
+            }
             Item::Initial(_) |
             Item::Always(..) => (),
             Item::HierInstantiation(inst) => {
@@ -547,7 +615,10 @@ impl<'a> AstVisitor for Resolver<'a> {
                         match port_conn {
                             PortConn::Ordered(mut list) => {
                                 for (_, expr) in list.iter_mut() {
-                                    if let Some(v) = expr { self.visit_expr(v); }
+                                    if let Some(v) = expr {
+                                        self.declare_implicit_net(v);
+                                        self.visit_expr(v);
+                                    }
                                 }
                                 if list.len() > ports.len() {
                                     self.diag.report_error(
@@ -596,7 +667,10 @@ impl<'a> AstVisitor for Resolver<'a> {
                                     match conn {
                                         NamedPortConn::Explicit(_, mut expr) => {
                                             // Explicit port - just visit the expression and return as is.
-                                            if let Some(v) = &mut expr { self.visit_expr(v) };
+                                            if let Some(v) = &mut expr {
+                                                self.declare_implicit_net(v);
+                                                self.visit_expr(v);
+                                            }
                                             new_list[id] = Some((attr, expr));
                                         }
                                         NamedPortConn::Implicit(mut name) => {
@@ -643,7 +717,10 @@ impl<'a> AstVisitor for Resolver<'a> {
                 }
                 return;
             }
-            Item::GenRegion(_) => (),
+            Item::GenRegion(list) => {
+                self.visit_items(list);
+                return;
+            }
             Item::LoopGen(gen) => {
                 if let Some(name) = &mut gen.block.name {
                     self.add_to_scope(name, SymbolKind::GenBlock);
@@ -655,9 +732,7 @@ impl<'a> AstVisitor for Resolver<'a> {
                 }
                 self.visit_expr(&mut gen.cond);
                 self.visit_expr(&mut gen.update);
-                for item in &mut gen.block.items {
-                    self.visit_item(item);
-                }
+                self.visit_items(&mut gen.block.items);
                 self.scopes.pop();
                 return;
             }
@@ -679,9 +754,7 @@ impl<'a> AstVisitor for Resolver<'a> {
                         }
                     }
                     self.scopes.push(Scope::new());
-                    for item in &mut block.items {
-                        self.visit_item(item);
-                    }
+                    self.visit_items(&mut block.items);
                     self.scopes.pop();
                 }
 
@@ -697,9 +770,32 @@ impl<'a> AstVisitor for Resolver<'a> {
                         }
                     }
                     self.scopes.push(Scope::new());
-                    for item in &mut false_item.items {
-                        self.visit_item(item);
+                    self.visit_items(&mut false_item.items);
+                    self.scopes.pop();
+                }
+                return;
+            }
+            Item::CaseGen(gen) => {
+                // Similar to IfGen, blocks of different case items can share names.
+                let mut names_added: HashMap<String, SymbolId> = HashMap::new();
+
+                self.visit_expr(&mut gen.expr);
+                for (arm, block) in &mut gen.items {
+                    for expr in arm {
+                        self.visit_expr(expr);
+                    }
+                    if let Some(name) = &mut block.name {
+                        // Use symbol in names_added if there is, otherwise insert.
+                        match names_added.get(&name.value).copied() {
+                            None => {
+                                self.add_to_scope(name, SymbolKind::GenBlock);
+                                names_added.insert(name.value.clone(), name.symbol);
+                            }
+                            Some(v) => name.symbol = v,
+                        }
                     }
+                    self.scopes.push(Scope::new());
+                    self.visit_items(&mut block.items);
                     self.scopes.pop();
                 }
                 return;
@@ -837,6 +933,15 @@ impl<'a> AstVisitor for Resolver<'a> {
                 self.visit_stmt(body);
                 self.scopes.pop();
             }
+            StmtKind::Foreach(ref mut array, ref mut vars, ref mut body) => {
+                self.visit_expr(array);
+                self.scopes.push(Scope::new());
+                for var in vars.iter_mut().flatten() {
+                    self.add_to_scope(var, SymbolKind::Var);
+                }
+                self.visit_stmt(body);
+                self.scopes.pop();
+            }
             StmtKind::SeqBlock(ref mut items) => {
                 self.scopes.push(Scope::new());
                 for item in items { self.visit_stmt(item); }
@@ -0,0 +1,237 @@
+//! Registry of system functions that can be used within constant expressions, and formatting of
+//! arguments of elaboration system tasks.
+
+use num::{BigInt, BigUint, One, ToPrimitive};
+
+use source::{DiagMgr, Span};
+use number::{LogicVec, LogicValue};
+use super::ty::{Ty, IntTy};
+use super::expr::Val;
+
+/// Describes how a call to a constant system function is type checked and evaluated.
+pub enum SysFuncKind {
+    /// A conversion function, which is type checked into a sign cast.
+    SignCast(bool),
+    /// A query on the type of the argument, which can be either a type or an expression. The
+    /// value of the argument is not needed, so the call is folded during type checking.
+    TypeQuery(fn(&Ty) -> Option<usize>),
+    /// A query on a dimension of the first argument, which can be either a type or an
+    /// expression. An optional second argument selects the dimension, defaulting to 1. The
+    /// function is given the left and right bound of the dimension.
+    DimQuery(fn(i32, i32) -> i32),
+    /// A function computing an `int` from the value of a single integral argument.
+    IntFunc(fn(&LogicVec) -> LogicVec),
+}
+
+pub struct SysFunc {
+    pub name: &'static str,
+    pub kind: SysFuncKind,
+}
+
+impl SysFunc {
+    /// Minimum and maximum number of arguments accepted.
+    pub fn arity(&self) -> (usize, usize) {
+        match self.kind {
+            SysFuncKind::DimQuery(_) => (1, 2),
+            _ => (1, 1),
+        }
+    }
+}
+
+static SYS_FUNCS: &[SysFunc] = &[
+    SysFunc { name: "signed", kind: SysFuncKind::SignCast(true) },
+    SysFunc { name: "unsigned", kind: SysFuncKind::SignCast(false) },
+    SysFunc { name: "bits", kind: SysFuncKind::TypeQuery(bits) },
+    SysFunc { name: "left", kind: SysFuncKind::DimQuery(|left, _| left) },
+    SysFunc { name: "right", kind: SysFuncKind::DimQuery(|_, right| right) },
+    SysFunc { name: "high", kind: SysFuncKind::DimQuery(|left, right| left.max(right)) },
+    SysFunc { name: "low", kind: SysFuncKind::DimQuery(|left, right| left.min(right)) },
+    SysFunc { name: "size", kind: SysFuncKind::DimQuery(|left, right| (left - right).abs() + 1) },
+    SysFunc { name: "clog2", kind: SysFuncKind::IntFunc(clog2) },
+    SysFunc { name: "countones", kind: SysFuncKind::IntFunc(countones) },
+];
+
+/// Find a constant system function by its name, without the leading `$`.
+pub fn find(name: &str) -> Option<&'static SysFunc> {
+    SYS_FUNCS.iter().find(|func| func.name == name)
+}
+
+/// Get the number of bits needed to hold a value of the type, or None if the type does not have
+/// a fixed size.
+pub fn bits(ty: &Ty) -> Option<usize> {
+    match ty {
+        Ty::Int(subty) => Some(subty.width()),
+        Ty::FixStr(len) => Some(len * 8),
+        Ty::Array(element, left, right) => Some(bits(element)? * ((left - right).abs() + 1) as usize),
+        _ => None,
+    }
+}
+
+/// Get the bounds of all dimensions of a type, unpacked dimensions first.
+pub fn dims(ty: &Ty) -> Vec<(i32, i32)> {
+    fn int_dims(ty: &IntTy, list: &mut Vec<(i32, i32)>) {
+        match ty {
+            IntTy::Logic(..) => (),
+            IntTy::Array(element, left, right) => {
+                list.push((*left, *right));
+                int_dims(element, list);
+            }
+            // Other integral types are treated as simple vectors.
+            _ => list.push((ty.width() as i32 - 1, 0)),
+        }
+    }
+
+    let mut list = Vec::new();
+    let mut ty = ty;
+    while let Ty::Array(element, left, right) = ty {
+        list.push((*left, *right));
+        ty = element;
+    }
+    if let Ty::Int(subty) = ty {
+        int_dims(subty, &mut list);
+    }
+    list
+}
+
+/// The value of an `int` result that cannot be determined.
+fn unknown() -> LogicVec {
+    LogicVec::fill(32, true, LogicValue::X)
+}
+
+/// Ceiling of the base-2 logarithm, with the argument treated as unsigned.
+fn clog2(val: &LogicVec) -> LogicVec {
+    let mut val = val.clone();
+    val.signed = false;
+    let val = match val.get_two_state() {
+        Some(v) => v,
+        None => return unknown(),
+    };
+    if val <= BigInt::from(1) {
+        return LogicVec::from_integer(0);
+    }
+    let bits = (val - BigInt::from(1)).bits();
+    LogicVec::from_integer(bits as i32)
+}
+
+/// Number of bits that are one. X and Z bits are not counted.
+fn countones(val: &LogicVec) -> LogicVec {
+    LogicVec::from_integer(val.count_ones() as i32)
+}
+
+/// Render arguments of a display-like system task into a message. Each string argument is used
+/// as a format string for the arguments following it. Other arguments are shown in decimal.
+/// `scope` is the hierarchical name printed for `%m`.
+pub fn format_args(diag: &DiagMgr, args: &[(Span, Val)], scope: &str) -> String {
+    let mut out = String::new();
+    let mut iter = args.iter();
+    while let Some((span, val)) = iter.next() {
+        match val {
+            Val::FixStr(fmt) => format_str(diag, *span, fmt, scope, &mut iter, &mut out),
+            _ => out.push_str(&format_val(diag, *span, 'd', None, val)),
+        }
+    }
+    out
+}
+
+/// Render a format string, consuming arguments for each format specifier.
+fn format_str<'a>(
+    diag: &DiagMgr, span: Span, fmt: &str, scope: &str,
+    args: &mut impl Iterator<Item=&'a (Span, Val)>, out: &mut String,
+) {
+    let mut chars = fmt.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            out.push(ch);
+            continue;
+        }
+        let mut width = None;
+        while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+            width = Some(width.unwrap_or(0) * 10 + digit as usize);
+            chars.next();
+        }
+        let spec = match chars.next() {
+            Some('%') => {
+                out.push('%');
+                continue;
+            }
+            // The hierarchical name does not consume an argument.
+            Some('m') | Some('M') => {
+                out.push_str(scope);
+                continue;
+            }
+            Some(spec) => spec.to_ascii_lowercase(),
+            None => {
+                diag.report_error("format string ends with an incomplete format specifier", span);
+                return;
+            }
+        };
+        if !"dhxbosceftg".contains(spec) {
+            diag.report_error(format!("unsupported format specifier %{}", spec), span);
+            continue;
+        }
+        match args.next() {
+            Some((arg_span, val)) => out.push_str(&format_val(diag, *arg_span, spec, width, val)),
+            None => diag.report_error(format!("missing argument for format specifier %{}", spec), span),
+        }
+    }
+}
+
+/// Format a single value according to a format specifier.
+fn format_val(diag: &DiagMgr, span: Span, spec: char, width: Option<usize>, val: &Val) -> String {
+    match (spec, val) {
+        ('s', Val::FixStr(str)) => str.clone(),
+        ('s', Val::Int(vec)) => {
+            // Characters are taken from the bit pattern regardless of signedness.
+            let mut vec = vec.clone().force_two_state();
+            vec.signed = false;
+            let bytes = vec.get_two_state().unwrap().to_biguint().unwrap().to_bytes_be();
+            bytes.into_iter().filter(|&ch| ch != 0).map(|ch| ch as char).collect()
+        }
+        ('c', Val::Int(vec)) => {
+            let ch = vec.clone().force_two_state().extend_or_trunc(8);
+            let mut ch = ch.get_two_state().unwrap().to_i32().unwrap();
+            if ch < 0 { ch += 256 }
+            (ch as u8 as char).to_string()
+        }
+        ('d', Val::Int(vec)) | ('t', Val::Int(vec)) => {
+            let str = vec.to_radix_string(10);
+            // By default decimal numbers are padded to the size of largest possible value
+            let width = width.unwrap_or_else(|| {
+                let max = (BigUint::one() << vec.width()) - BigUint::one();
+                max.to_string().len() + vec.signed as usize
+            });
+            format!("{:>width$}", str, width = width)
+        }
+        ('h', Val::Int(vec)) | ('x', Val::Int(vec)) | ('b', Val::Int(vec)) | ('o', Val::Int(vec)) => {
+            let radix = match spec {
+                'b' => 2,
+                'o' => 8,
+                _ => 16,
+            };
+            let str = vec.to_radix_string(radix);
+            match width {
+                None => str,
+                Some(width) => {
+                    // An explicit width removes leading zeros
+                    let str = str.trim_start_matches('0');
+                    let str = if str.is_empty() { "0" } else { str };
+                    format!("{:0>width$}", str, width = width)
+                }
+            }
+        }
+        ('e', Val::Real(val)) => format!("{:e}", val),
+        ('f', Val::Real(val)) => format!("{:.6}", val),
+        ('g', Val::Real(val)) => format!("{}", val),
+        ('d', Val::Real(val)) => format!("{}", val.round()),
+        ('e', Val::Int(vec)) | ('f', Val::Int(vec)) | ('g', Val::Int(vec)) => {
+            match vec.get_two_state().and_then(|v| v.to_f64()) {
+                Some(val) => format_val(diag, span, spec, width, &Val::Real(val)),
+                None => "x".to_owned(),
+            }
+        }
+        _ => {
+            diag.report_error(format!("value cannot be displayed with format specifier %{}", spec), span);
+            String::new()
+        }
+    }
+}
@@ -0,0 +1,685 @@
+//! Lower reconstructed AST so that it only uses Verilog-2005 constructs.
+//!
+//! Types and typed expressions are already taken care of when reconstructing in Verilog mode.
+//! This pass deals with the remaining constructs that can be lowered syntactically, e.g.
+//! `always_comb` or declarations within statements, and decides whether a variable should be
+//! declared as `reg` or `wire` depending on how it is driven.
+
+use std::mem;
+use std::collections::{HashMap, HashSet};
+
+use syntax::ast::*;
+use syntax::ast_visit::AstVisitor;
+use syntax::tokens::{TokenKind, Keyword};
+use number::{LogicVec, LogicNumber, LogicValue};
+use source::{DiagMgr, Span};
+use super::reconstruct::reconstruct_usize;
+use super::jump_elim::jump_elim;
+
+pub fn lower(diag: &DiagMgr, list: &mut Vec<Item>) {
+    let mut lowerer = VerilogLowerer {
+        diag,
+        blk_id: 0,
+        typedefs: HashMap::new(),
+    };
+    // Parameters and types outside design units are all substituted during elaboration.
+    list.retain(|item| !matches!(item, Item::ParamDecl(_) | Item::Typedef(..)));
+    for item in list {
+        match item {
+            Item::DesignDecl(decl) => lowerer.lower_design(decl),
+            Item::FuncDecl(decl) => lowerer.unsupported("function outside design units", decl.name.span),
+            Item::TaskDecl(decl) => lowerer.unsupported("task outside design units", decl.name.span),
+            Item::DataDecl(decl) => lowerer.unsupported("variable outside design units", decl.list[0].name.span),
+            Item::NetDecl(decl) => lowerer.unsupported("net outside design units", decl.list[0].name.span),
+            _ => (),
+        }
+    }
+}
+
+/// Get the name of the variable assigned by an lvalue.
+fn lvalue_names(expr: &Expr, names: &mut HashSet<String>) {
+    match &expr.value {
+        ExprKind::HierName(id) => {
+            let mut id = id;
+            loop {
+                match id {
+                    HierId::Member(parent, _) |
+                    HierId::Select(parent, _) => id = &parent.value,
+                    HierId::Name(_, name) => {
+                        names.insert(name.value.clone());
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        ExprKind::Concat(list, _) => {
+            for expr in list {
+                lvalue_names(expr, names);
+            }
+        }
+        ExprKind::Paren(expr) => lvalue_names(expr, names),
+        _ => (),
+    }
+}
+
+/// Build a statement from its kind.
+fn new_stmt(value: StmtKind) -> Stmt {
+    Stmt {
+        label: None,
+        attr: None,
+        value,
+    }
+}
+
+/// Build a blocking assignment statement to a variable.
+fn assign_stmt(name: &Ident, rhs: Expr) -> Stmt {
+    let lhs = Spanned::new(ExprKind::HierName(HierId::Name(None, Box::new(name.clone()))), name.span);
+    let span = rhs.span;
+    new_stmt(StmtKind::Expr(Box::new(Spanned::new(ExprKind::Assign(Box::new(lhs), Box::new(rhs)), span))))
+}
+
+/// Get directions of all ports of a function or task, in declaration order.
+fn port_dirs(ports: &[PortDecl]) -> Vec<PortDir> {
+    ports.iter().flat_map(|port| match port {
+        PortDecl::Data(dir, _, _, list) => vec![*dir; list.len()],
+        _ => unreachable!(),
+    }).collect()
+}
+
+/// Collect all variables assigned procedurally.
+struct DriverCollector {
+    names: HashSet<String>,
+    /// Port directions of functions and tasks declared within the design unit. Variables bound
+    /// to ports other than inputs are assigned procedurally as well.
+    subroutines: HashMap<String, Vec<PortDir>>,
+    /// Names declared within the function or task being visited, innermost scope last.
+    locals: Vec<HashSet<String>>,
+}
+
+impl DriverCollector {
+    fn drive(&mut self, lhs: &Expr) {
+        let mut names = HashSet::new();
+        lvalue_names(lhs, &mut names);
+        for name in names {
+            if !self.locals.iter().any(|scope| scope.contains(&name)) {
+                self.names.insert(name);
+            }
+        }
+    }
+
+    /// Collect assignments within a function or task to variables not declared in it. `name` is
+    /// the name of the function, which is assigned to return a value.
+    fn visit_subroutine(&mut self, name: Option<&Ident>, ports: &[PortDecl], stmts: &mut [Stmt]) {
+        let mut scope: HashSet<String> = name.iter().map(|name| name.value.clone()).collect();
+        for port in ports {
+            if let PortDecl::Data(.., list) = port {
+                scope.extend(list.iter().map(|assign| assign.name.value.clone()));
+            }
+        }
+        self.locals.push(scope);
+        for stmt in stmts {
+            self.visit_stmt(stmt);
+        }
+        self.locals.pop();
+    }
+}
+
+impl AstVisitor for DriverCollector {
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        match &stmt.value {
+            StmtKind::SeqBlock(_) => {
+                self.locals.push(HashSet::new());
+                self.do_visit_stmt(stmt);
+                self.locals.pop();
+            }
+            StmtKind::DataDecl(decl) => {
+                if let Some(scope) = self.locals.last_mut() {
+                    scope.extend(decl.list.iter().map(|assign| assign.name.value.clone()));
+                }
+                self.do_visit_stmt(stmt);
+            }
+            _ => self.do_visit_stmt(stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        match &expr.value {
+            ExprKind::Assign(lhs, _) |
+            ExprKind::NonblockAssign(lhs, _) => self.drive(lhs),
+            ExprKind::FuncCall { expr: callee, args: Some(args), .. } => {
+                if let ExprKind::HierName(HierId::Name(None, name)) = &callee.value {
+                    if let Some(dirs) = self.subroutines.get(&name.value).cloned() {
+                        // Arguments are bound to ports in declaration order during elaboration.
+                        for (dir, arg) in dirs.iter().zip(&args.ordered) {
+                            match (dir, arg) {
+                                (PortDir::Input, _) | (_, None) => (),
+                                (_, Some(arg)) => self.drive(arg),
+                            }
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+        self.do_visit_expr(expr);
+    }
+}
+
+struct VerilogLowerer<'a> {
+    diag: &'a DiagMgr,
+    /// Counter for naming blocks which need to be named in Verilog-2005
+    blk_id: usize,
+    /// Typedefs of the current design unit, which are substituted into types referring to them
+    typedefs: HashMap<String, DataType>,
+}
+
+impl<'a> VerilogLowerer<'a> {
+    fn unsupported(&self, what: &str, span: Span) {
+        self.diag.report_error(format!("{} cannot be lowered to Verilog-2005", what), span);
+    }
+
+    fn new_blk_name(&mut self) -> Box<Ident> {
+        let name = format!("unnamed_blk_{}", self.blk_id);
+        self.blk_id += 1;
+        Box::new(Ident::new_unspanned(name))
+    }
+
+    /// Lower a data type. Types of variables become `reg`, and all others become implicit.
+    fn lower_ty(&self, ty: &mut DataType, var: bool) {
+        let (sign, dim) = match &mut ty.value {
+            DataTypeKind::Implicit(sign, dim) |
+            DataTypeKind::IntVec(_, sign, dim) => (*sign, mem::take(dim)),
+            DataTypeKind::IntAtom(IntAtomTy::Int, None) |
+            DataTypeKind::IntAtom(IntAtomTy::Int, Some(Signing::Signed)) |
+            DataTypeKind::IntAtom(IntAtomTy::Integer, None) |
+            DataTypeKind::IntAtom(IntAtomTy::Integer, Some(Signing::Signed)) => {
+                ty.value = DataTypeKind::IntAtom(IntAtomTy::Integer, None);
+                return;
+            }
+            DataTypeKind::IntAtom(IntAtomTy::Time, None) |
+            DataTypeKind::Real(_) => return,
+            DataTypeKind::HierName(None, name, dim) if dim.is_empty() && self.typedefs.contains_key(&name.value) => {
+                ty.value = self.typedefs[&name.value].value.clone();
+                self.lower_ty(ty, var);
+                return;
+            }
+            DataTypeKind::IntAtom(atom, sign) => {
+                let width = match atom {
+                    IntAtomTy::Byte => 8,
+                    IntAtomTy::Shortint => 16,
+                    IntAtomTy::Int | IntAtomTy::Integer => 32,
+                    IntAtomTy::Longint | IntAtomTy::Time => 64,
+                };
+                let default_sign = if *atom == IntAtomTy::Time { Signing::Unsigned } else { Signing::Signed };
+                let dim = Spanned::new(DimKind::Range(
+                    Box::new(reconstruct_usize(width - 1)),
+                    Box::new(reconstruct_usize(0))
+                ), ty.span);
+                (sign.unwrap_or(default_sign), vec![dim])
+            }
+            _ => {
+                self.unsupported("data type", ty.span);
+                return;
+            }
+        };
+        ty.value = if var {
+            DataTypeKind::IntVec(IntVecTy::Reg, sign, dim)
+        } else {
+            DataTypeKind::Implicit(sign, dim)
+        };
+    }
+
+    /// Lower a statement that has side effects only, i.e. an expression statement or for-loop
+    /// update. Increments and operator assignments are turned into blocking assignments.
+    fn lower_side_effect(&self, expr: &mut Expr) {
+        let (lhs, op, rhs) = match &mut expr.value {
+            // Task enables without arguments cannot have parentheses in Verilog-2005.
+            ExprKind::FuncCall { args, .. } => {
+                if args.as_ref().is_some_and(|args| args.ordered.is_empty() && args.named.is_empty()) {
+                    *args = None;
+                }
+                return;
+            }
+            ExprKind::PrefixIncDec(incdec, _, lhs) |
+            ExprKind::PostfixIncDec(lhs, _, incdec) => {
+                let op = if *incdec == IncDec::Inc { BinaryOp::Add } else { BinaryOp::Sub };
+                (lhs.clone(), op, Box::new(reconstruct_usize(1)))
+            }
+            ExprKind::BinaryAssign(lhs, op, rhs) => {
+                (lhs.clone(), *op, Box::new(Spanned::new(ExprKind::Paren(rhs.clone()), rhs.span)))
+            }
+            _ => return,
+        };
+        let span = expr.span;
+        let value = Spanned::new(ExprKind::Binary(lhs.clone(), op, None, rhs), span);
+        expr.value = ExprKind::Assign(lhs, Box::new(value));
+    }
+
+    /// Move declarations within a list of statements to the front, as required by Verilog-2005.
+    /// Returns whether there is any declaration.
+    fn hoist_decls(&self, list: &mut Vec<Stmt>) -> bool {
+        if !list.iter().any(|stmt| matches!(stmt.value, StmtKind::DataDecl(_))) {
+            return false;
+        }
+        let mut decls = Vec::new();
+        let mut stmts = Vec::new();
+        for item in list.drain(..) {
+            match item.value {
+                StmtKind::DataDecl(mut decl) => {
+                    for assign in &mut decl.list {
+                        if let Some(init) = assign.init.take() {
+                            stmts.push(assign_stmt(&assign.name, *init));
+                        }
+                    }
+                    decls.push(new_stmt(StmtKind::DataDecl(decl)));
+                }
+                _ => stmts.push(item),
+            }
+        }
+        decls.extend(stmts);
+        *list = decls;
+        true
+    }
+
+    /// Lower ports and body of functions and tasks. `func` is the name of the function, if any.
+    fn lower_subroutine(&mut self, func: Option<&Ident>, ports: &mut Vec<PortDecl>, stmts: &mut Vec<Stmt>) {
+        let mut names = HashSet::new();
+        for port in ports.iter() {
+            match port {
+                PortDecl::Data(_, _, _, list) |
+                PortDecl::Interface(_, _, list) => names.extend(list.iter().map(|assign| assign.name.value.clone())),
+                PortDecl::Explicit(_, name, _) => { names.insert(name.value.clone()); }
+            }
+        }
+        jump_elim(stmts, func, names);
+        for port in ports {
+            match port {
+                PortDecl::Data(_, net, ty, list) => {
+                    if let NetPortType::Variable = net {
+                        *net = NetPortType::Default;
+                    }
+                    self.lower_ty(ty, false);
+                    // Default values are already filled into calls during elaboration.
+                    for assign in list {
+                        assign.init = None;
+                    }
+                }
+                PortDecl::Interface(_, _, list) => self.unsupported("interface port", list[0].name.span),
+                PortDecl::Explicit(_, name, _) => self.unsupported("explicit port", name.span),
+            }
+        }
+        for stmt in stmts.iter_mut() {
+            self.visit_stmt(stmt);
+        }
+        // Declarations must precede the single statement forming the body.
+        self.hoist_decls(stmts);
+        let first_stmt = stmts.iter().position(|stmt| !matches!(stmt.value, StmtKind::DataDecl(_)));
+        if let Some(first_stmt) = first_stmt {
+            if stmts.len() - first_stmt > 1 {
+                let body = stmts.split_off(first_stmt);
+                stmts.push(new_stmt(StmtKind::SeqBlock(body)));
+            }
+        }
+    }
+
+    /// Lower jump statements within a procedural block.
+    fn lower_jumps(&mut self, body: &mut Box<Stmt>) {
+        let mut list = vec![mem::replace(&mut **body, new_stmt(StmtKind::Empty))];
+        jump_elim(&mut list, None, HashSet::new());
+        **body = match list.len() {
+            1 => list.pop().unwrap(),
+            _ => new_stmt(StmtKind::SeqBlock(list)),
+        };
+    }
+
+    fn lower_item(&mut self, item: &mut Item) {
+        match item {
+            Item::Always(kw, body) => {
+                self.lower_jumps(body);
+                self.visit_stmt(body);
+                match kw {
+                    AlwaysKw::AlwaysComb |
+                    AlwaysKw::AlwaysLatch => {
+                        let inner = mem::replace(body, Box::new(new_stmt(StmtKind::Empty)));
+                        **body = new_stmt(StmtKind::TimingCtrl(TimingCtrl::ImplicitEventCtrl, inner));
+                    }
+                    _ => (),
+                }
+                *kw = AlwaysKw::Always;
+            }
+            Item::Initial(body) => {
+                self.lower_jumps(body);
+                self.visit_stmt(body);
+            }
+            Item::ContinuousAssign(list) => {
+                for expr in list {
+                    self.visit_expr(expr);
+                }
+            }
+            Item::FuncDecl(decl) => {
+                let decl = &mut **decl;
+                self.lower_subroutine(Some(&decl.name), &mut decl.ports, &mut decl.stmts);
+            }
+            Item::TaskDecl(decl) => {
+                let decl = &mut **decl;
+                self.lower_subroutine(None, &mut decl.ports, &mut decl.stmts);
+            }
+            Item::ParamDecl(decl) => {
+                for assign in &mut decl.list {
+                    if let Some(init) = &mut assign.init {
+                        self.visit_expr(init);
+                    }
+                }
+            }
+            Item::DataDecl(decl) => {
+                for assign in &mut decl.list {
+                    if let Some(init) = &mut assign.init {
+                        self.visit_expr(init);
+                    }
+                }
+            }
+            Item::NetDecl(decl) => {
+                for assign in &mut decl.list {
+                    if let Some(init) = &mut assign.init {
+                        self.visit_expr(init);
+                    }
+                }
+            }
+            Item::HierInstantiation(_) => self.do_visit_item(item),
+            Item::GenRegion(list) => {
+                for item in list {
+                    self.lower_item(item);
+                }
+            }
+            Item::IfGen(gen) => {
+                for (cond, block) in &mut gen.if_block {
+                    self.visit_expr(cond);
+                    for item in &mut block.items {
+                        self.lower_item(item);
+                    }
+                }
+                if let Some(block) = &mut gen.else_block {
+                    for item in &mut block.items {
+                        self.lower_item(item);
+                    }
+                }
+            }
+            Item::LoopGen(gen) => {
+                for item in &mut gen.block.items {
+                    self.lower_item(item);
+                }
+            }
+            Item::ModportDecl(_, list) => self.unsupported("modport", list[0].0.span),
+            _ => (),
+        }
+    }
+
+    /// Declare variables as `reg` if they are assigned procedurally or initialised, and as
+    /// `wire` otherwise.
+    fn declare_item(&self, item: &mut Item, regs: &HashSet<String>) {
+        match item {
+            Item::DataDecl(decl) => {
+                if decl.list.iter().any(|assign| assign.init.is_some() || regs.contains(&*assign.name)) {
+                    self.lower_ty(&mut decl.ty, true);
+                    return;
+                }
+                let mut ty = mem::replace(&mut decl.ty, Spanned::new_unspanned(DataTypeKind::Void));
+                self.lower_ty(&mut ty, false);
+                *item = Item::NetDecl(Box::new(NetDecl {
+                    attr: None,
+                    net: NetTy::Wire,
+                    ty,
+                    list: mem::take(&mut decl.list),
+                }));
+            }
+            Item::NetDecl(decl) => self.lower_ty(&mut decl.ty, false),
+            Item::GenRegion(list) => {
+                for item in list {
+                    self.declare_item(item, regs);
+                }
+            }
+            Item::IfGen(gen) => {
+                for (_, block) in &mut gen.if_block {
+                    for item in &mut block.items {
+                        self.declare_item(item, regs);
+                    }
+                }
+                if let Some(block) = &mut gen.else_block {
+                    for item in &mut block.items {
+                        self.declare_item(item, regs);
+                    }
+                }
+            }
+            Item::LoopGen(gen) => {
+                for item in &mut gen.block.items {
+                    self.declare_item(item, regs);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn lower_design(&mut self, decl: &mut DesignDecl) {
+        if decl.kw != Keyword::Module {
+            self.unsupported(&format!("{}", decl.kw), decl.name.span);
+        }
+        self.blk_id = 0;
+
+        // Typedefs that remain are replaced by the types they name.
+        self.typedefs.clear();
+        let mut typedefs = Vec::new();
+        decl.items.retain(|item| match item {
+            Item::Typedef(None, ty, name, dim) if dim.is_empty() => {
+                typedefs.push((name.value.clone(), (**ty).clone()));
+                false
+            }
+            _ => true,
+        });
+        self.typedefs.extend(typedefs);
+
+        for item in &mut decl.items {
+            self.lower_item(item);
+        }
+
+        // Expressions that cannot be lowered are left as is, and the visitor cannot handle them.
+        if self.diag.has_error() {
+            return;
+        }
+
+        // Find out all variables that are assigned procedurally.
+        let mut collector = DriverCollector {
+            names: HashSet::new(),
+            subroutines: HashMap::new(),
+            locals: Vec::new(),
+        };
+        for item in &decl.items {
+            match item {
+                Item::FuncDecl(func) => { collector.subroutines.insert(func.name.value.clone(), port_dirs(&func.ports)); }
+                Item::TaskDecl(task) => { collector.subroutines.insert(task.name.value.clone(), port_dirs(&task.ports)); }
+                _ => (),
+            }
+        }
+        for item in &mut decl.items {
+            match item {
+                Item::Always(..) |
+                Item::Initial(..) |
+                Item::GenRegion(..) |
+                Item::IfGen(..) |
+                Item::LoopGen(..) => collector.visit_item(item),
+                Item::FuncDecl(func) => collector.visit_subroutine(Some(&func.name), &func.ports, &mut func.stmts),
+                Item::TaskDecl(task) => collector.visit_subroutine(None, &task.ports, &mut task.stmts),
+                _ => (),
+            }
+        }
+        let regs = collector.names;
+
+        for port in &mut decl.port {
+            if let PortDecl::Data(dir, net, ty, list) = port {
+                let driven = list.iter().any(|assign| regs.contains(&*assign.name));
+                match net {
+                    NetPortType::Builtin(_) |
+                    NetPortType::Default if !driven => self.lower_ty(ty, false),
+                    NetPortType::Variable if !driven => {
+                        *net = NetPortType::Builtin(NetTy::Wire);
+                        self.lower_ty(ty, false);
+                    }
+                    NetPortType::Variable |
+                    NetPortType::Default if *dir == PortDir::Output => {
+                        *net = NetPortType::Default;
+                        self.lower_ty(ty, true);
+                    }
+                    NetPortType::UserDefined(_) |
+                    NetPortType::Interconnect => self.unsupported("net type", list[0].name.span),
+                    _ => self.unsupported("procedural assignment to non-output port", list[0].name.span),
+                }
+            }
+        }
+        for item in &mut decl.items {
+            self.declare_item(item, &regs);
+        }
+    }
+}
+
+impl<'a> AstVisitor for VerilogLowerer<'a> {
+    fn visit_ty(&mut self, ty: &mut DataType) {
+        // Only types of variables declared within statements reach here.
+        self.lower_ty(ty, true);
+        self.do_visit_ty(ty);
+    }
+
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.value {
+            StmtKind::If(uniq, ..) => *uniq = None,
+            StmtKind::Case { uniq, .. } => *uniq = None,
+            StmtKind::Assert { expr, .. } => self.unsupported("assertion", expr.span),
+            // Foreach loops are rewritten during elaboration, so none should be left here.
+            StmtKind::Foreach(array, ..) => self.unsupported("foreach loop", array.span),
+            StmtKind::Expr(expr) => self.lower_side_effect(expr),
+            StmtKind::For { update, .. } => {
+                for expr in update {
+                    self.lower_side_effect(expr);
+                }
+            }
+            _ => (),
+        }
+        self.do_visit_stmt(stmt);
+
+        let needs_name = match &mut stmt.value {
+            StmtKind::SeqBlock(list) => self.hoist_decls(list),
+            StmtKind::For { ty, init, .. } => {
+                // Move declaration of loop variables out of the loop
+                if let Some(ty) = ty.take() {
+                    let list = init.iter().filter_map(|expr| match &expr.value {
+                        ExprKind::Assign(lhs, _) => match &lhs.value {
+                            ExprKind::HierName(HierId::Name(None, name)) => Some(DeclAssign {
+                                name: (**name).clone(),
+                                dim: Vec::new(),
+                                init: None,
+                            }),
+                            _ => None,
+                        },
+                        _ => None,
+                    }).collect();
+                    let decl = new_stmt(StmtKind::DataDecl(Box::new(DataDecl {
+                        attr: None,
+                        has_const: false,
+                        lifetime: Lifetime::Static,
+                        ty: *ty,
+                        list,
+                    })));
+                    let for_stmt = mem::replace(stmt, new_stmt(StmtKind::Empty));
+                    stmt.value = StmtKind::SeqBlock(vec![decl, for_stmt]);
+                    true
+                } else {
+                    false
+                }
+            }
+            StmtKind::DoWhile(..) => {
+                // Verilog has no do-while loop, so execute the body once before a while loop.
+                if let StmtKind::DoWhile(body, cond) = mem::replace(&mut stmt.value, StmtKind::Empty) {
+                    let mut first = Stmt::clone(&body);
+                    // Block names must stay unique within the enclosing scope.
+                    if first.label.is_some() {
+                        first.label = Some(self.new_blk_name());
+                    }
+                    stmt.value = StmtKind::SeqBlock(vec![first, new_stmt(StmtKind::While(cond, body))]);
+                }
+                false
+            }
+            _ => false,
+        };
+        // Only named blocks can have declarations
+        if needs_name && stmt.label.is_none() {
+            stmt.label = Some(self.new_blk_name());
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        match &mut expr.value {
+            ExprKind::PrefixIncDec(..) |
+            ExprKind::PostfixIncDec(..) |
+            ExprKind::BinaryAssign(..) => {
+                self.unsupported("assignment within expression", expr.span);
+                return;
+            }
+            ExprKind::Type(_) |
+            ExprKind::TypeCast(..) |
+            ExprKind::ConstCast(_) |
+            ExprKind::EmptyQueue |
+            ExprKind::MinTypMax(..) => {
+                self.unsupported("expression", expr.span);
+                return;
+            }
+            ExprKind::HierName(HierId::Name(Some(_), _)) => {
+                self.unsupported("reference to package item", expr.span);
+                return;
+            }
+            ExprKind::Binary(_, BinaryOp::WildEq, _, _) |
+            ExprKind::Binary(_, BinaryOp::WildNeq, _, _) |
+            ExprKind::Binary(_, BinaryOp::Imply, _, _) |
+            ExprKind::Binary(_, BinaryOp::Equiv, _, _) => {
+                self.unsupported("operator", expr.span);
+            }
+            ExprKind::AssignPattern(_, AssignPattern::Simple(list)) => {
+                let list = mem::take(list);
+                expr.value = ExprKind::Concat(list, None);
+            }
+            ExprKind::AssignPattern(..) => {
+                self.unsupported("assignment pattern", expr.span);
+                return;
+            }
+            ExprKind::SignCast(sign, inner) => {
+                let name = if *sign == Signing::Signed { "signed" } else { "unsigned" };
+                let inner = mem::replace(inner, Box::new(Spanned::new_unspanned(ExprKind::EmptyQueue)));
+                expr.value = ExprKind::SysTfCall(Box::new(SysTfCall {
+                    task: Spanned::new(name.to_owned(), expr.span),
+                    args: Some(Args {
+                        ordered: vec![Some(inner)],
+                        named: Vec::new(),
+                    }),
+                }));
+            }
+            ExprKind::Literal(literal) => {
+                if let TokenKind::UnbasedLiteral(value) = literal.value {
+                    // An unsized literal is extended to the width of the context, but only for 0,
+                    // x and z. '1 is thus expressed as ~'b0.
+                    let bit = if value == LogicValue::One { LogicValue::Zero } else { value };
+                    let number = LogicNumber {
+                        value: LogicVec::fill(1, false, bit),
+                        sized: false,
+                    };
+                    let span = literal.span;
+                    let number = Spanned::new(ExprKind::Literal(Spanned::new(TokenKind::IntegerLiteral(number), span)), span);
+                    expr.value = if value == LogicValue::One {
+                        ExprKind::Unary(UnaryOp::Not, None, Box::new(number))
+                    } else {
+                        number.value
+                    };
+                }
+            }
+            _ => (),
+        }
+        self.do_visit_expr(expr);
+    }
+}
@@ -0,0 +1,164 @@
+//! Expansion of file lists and plus-arguments as accepted by most EDA tools. The content of file
+//! lists given by `-f` or `-F` is spliced into the command line, and `+incdir+`, `+define+` and
+//! `+libext+` are turned into equivalent options.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Options taking a value which is not a path.
+const VALUE_OPTS: &[&str] = &[
+    "-o", "-t", "-G", "-b", "-D", "-U", "-p", "-MF", "-MT", "--MF", "--MT", "--std", "--keywords", "--libext",
+];
+
+/// Options taking a path as the value. Within a file list given by `-F`, relative paths are
+/// relative to the file list.
+const PATH_OPTS: &[&str] = &["-f", "-F", "-I", "-v", "-y", "-isystem", "--isystem"];
+
+/// Expand file lists and plus-arguments in command line arguments, excluding the program name.
+pub fn expand_args(args: Vec<String>) -> Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    expand(args, None, &mut out, &mut Vec::new())?;
+    Ok(out)
+}
+
+/// Expand a list of arguments. `base` is the directory that relative paths are relative to, and
+/// `stack` contains the file lists being read.
+fn expand(args: Vec<String>, base: Option<&Path>, out: &mut Vec<String>, stack: &mut Vec<PathBuf>) -> Result<(), String> {
+    let rebase = |path: &str| match base {
+        Some(base) if Path::new(path).is_relative() => base.join(path).to_string_lossy().into_owned(),
+        _ => path.to_owned(),
+    };
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if let Some(rest) = arg.strip_prefix('+') {
+            let mut parts = rest.split('+').filter(|part| !part.is_empty());
+            let opt = match parts.next() {
+                Some("incdir") => "-I",
+                Some("define") => "-D",
+                Some("libext") => "--libext",
+                _ => return Err(format!("unknown option {}", arg)),
+            };
+            for part in parts {
+                out.push(opt.to_owned());
+                out.push(if opt == "-I" { rebase(part) } else { part.to_owned() });
+            }
+            continue;
+        }
+
+        let is_path = PATH_OPTS.contains(&&arg[..]);
+        if !is_path && !VALUE_OPTS.contains(&&arg[..]) {
+            out.push(if arg.starts_with('-') { arg } else { rebase(&arg) });
+            continue;
+        }
+        let value = match iter.next() {
+            Some(v) => v,
+            None => return Err(format!("option {} requires a value", arg)),
+        };
+        if !is_path {
+            out.push(arg);
+            out.push(value);
+            continue;
+        }
+        let value = rebase(&value);
+        match &arg[..] {
+            "-f" | "-F" => {
+                let path = PathBuf::from(&value);
+                let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if stack.contains(&canonical) {
+                    return Err(format!("file list {} includes itself", value));
+                }
+                let list = read_list(&path)?;
+                stack.push(canonical);
+                // Paths in lists given by -f are relative to the working directory
+                let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                let base = if arg == "-F" { Some(dir.as_path()) } else { None };
+                expand(list, base, out, stack)?;
+                stack.pop();
+            }
+            _ => {
+                out.push(arg);
+                out.push(value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Split a file list into arguments. Comments are removed and environment variables are expanded.
+fn read_list(path: &Path) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("failed when reading file list {}: {}", path.display(), err))?;
+
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut chars = content.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&ch| ch != '\n') { chars.next(); }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for ch in chars.by_ref() {
+                    if prev == '*' && ch == '/' { break }
+                    prev = ch;
+                }
+            }
+            '#' if arg.is_empty() => {
+                while chars.peek().is_some_and(|&ch| ch != '\n') { chars.next(); }
+            }
+            '"' => {
+                for ch in chars.by_ref() {
+                    if ch == '"' { break }
+                    arg.push(ch);
+                }
+            }
+            _ if ch.is_whitespace() => {
+                if !arg.is_empty() {
+                    args.push(expand_env(&arg)?);
+                    arg.clear();
+                }
+            }
+            _ => arg.push(ch),
+        }
+    }
+    if !arg.is_empty() {
+        args.push(expand_env(&arg)?);
+    }
+    Ok(args)
+}
+
+/// Expand environment variables written as `$NAME`, `${NAME}` or `$(NAME)`.
+fn expand_env(arg: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = arg;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        let (name, len) = match rest.chars().next() {
+            Some(open @ '{') | Some(open @ '(') => {
+                let close = if open == '{' { '}' } else { ')' };
+                match rest.find(close) {
+                    Some(end) => (&rest[1..end], end + 1),
+                    None => return Err(format!("unterminated environment variable in {}", arg)),
+                }
+            }
+            _ => {
+                let end = rest.find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_').unwrap_or(rest.len());
+                (&rest[..end], end)
+            }
+        };
+        if name.is_empty() {
+            return Err(format!("expected environment variable name after $ in {}", arg));
+        }
+        match env::var(name) {
+            Ok(value) => out.push_str(&value),
+            Err(_) => return Err(format!("environment variable {} is not defined", name)),
+        }
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
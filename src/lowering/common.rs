@@ -1,7 +1,10 @@
 use std::rc::Rc;
+use std::collections::HashSet;
 
+use source::Span;
+use syntax::tokens::Keyword;
 use syntax::ast::Ident;
-use elaborate::hier::HierItem;
+use elaborate::hier::{HierItem, HierScope};
 
 pub fn name_of_mut(item: &mut HierItem) -> Option<&mut Ident> {
     match item {
@@ -56,3 +59,71 @@ pub fn name_of(item: &HierItem) -> Option<&Ident> {
         }
     }
 }
+
+/// Collect all names declared in a scope and its generate blocks.
+pub fn collect_names(scope: &HierScope, names: &mut HashSet<String>) {
+    for item in &scope.items {
+        match item {
+            // Enums are named by the elements.
+            HierItem::Enum(..) => (),
+            HierItem::Design(_) => (),
+            HierItem::GenBlock(genblk) => {
+                if let Some(name) = &genblk.name { names.insert(name.value.clone()); }
+                collect_names(&genblk.scope, names);
+            }
+            HierItem::LoopGenBlock(loopgenblk) => {
+                if let Some(name) = &loopgenblk.name { names.insert(name.value.clone()); }
+                for (_, genblk) in loopgenblk.instances.borrow().iter() {
+                    collect_names(&genblk.scope, names);
+                }
+            }
+            _ => {
+                if let Some(name) = name_of(item) { names.insert(name.value.clone()); }
+            }
+        }
+    }
+}
+
+/// Pick a name based on `name` that is not in `taken`, and mark it as taken.
+pub fn fresh_name(taken: &mut HashSet<String>, name: &str, span: Span) -> Ident {
+    let mut candidate = name.to_owned();
+    let mut suffix = 0;
+    while taken.contains(&candidate) {
+        suffix += 1;
+        candidate = format!("{}_{}", name, suffix);
+    }
+    taken.insert(candidate.clone());
+    Ident::new(candidate, span)
+}
+
+/// Insert declarations into a design unit after its parameters and ports, so they are not
+/// mistaken as either of them.
+pub fn insert_decls(scope: &mut HierScope, mut decls: Vec<HierItem>) {
+    if decls.is_empty() {
+        return;
+    }
+    ::util::replace_with(scope, |scope| {
+        let mut new_scope = HierScope::new();
+        for item in scope.items {
+            match item {
+                HierItem::Param(ref decl) if decl.kw == Keyword::Parameter => (),
+                HierItem::DataPort(_) |
+                HierItem::InterfacePort(_) => (),
+                _ => for decl in decls.drain(..) {
+                    let ident = name_of(&decl).cloned();
+                    new_scope.insert(ident, decl);
+                }
+            }
+            let ident = match item {
+                HierItem::Design(_) => None,
+                _ => name_of(&item).cloned(),
+            };
+            new_scope.insert(ident, item);
+        }
+        for decl in decls {
+            let ident = name_of(&decl).cloned();
+            new_scope.insert(ident, decl);
+        }
+        new_scope
+    })
+}
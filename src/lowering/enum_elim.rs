@@ -0,0 +1,273 @@
+//! Replace enumerations with their base types. Each enum element becomes a localparam, and
+//! references to enum elements, which elaboration has already folded into constants, are turned
+//! into references to these localparams.
+//!
+//! Elements declared in a scope keep their names. Elements of enums declared elsewhere get a
+//! localparam in each design unit that uses them, named after the package they come from if any.
+//! Names are made unique within the design unit, including its generate blocks.
+//!
+//! This pass must be run after struct_elim, as members of packed structs are not visited.
+
+use std::mem;
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+
+use syntax::tokens::Keyword;
+use syntax::ast::{self, Ident};
+use elaborate::ty::{Ty, IntTy, Enum};
+use elaborate::expr::{self, Val};
+use elaborate::hier::{self, HierItem, HierScope};
+use elaborate::eht_visit::EhtVisitor;
+
+use super::common::fresh_name;
+
+pub fn enum_elim(source: &mut hier::Source) {
+    let mut elim = EnumEliminator {
+        origins: HashMap::new(),
+        taken: HashSet::new(),
+        scopes: Vec::new(),
+        foreign: Vec::new(),
+    };
+    elim.visit(source);
+}
+
+struct EnumEliminator {
+    /// Mapping from enums to names of packages they are declared in.
+    origins: HashMap<usize, String>,
+
+    /// All names used in the current design unit.
+    taken: HashSet<String>,
+
+    /// Names of localparams for enum elements visible in each of the nested scopes.
+    scopes: Vec<HashMap<(usize, usize), Ident>>,
+
+    /// Localparams to be added to current design unit for enums declared elsewhere.
+    foreign: Vec<HierItem>,
+}
+
+fn enum_key(enu: &Enum) -> usize {
+    enu as *const _ as usize
+}
+
+/// Replace all enums within an integral type with their base types.
+fn lower_int(ty: &IntTy) -> IntTy {
+    match ty {
+        IntTy::Enum(enu) => lower_int(&enu.base),
+        IntTy::Array(element, ub, lb) => IntTy::Array(Box::new(lower_int(element)), *ub, *lb),
+        _ => ty.clone(),
+    }
+}
+
+/// Replace all enums within a type with their base types.
+fn lower_ty(ty: &Ty) -> Ty {
+    match ty {
+        Ty::Int(subty) => Ty::Int(lower_int(subty)),
+        Ty::Array(element, ub, lb) => Ty::Array(Box::new(lower_ty(element)), *ub, *lb),
+        _ => ty.clone(),
+    }
+}
+
+fn lower_val(val: &Val) -> Val {
+    match val {
+        Val::Type(ty) => Val::Type(lower_ty(ty)),
+        _ => val.clone(),
+    }
+}
+
+/// Build the localparam for an enum element.
+fn localparam(enu: &Enum, index: usize, name: Ident) -> HierItem {
+    HierItem::Param(Rc::new(hier::ParamDecl {
+        kw: Keyword::Localparam,
+        name,
+        ty: Ty::Int(lower_int(&enu.base)),
+        init: Val::Int(enu.elements.borrow()[index].1.clone()),
+    }))
+}
+
+impl EnumEliminator {
+    pub fn visit(&mut self, source: &mut hier::Source) {
+        for pkg in source.pkgs.values() {
+            for item in &pkg.scope.items {
+                if let HierItem::Enum(enu, _) = item {
+                    self.origins.insert(enum_key(enu), pkg.name.value.clone());
+                }
+            }
+        }
+
+        for pkg in source.pkgs.values_mut() {
+            self.xfrm_unit(&mut pkg.scope);
+        }
+        for unit in &mut source.units {
+            for item in &mut unit.items {
+                if let HierItem::Design(decl) = item {
+                    for (_, inst) in decl.instances.borrow_mut().iter_mut() {
+                        self.xfrm_unit(&mut Rc::get_mut(inst).unwrap().scope);
+                    }
+                }
+            }
+            self.xfrm_unit(unit);
+        }
+
+        // All references to enums are gone now.
+        source.enums.clear();
+    }
+
+    /// Find the localparam of an enum element, creating one in the design unit if the enum is
+    /// not declared in any enclosing scope.
+    fn element_name(&mut self, enu: &Rc<Enum>, index: usize) -> Ident {
+        let key = (enum_key(enu), index);
+        for scope in self.scopes.iter().rev() {
+            if let Some(name) = scope.get(&key) {
+                return name.clone();
+            }
+        }
+        let element = enu.elements.borrow()[index].0.clone();
+        let name = match self.origins.get(&key.0) {
+            Some(pkg) => format!("{}_{}", pkg, element),
+            None => element.value.clone(),
+        };
+        let name = fresh_name(&mut self.taken, &name, element.span);
+        self.scopes[0].insert(key, name.clone());
+        self.foreign.push(localparam(enu, index, name.clone()));
+        name
+    }
+
+    /// Lower a design unit, package or compilation unit.
+    fn xfrm_unit(&mut self, scope: &mut HierScope) {
+        self.taken.clear();
+        super::common::collect_names(scope, &mut self.taken);
+        self.foreign.clear();
+        self.xfrm_scope(scope);
+
+        let foreign = mem::take(&mut self.foreign);
+        super::common::insert_decls(scope, foreign);
+    }
+
+    fn xfrm_scope(&mut self, scope: &mut HierScope) {
+        // Elements declared in this scope keep their names unless they clash.
+        let mut map = HashMap::new();
+        for item in &scope.items {
+            if let HierItem::Enum(enu, index) = item {
+                let element = enu.elements.borrow()[*index].0.clone();
+                let name = fresh_name(&mut self.taken, &element.value, element.span);
+                map.insert((enum_key(enu), *index), name);
+            }
+        }
+        self.scopes.push(map);
+
+        for (pos, item) in scope.items.iter_mut().enumerate() {
+            if let HierItem::Enum(enu, index) = item {
+                let name = self.scopes.last().unwrap()[&(enum_key(enu), *index)].clone();
+                scope.names.insert(name.value.clone(), pos);
+                *item = localparam(enu, *index, name);
+                continue;
+            }
+            self.visit_item(item);
+        }
+
+        self.scopes.pop();
+    }
+
+    fn visit_item(&mut self, item: &mut HierItem) {
+        match item {
+            HierItem::Param(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+                decl.init = lower_val(&decl.init);
+            }
+            HierItem::Type(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+            }
+            HierItem::DataPort(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+            }
+            HierItem::DataDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+                if let Some(expr) = &mut decl.init { self.visit_expr(expr); }
+            }
+            HierItem::NetDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+                if let Some(expr) = &mut decl.init { self.visit_expr(expr); }
+            }
+            HierItem::FuncDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+                self.lower_subroutine(&mut decl.ports, &mut decl.body);
+            }
+            HierItem::TaskDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                self.lower_subroutine(&mut decl.ports, &mut decl.body);
+            }
+            HierItem::ContinuousAssign(expr) => {
+                self.visit_expr(Rc::get_mut(expr).unwrap());
+            }
+            HierItem::Always(_, stmt) => {
+                self.visit_stmt(Rc::get_mut(stmt).unwrap());
+            }
+            HierItem::Instance(decl) => {
+                for expr in Rc::get_mut(decl).unwrap().port.iter_mut().flatten() {
+                    self.visit_expr(expr);
+                }
+            }
+            HierItem::GenBlock(genblk) => {
+                self.xfrm_scope(&mut Rc::get_mut(genblk).unwrap().scope);
+            }
+            HierItem::LoopGenBlock(loopgenblk) => {
+                for (_, genblk) in loopgenblk.instances.borrow_mut().iter_mut() {
+                    self.xfrm_scope(&mut Rc::get_mut(genblk).unwrap().scope);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Lower types and expressions within the signature and body of a function or task.
+    fn lower_subroutine(&mut self, ports: &mut [hier::TfPortDecl], body: &mut [expr::Stmt]) {
+        for port in ports {
+            port.ty = lower_ty(&port.ty);
+            if let Some(expr) = &mut port.init { self.visit_expr(expr); }
+        }
+        body.iter_mut().for_each(|stmt| self.visit_stmt(stmt));
+    }
+}
+
+impl EhtVisitor for EnumEliminator {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        // Constants of enum type that match an element are references to that element.
+        let element = match (&expr.value, &expr.ty) {
+            (expr::ExprKind::Const(Val::Int(val)), Ty::Int(IntTy::Enum(enu))) => {
+                enu.elements.borrow().iter().position(|(_, v)| v == val).map(|index| (enu.clone(), index))
+            }
+            _ => None,
+        };
+        match element {
+            Some((enu, index)) => {
+                let name = self.element_name(&enu, index);
+                expr.value = expr::ExprKind::HierName(ast::HierId::Name(None, Box::new(name)));
+            }
+            None => self.do_visit_expr(expr),
+        }
+        match expr.value {
+            expr::ExprKind::AssignPattern(ref mut ty, _) |
+            expr::ExprKind::TypeCast(ref mut ty, _) => **ty = lower_ty(ty),
+            _ => (),
+        }
+        expr.ty = lower_ty(&expr.ty);
+    }
+
+    fn visit_stmt(&mut self, stmt: &mut expr::Stmt) {
+        match stmt.value {
+            expr::StmtKind::For { ty: Some(ref mut ty), .. } => **ty = lower_ty(ty),
+            expr::StmtKind::DataDecl(ref mut decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+            }
+            _ => (),
+        }
+        self.do_visit_stmt(stmt);
+    }
+}
@@ -207,6 +207,13 @@ impl GenBlkEliminator {
                 HierItem::Always(_, stmt) => {
                     self.visit_stmt(Rc::get_mut(stmt).unwrap());
                 }
+                HierItem::FuncDecl(_) | HierItem::TaskDecl(_) => {
+                    // The subroutine is placed into the scope first, so that references to
+                    // itself can be resolved.
+                    self.scopes.last_mut().unwrap().items.push(item);
+                    self.xfrm_subroutine();
+                    continue;
+                }
                 HierItem::GenBlock(genblk) => {
                     ::util::replace_with(&mut Rc::get_mut(genblk).unwrap().scope, |scope| self.xfrm_scope(scope));
                 }
@@ -218,6 +225,51 @@ impl GenBlkEliminator {
         self.scopes.pop().unwrap()
     }
 
+    /// Transform expressions within the function or task last placed into the current scope,
+    /// with its ports in scope.
+    fn xfrm_subroutine(&mut self) {
+        let (mut ports, mut body) = match self.scopes.last_mut().unwrap().items.last_mut().unwrap() {
+            HierItem::FuncDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                (std::mem::take(&mut decl.ports), std::mem::take(&mut decl.body))
+            }
+            HierItem::TaskDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                (std::mem::take(&mut decl.ports), std::mem::take(&mut decl.body))
+            }
+            _ => unreachable!(),
+        };
+
+        for port in &mut ports {
+            if let Some(expr) = &mut port.init { self.visit_expr(expr); }
+        }
+        self.scopes.push(HierScope::new());
+        for port in &ports {
+            self.scopes.last_mut().unwrap().insert(Some(Ident::clone(&port.name)), HierItem::DataDecl(Rc::new(hier::DataDecl {
+                lifetime: ast::Lifetime::Automatic,
+                ty: port.ty.clone(),
+                name: Ident::clone(&port.name),
+                init: None,
+            })));
+        }
+        body.iter_mut().for_each(|stmt| self.visit_stmt(stmt));
+        self.scopes.pop();
+
+        match self.scopes.last_mut().unwrap().items.last_mut().unwrap() {
+            HierItem::FuncDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ports = ports;
+                decl.body = body;
+            }
+            HierItem::TaskDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ports = ports;
+                decl.body = body;
+            }
+            _ => unreachable!(),
+        }
+    }
+
     /// Third stage of gen blk elimination: expand gen blkerate constructs.
     pub fn expand_genblk(&mut self, scope: HierScope) -> HierScope {
         self.scopes.push(HierScope::new());
@@ -283,6 +335,16 @@ impl EhtVisitor for GenBlkEliminator {
                 self.scopes.pop();
                 return;
             },
+            expr::StmtKind::Foreach { array, vars, body } => {
+                self.visit_expr(array);
+                self.scopes.push(HierScope::new());
+                for (decl, _, _) in vars.iter().flatten() {
+                    self.scopes.last_mut().unwrap().insert(Some(Ident::clone(&decl.name)), HierItem::DataDecl(Rc::clone(decl)));
+                }
+                self.visit_stmt(body);
+                self.scopes.pop();
+                return;
+            }
             expr::StmtKind::SeqBlock(list) => {
                 self.scopes.push(HierScope::new());
                 list.iter_mut().for_each(|stmt| self.visit_stmt(stmt));
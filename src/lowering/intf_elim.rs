@@ -0,0 +1,509 @@
+//! Flatten interfaces into plain ports and nets. Each interface port is replaced by one data port
+//! for each signal visible through its modport, or inout ports for all signals if no modport is
+//! given. Each interface instance is replaced by a group of nets in the instantiating module, and
+//! references like `intf.sig` are renamed to `intf_sig`.
+//!
+//! This pass must be run after inst_array_elim and gen_blk_elim, as it expects all interface
+//! references to be simple names.
+
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+
+use source::DiagMgr;
+use syntax::tokens::Keyword;
+use syntax::ast::{self, Ident, PortDir, NetPortType, NetTy};
+use elaborate::ty::Ty;
+use elaborate::expr;
+use elaborate::hier::{self, HierScope, HierItem, DesignInstHandle, DesignInstantiation};
+use elaborate::eht_visit::EhtVisitor;
+use syntax::ast_visit::AstVisitor;
+
+pub fn intf_elim(diag: &DiagMgr, source: &mut hier::Source) {
+    let mut elim = IntfEliminator {
+        diag,
+        ports: HashMap::new(),
+        intfs: HashMap::new(),
+    };
+    elim.visit(source);
+}
+
+/// A signal of an interface, as seen from an interface port or an interface instance.
+#[derive(Clone)]
+struct Signal {
+    name: Ident,
+    dir: PortDir,
+    /// Net type of the signal within the interface, or None if it is a variable.
+    net: Option<NetTy>,
+    ty: Ty,
+}
+
+struct IntfEliminator<'a> {
+    diag: &'a DiagMgr,
+
+    /// Mapping from design instantiations to signals carried by each of their ports. Data ports
+    /// are mapped to None.
+    ports: HashMap<usize, Vec<Option<Vec<Signal>>>>,
+
+    /// Interface ports and interface instances visible in the current module.
+    intfs: HashMap<String, Vec<Signal>>,
+}
+
+fn is_intf(inst: &DesignInstHandle) -> bool {
+    inst.0.ast.kw == Keyword::Interface
+}
+
+fn inst_key(inst: &DesignInstantiation) -> usize {
+    inst as *const _ as usize
+}
+
+/// Retrieve name, net type and data type of an item that can be accessed as an interface signal.
+fn signal_of(item: &HierItem) -> Option<(&Ident, Option<NetTy>, Ty)> {
+    match item {
+        HierItem::DataPort(decl) => {
+            // Ports of interfaces will become nets in the instantiating module.
+            let net = match decl.net {
+                NetPortType::Builtin(net) => net,
+                _ => NetTy::Wire,
+            };
+            Some((&decl.name, Some(net), decl.ty.clone()))
+        }
+        HierItem::DataDecl(decl) => Some((&decl.name, None, decl.ty.clone())),
+        HierItem::NetDecl(decl) => Some((&decl.name, Some(decl.net), decl.ty.clone())),
+        _ => None,
+    }
+}
+
+/// Net type to use for a port that carries the given signal.
+fn port_net_type(signal: &Signal) -> NetPortType {
+    match (signal.dir, signal.net) {
+        (PortDir::Output, None) => NetPortType::Variable,
+        (_, Some(net)) => NetPortType::Builtin(net),
+        (_, None) => NetPortType::Builtin(NetTy::Wire),
+    }
+}
+
+fn name_expr(name: Ident, ty: Ty) -> expr::Expr {
+    let span = name.span;
+    expr::Expr {
+        value: expr::ExprKind::HierName(ast::HierId::Name(None, Box::new(name))),
+        span,
+        ty,
+    }
+}
+
+impl<'a> IntfEliminator<'a> {
+    pub fn visit(&mut self, source: &mut hier::Source) {
+        // We need to know the shape of ports of all instantiations before transforming any of
+        // them, as connections of an instance depend on the ports of the instantiated design.
+        for unit in &source.units {
+            for item in &unit.items {
+                if let HierItem::Design(decl) = item {
+                    for (_, inst) in decl.instances.borrow().iter() {
+                        let ports = self.gather_ports(inst);
+                        self.ports.insert(inst_key(inst), ports);
+                    }
+                }
+            }
+        }
+
+        for unit in &mut source.units {
+            for item in &mut unit.items {
+                if let HierItem::Design(decl) = item {
+                    if decl.ast.kw == Keyword::Interface {
+                        continue;
+                    }
+                    for (_, inst) in decl.instances.borrow_mut().iter_mut() {
+                        let inst = Rc::get_mut(inst).unwrap();
+                        let key = inst_key(inst);
+                        ::util::replace_with(&mut inst.scope, |scope| self.xfrm_scope(key, scope));
+                    }
+                }
+            }
+        }
+
+        // Interfaces are no longer referenced by anything, so remove them from the output.
+        for unit in &mut source.units {
+            for item in &mut unit.items {
+                if let HierItem::Design(decl) = item {
+                    if decl.ast.kw == Keyword::Interface {
+                        decl.instances.borrow_mut().clear();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Collect signals of an interface instance, as seen through the modport if given.
+    fn intf_signals(&self, inst: &DesignInstHandle, modport: Option<&hier::Modport>) -> Vec<Signal> {
+        let instance = inst.get_instance();
+        match modport {
+            None => instance.scope.items.iter().filter_map(|item| {
+                let (name, net, ty) = signal_of(item)?;
+                Some(Signal { name: name.clone(), dir: PortDir::Inout, net, ty })
+            }).collect(),
+            Some(modport) => modport.scope.items.iter().filter_map(|item| {
+                let port = if let HierItem::DataPort(port) = item { port } else { unreachable!() };
+                match instance.scope.find(&port.name).and_then(signal_of) {
+                    None => {
+                        self.diag.report_error(
+                            format!("cannot find signal {} in interface {}", port.name, instance.name),
+                            port.name.span
+                        );
+                        None
+                    }
+                    Some((_, net, ty)) => Some(Signal { name: port.name.clone(), dir: port.dir, net, ty }),
+                }
+            }).collect(),
+        }
+    }
+
+    /// First stage of interface elimination: find out signals carried by each port.
+    fn gather_ports(&self, inst: &DesignInstantiation) -> Vec<Option<Vec<Signal>>> {
+        inst.scope.items.iter().filter_map(|item| {
+            match item {
+                HierItem::DataPort(_) => Some(None),
+                HierItem::InterfacePort(decl) => {
+                    Some(Some(self.intf_signals(&decl.inst, decl.modport.as_ref().map(|x| &**x))))
+                }
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// Insert a newly created item into the scope, checking that it doesn't clash with existing
+    /// names.
+    fn insert(&self, scope: &mut HierScope, names: &HashMap<String, usize>, ident: Ident, item: HierItem) {
+        if names.contains_key(&ident.value) || scope.names.contains_key(&ident.value) {
+            self.diag.report_error(
+                format!("flattened interface signal {} clashes with an existing name", ident),
+                ident.span
+            );
+        }
+        scope.insert(Some(ident), item);
+    }
+
+    /// Second stage of interface elimination: flatten interface ports and instances, and rewrite
+    /// all references to them.
+    fn xfrm_scope(&mut self, key: usize, scope: HierScope) -> HierScope {
+        let HierScope { items, names, .. } = scope;
+
+        // Find out all interfaces visible in this scope first, as they can be referenced before
+        // their declaration.
+        self.intfs.clear();
+        let mut port_index = 0;
+        for item in &items {
+            match item {
+                HierItem::DataPort(_) => port_index += 1,
+                HierItem::InterfacePort(decl) => {
+                    let signals = self.ports[&key][port_index].clone().unwrap();
+                    self.intfs.insert(decl.name.value.clone(), signals);
+                    port_index += 1;
+                }
+                HierItem::Instance(decl) if is_intf(&decl.inst) => {
+                    let signals = self.intf_signals(&decl.inst, None);
+                    self.intfs.insert(decl.name.value.clone(), signals);
+                }
+                _ => (),
+            }
+        }
+
+        let mut new_scope = HierScope::new();
+        for mut item in items {
+            match item {
+                HierItem::InterfacePort(ref decl) => {
+                    for signal in &self.intfs[&decl.name.value] {
+                        let name = Ident::new(format!("{}_{}", decl.name, signal.name), decl.name.span);
+                        let port = HierItem::DataPort(Rc::new(hier::DataPortDecl {
+                            dir: signal.dir,
+                            net: port_net_type(signal),
+                            name: name.clone(),
+                            ty: signal.ty.clone(),
+                            init: None,
+                        }));
+                        self.insert(&mut new_scope, &names, name, port);
+                    }
+                    continue;
+                }
+                HierItem::Instance(ref decl) if is_intf(&decl.inst) => {
+                    self.flatten_instance(decl, &mut new_scope, &names);
+                    continue;
+                }
+                HierItem::Instance(ref mut decl) => {
+                    let shapes = self.ports[&inst_key(&decl.inst.get_instance())].clone();
+                    ::util::replace_with(&mut Rc::get_mut(decl).unwrap().port, |ports| self.xfrm_ports(ports, &shapes));
+                }
+                HierItem::ContinuousAssign(ref mut expr) => {
+                    self.visit_expr(Rc::get_mut(expr).unwrap());
+                }
+                HierItem::Always(_, ref mut stmt) => {
+                    self.visit_stmt(Rc::get_mut(stmt).unwrap());
+                }
+                HierItem::FuncDecl(ref mut decl) => {
+                    let decl = Rc::get_mut(decl).unwrap();
+                    for port in &mut decl.ports {
+                        if let Some(expr) = &mut port.init { self.visit_expr(expr); }
+                    }
+                    decl.body.iter_mut().for_each(|stmt| self.visit_stmt(stmt));
+                }
+                HierItem::TaskDecl(ref mut decl) => {
+                    let decl = Rc::get_mut(decl).unwrap();
+                    for port in &mut decl.ports {
+                        if let Some(expr) = &mut port.init { self.visit_expr(expr); }
+                    }
+                    decl.body.iter_mut().for_each(|stmt| self.visit_stmt(stmt));
+                }
+                HierItem::DataDecl(ref mut decl) => {
+                    if let Some(expr) = &mut Rc::get_mut(decl).unwrap().init { self.visit_expr(expr); }
+                }
+                HierItem::NetDecl(ref mut decl) => {
+                    if let Some(expr) = &mut Rc::get_mut(decl).unwrap().init { self.visit_expr(expr); }
+                }
+                _ => (),
+            }
+            let ident = super::common::name_of(&item).map(Ident::clone);
+            new_scope.insert(ident, item);
+        }
+        new_scope
+    }
+
+    /// Expand connections to interface ports into connections to each individual signal.
+    fn xfrm_ports(
+        &mut self, ports: Vec<Option<expr::Expr>>, shapes: &[Option<Vec<Signal>>]
+    ) -> Vec<Option<expr::Expr>> {
+        let mut new_list = Vec::new();
+        for (port, shape) in ports.into_iter().zip(shapes) {
+            let signals = match shape {
+                None => {
+                    new_list.push(port.map(|mut port| {
+                        self.visit_expr(&mut port);
+                        port
+                    }));
+                    continue;
+                }
+                Some(signals) => signals,
+            };
+            // Elaborator has already checked that interface ports are connected.
+            let port = port.unwrap();
+            let intf = match port.value {
+                expr::ExprKind::HierName(ast::HierId::Name(None, ref name)) => name,
+                _ => {
+                    self.diag.report_error("this interface connection cannot be flattened", port.span);
+                    continue;
+                }
+            };
+            let visible = match self.intfs.get(&intf.value) {
+                Some(v) => v,
+                None => {
+                    self.diag.report_error("this interface connection cannot be flattened", port.span);
+                    continue;
+                }
+            };
+            for signal in signals {
+                if !visible.iter().any(|v| v.name.value == signal.name.value) {
+                    self.diag.report_error(
+                        format!("signal {} is not accessible through {}", signal.name, intf),
+                        port.span
+                    );
+                }
+                let name = Ident::new(format!("{}_{}", intf, signal.name), port.span);
+                new_list.push(Some(name_expr(name, signal.ty.clone())));
+            }
+        }
+        new_list
+    }
+
+    /// Replace an interface instance with nets for each of its signals. Continuous assignments
+    /// and always blocks within the interface are moved into the current module.
+    fn flatten_instance(
+        &mut self, decl: &hier::InstanceDecl, scope: &mut HierScope, names: &HashMap<String, usize>
+    ) {
+        let instance = decl.inst.get_instance();
+        let signals: HashSet<String> = instance.scope.items.iter()
+            .filter_map(|item| signal_of(item).map(|(name, ..)| name.value.clone()))
+            .collect();
+        let prefix = decl.name.value.clone();
+        let mut renamer = SignalRenamer {
+            prefix: &prefix,
+            names: &signals,
+        };
+
+        let mut port_index = 0;
+        for item in &instance.scope.items {
+            match item {
+                HierItem::DataPort(port) => {
+                    let name = Ident::new(format!("{}_{}", prefix, port.name), decl.name.span);
+                    let mut conn = decl.port.get(port_index).and_then(Clone::clone);
+                    port_index += 1;
+                    if let Some(conn) = &mut conn { self.visit_expr(conn); }
+                    let net = match port.net {
+                        NetPortType::Builtin(net) => net,
+                        _ => NetTy::Wire,
+                    };
+                    let init = match port.dir {
+                        PortDir::Input => conn.map(Box::new),
+                        PortDir::Output => {
+                            if let Some(conn) = conn {
+                                let rhs = name_expr(name.clone(), port.ty.clone());
+                                let span = conn.span;
+                                scope.insert(None, HierItem::ContinuousAssign(Rc::new(expr::Expr {
+                                    value: expr::ExprKind::Assign(Box::new(conn), Box::new(rhs)),
+                                    span,
+                                    ty: Ty::Void,
+                                })));
+                            }
+                            None
+                        }
+                        _ => {
+                            self.diag.report_error(
+                                "only input and output ports of interfaces can be flattened",
+                                port.name.span
+                            );
+                            None
+                        }
+                    };
+                    let item = HierItem::NetDecl(Rc::new(hier::NetDecl {
+                        net,
+                        ty: port.ty.clone(),
+                        name: name.clone(),
+                        init,
+                    }));
+                    self.insert(scope, names, name, item);
+                }
+                HierItem::DataDecl(data) => {
+                    let name = Ident::new(format!("{}_{}", prefix, data.name), decl.name.span);
+                    let mut init = data.init.clone();
+                    if let Some(init) = &mut init { renamer.visit_expr(init); }
+                    let item = HierItem::DataDecl(Rc::new(hier::DataDecl {
+                        lifetime: data.lifetime,
+                        ty: data.ty.clone(),
+                        name: name.clone(),
+                        init,
+                    }));
+                    self.insert(scope, names, name, item);
+                }
+                HierItem::NetDecl(net) => {
+                    let name = Ident::new(format!("{}_{}", prefix, net.name), decl.name.span);
+                    let mut init = net.init.clone();
+                    if let Some(init) = &mut init { renamer.visit_expr(init); }
+                    let item = HierItem::NetDecl(Rc::new(hier::NetDecl {
+                        net: net.net,
+                        ty: net.ty.clone(),
+                        name: name.clone(),
+                        init,
+                    }));
+                    self.insert(scope, names, name, item);
+                }
+                HierItem::ContinuousAssign(expr) => {
+                    let mut expr = expr::Expr::clone(expr);
+                    renamer.visit_expr(&mut expr);
+                    scope.insert(None, HierItem::ContinuousAssign(Rc::new(expr)));
+                }
+                HierItem::Always(kw, stmt) => {
+                    let mut stmt = expr::Stmt::clone(stmt);
+                    renamer.visit_stmt(&mut stmt);
+                    scope.insert(None, HierItem::Always(*kw, Rc::new(stmt)));
+                }
+                // Parameters, types and enums are already folded into their uses, and modports
+                // are no longer meaningful after flattening.
+                HierItem::Param(_) |
+                HierItem::Type(_) |
+                HierItem::Enum(..) |
+                HierItem::Modport(_) => (),
+                _ => {
+                    self.diag.report_error(
+                        format!("interface {} contains items that cannot be flattened", instance.name),
+                        decl.name.span
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl<'a> IntfEliminator<'a> {
+    /// Rename a reference like `intf.sig` to the flattened signal. Returns false if the reference
+    /// is not through an interface.
+    fn flatten_ref(&self, id: &mut ast::HierId) -> bool {
+        let new_name = match id {
+            ast::HierId::Member(parent, name) => match parent.value {
+                ast::HierId::Name(None, ref intf) => {
+                    let signals = match self.intfs.get(&intf.value) {
+                        Some(v) => v,
+                        None => return false,
+                    };
+                    // Only signals visible through the modport are flattened into ports.
+                    if !signals.iter().any(|signal| signal.name.value == name.value) {
+                        self.diag.report_error(
+                            format!("signal {} is not accessible through {}", name, intf),
+                            parent.span.merge(name.span)
+                        );
+                        return true;
+                    }
+                    Ident::new(format!("{}_{}", intf, name), parent.span)
+                }
+                _ => return false,
+            }
+            _ => return false,
+        };
+        *id = ast::HierId::Name(None, Box::new(new_name));
+        true
+    }
+}
+
+impl<'a> EhtVisitor for IntfEliminator<'a> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        match expr.value {
+            expr::ExprKind::HierName(ref mut id) => { self.flatten_ref(id); }
+            _ => self.do_visit_expr(expr),
+        }
+    }
+
+    fn visit_timing_ctrl(&mut self, ctrl: &mut ast::TimingCtrl) {
+        EventRenamer(|id: &mut ast::HierId| self.flatten_ref(id)).visit_timing_ctrl(ctrl);
+    }
+}
+
+/// Rename references to interface signals when moving logic out of an interface.
+struct SignalRenamer<'a> {
+    prefix: &'a str,
+    names: &'a HashSet<String>,
+}
+
+impl<'a> SignalRenamer<'a> {
+    /// Prefix a reference to a signal of the interface. Returns false if the reference is not to
+    /// a signal.
+    fn rename(&self, id: &mut ast::HierId) -> bool {
+        match id {
+            ast::HierId::Name(None, name) if self.names.contains(&name.value) => {
+                name.value = format!("{}_{}", self.prefix, name.value);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'a> EhtVisitor for SignalRenamer<'a> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        match expr.value {
+            expr::ExprKind::HierName(ref mut id @ ast::HierId::Name(None, _)) => { self.rename(id); }
+            _ => self.do_visit_expr(expr),
+        }
+    }
+
+    fn visit_timing_ctrl(&mut self, ctrl: &mut ast::TimingCtrl) {
+        EventRenamer(|id: &mut ast::HierId| self.rename(id)).visit_timing_ctrl(ctrl);
+    }
+}
+
+/// Apply a renaming of references to timing controls, which are kept as AST. The renaming
+/// returns false for references it does not apply to.
+struct EventRenamer<F>(F);
+
+impl<F: FnMut(&mut ast::HierId) -> bool> AstVisitor for EventRenamer<F> {
+    fn visit_hier_name(&mut self, id: &mut ast::HierId) {
+        if !(self.0)(id) {
+            self.do_visit_hier_name(id);
+        }
+    }
+}
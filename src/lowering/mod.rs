@@ -4,6 +4,10 @@ mod loop_gen_elim;
 mod inst_array_elim;
 mod gen_blk_elim;
 mod type_param_elim;
+mod intf_elim;
+mod struct_elim;
+mod enum_elim;
+mod pkg_inline;
 mod prefix;
 
 pub use self::gen_name_assign::gen_name_assign;
@@ -11,4 +15,8 @@ pub use self::loop_gen_elim::loop_gen_elim;
 pub use self::inst_array_elim::inst_array_elim;
 pub use self::gen_blk_elim::gen_blk_elim;
 pub use self::type_param_elim::type_param_elim;
+pub use self::intf_elim::intf_elim;
+pub use self::struct_elim::struct_elim;
+pub use self::enum_elim::enum_elim;
+pub use self::pkg_inline::pkg_inline;
 pub use self::prefix::prefix;
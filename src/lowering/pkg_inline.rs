@@ -0,0 +1,364 @@
+//! Copy package items into design units that use them, so packages are no longer needed. Each
+//! parameter, typedef and function referenced from a design unit, directly or through other
+//! package items, becomes a localparam, typedef or function of that design unit. References are
+//! renamed to the local copies and all packages are removed afterwards.
+//!
+//! Parameters and types are already folded into their uses during elaboration, so references
+//! only remain in calls of functions and tasks, and within items that are not elaborated.
+
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+
+use source::DiagMgr;
+use syntax::tokens::Keyword;
+use syntax::ast::{self, Ident, HierId, Scope, DataTypeKind};
+use syntax::ast_visit::AstVisitor;
+use elaborate::ty::Ty;
+use elaborate::expr::{self, Val};
+use elaborate::hier::{self, HierItem, HierScope, PkgDecl};
+use elaborate::eht_visit::EhtVisitor;
+
+use super::common::fresh_name;
+
+pub fn pkg_inline(diag: &DiagMgr, source: &mut hier::Source) {
+    let hier::Source { units, pkgs, .. } = source;
+    {
+        let mut inliner = PkgInliner {
+            diag,
+            pkgs,
+            taken: HashSet::new(),
+            map: HashMap::new(),
+            copies: Vec::new(),
+        };
+        for unit in units.iter_mut() {
+            for item in &mut unit.items {
+                if let HierItem::Design(decl) = item {
+                    for (_, inst) in decl.instances.borrow_mut().iter_mut() {
+                        inliner.xfrm_unit(&mut Rc::get_mut(inst).unwrap().scope);
+                    }
+                }
+            }
+            inliner.xfrm_unit(unit);
+        }
+    }
+    pkgs.clear();
+}
+
+struct PkgInliner<'a> {
+    diag: &'a DiagMgr,
+    pkgs: &'a HashMap<String, PkgDecl>,
+
+    /// All names used in the current design unit.
+    taken: HashSet<String>,
+
+    /// Mapping from package items to names of their copies in the current design unit.
+    map: HashMap<(String, String), Ident>,
+
+    /// Copies of package items for current design unit, in dependency order.
+    copies: Vec<HierItem>,
+}
+
+/// Collect names declared within a function or task, which hide package items.
+fn subroutine_locals(ports: &[hier::TfPortDecl], body: &[expr::Stmt]) -> HashSet<String> {
+    fn visit_stmt(stmt: &expr::Stmt, locals: &mut HashSet<String>) {
+        match &stmt.value {
+            expr::StmtKind::DataDecl(decl) => { locals.insert(decl.name.value.clone()); }
+            expr::StmtKind::SeqBlock(list) => for stmt in list { visit_stmt(stmt, locals) },
+            expr::StmtKind::TimingCtrl(_, stmt) => visit_stmt(stmt, locals),
+            expr::StmtKind::If { success, failure, .. } => {
+                visit_stmt(success, locals);
+                if let Some(failure) = failure { visit_stmt(failure, locals); }
+            }
+            expr::StmtKind::Case { items, .. } => for (_, stmt) in items { visit_stmt(stmt, locals) },
+            expr::StmtKind::For { init, body, ty, .. } => {
+                if ty.is_some() {
+                    for expr in init {
+                        if let expr::ExprKind::Assign(lhs, _) = &expr.value {
+                            if let expr::ExprKind::HierName(HierId::Name(None, name)) = &lhs.value {
+                                locals.insert(name.value.clone());
+                            }
+                        }
+                    }
+                }
+                visit_stmt(body, locals);
+            }
+            expr::StmtKind::While(_, body) |
+            expr::StmtKind::DoWhile(body, _) |
+            expr::StmtKind::Repeat(_, body) |
+            expr::StmtKind::Forever(body) => visit_stmt(body, locals),
+            expr::StmtKind::Foreach { vars, body, .. } => {
+                for (decl, _, _) in vars.iter().flatten() { locals.insert(decl.name.value.clone()); }
+                visit_stmt(body, locals);
+            }
+            _ => (),
+        }
+    }
+
+    let mut locals: HashSet<String> = ports.iter().map(|port| port.name.value.clone()).collect();
+    for stmt in body {
+        visit_stmt(stmt, &mut locals);
+    }
+    locals
+}
+
+impl<'a> PkgInliner<'a> {
+    /// Get the local name of a package item, copying it into the current design unit if this is
+    /// the first use.
+    fn require(&mut self, pkg: &Ident, name: &Ident) -> Ident {
+        let key = (pkg.value.clone(), name.value.clone());
+        if let Some(local) = self.map.get(&key) {
+            return local.clone();
+        }
+        let item = match self.pkgs.get(&pkg.value).and_then(|decl| decl.scope.find(name)) {
+            Some(item) => item.clone(),
+            None => {
+                self.diag.report_error(format!("cannot find {} in package {}", name, pkg), name.span);
+                return name.clone();
+            }
+        };
+        let local = fresh_name(&mut self.taken, &name.value, name.span);
+        // Insert into map first, so recursive functions do not copy themselves again.
+        self.map.insert(key, local.clone());
+
+        let copy = match item {
+            HierItem::Param(decl) => HierItem::Param(Rc::new(hier::ParamDecl {
+                kw: Keyword::Localparam,
+                name: local.clone(),
+                ty: decl.ty.clone(),
+                init: decl.init.clone(),
+            })),
+            HierItem::Type(decl) => HierItem::Type(Rc::new(hier::TypedefDecl {
+                ty: decl.ty.clone(),
+                name: local.clone(),
+            })),
+            HierItem::Enum(enu, index) => HierItem::Param(Rc::new(hier::ParamDecl {
+                kw: Keyword::Localparam,
+                name: local.clone(),
+                ty: Ty::Int(hier::IntTy::Enum(enu.clone())),
+                init: Val::Int(enu.elements.borrow()[index].1.clone()),
+            })),
+            HierItem::FuncDecl(decl) => {
+                let mut ports = decl.ports.clone();
+                let mut body = decl.body.clone();
+                self.rename_subroutine(pkg, &mut ports, &mut body);
+                HierItem::FuncDecl(Rc::new(hier::FuncDecl {
+                    lifetime: decl.lifetime,
+                    ty: decl.ty.clone(),
+                    name: local.clone(),
+                    ports,
+                    body,
+                }))
+            }
+            HierItem::TaskDecl(decl) => {
+                let mut ports = decl.ports.clone();
+                let mut body = decl.body.clone();
+                self.rename_subroutine(pkg, &mut ports, &mut body);
+                HierItem::TaskDecl(Rc::new(hier::TaskDecl {
+                    lifetime: decl.lifetime,
+                    name: local.clone(),
+                    ports,
+                    body,
+                }))
+            }
+            _ => {
+                self.diag.report_error(
+                    format!("{} in package {} cannot be copied into design units", name, pkg),
+                    name.span
+                );
+                return local;
+            }
+        };
+        self.copies.push(copy);
+        local
+    }
+
+    /// Rename package references within a copy of a function or task declared in a package.
+    /// Unqualified references to items of that package are renamed as well.
+    fn rename_subroutine(&mut self, pkg: &Ident, ports: &mut [hier::TfPortDecl], body: &mut [expr::Stmt]) {
+        let locals = subroutine_locals(ports, body);
+        let mut renamer = SubroutineRenamer {
+            inliner: self,
+            pkg,
+            locals,
+        };
+        for port in ports {
+            if let Some(init) = &mut port.init { renamer.visit_expr(init); }
+        }
+        for stmt in body {
+            renamer.visit_stmt(stmt);
+        }
+    }
+
+    /// Copy package items used by a design unit or compilation unit into it.
+    fn xfrm_unit(&mut self, scope: &mut HierScope) {
+        self.taken.clear();
+        super::common::collect_names(scope, &mut self.taken);
+        self.map.clear();
+        self.copies.clear();
+        self.xfrm_scope(scope);
+        let copies = ::std::mem::take(&mut self.copies);
+        super::common::insert_decls(scope, copies);
+    }
+
+    fn xfrm_scope(&mut self, scope: &mut HierScope) {
+        for item in &mut scope.items {
+            match item {
+                HierItem::DataPort(decl) => {
+                    if let Some(expr) = &mut Rc::get_mut(decl).unwrap().init {
+                        self.rename_ast_expr(expr);
+                    }
+                }
+                HierItem::DataDecl(decl) => {
+                    if let Some(expr) = &mut Rc::get_mut(decl).unwrap().init { self.visit_expr(expr); }
+                }
+                HierItem::NetDecl(decl) => {
+                    if let Some(expr) = &mut Rc::get_mut(decl).unwrap().init { self.visit_expr(expr); }
+                }
+                HierItem::FuncDecl(decl) => {
+                    let decl = Rc::get_mut(decl).unwrap();
+                    for stmt in &mut decl.body { self.visit_stmt(stmt); }
+                }
+                HierItem::TaskDecl(decl) => {
+                    let decl = Rc::get_mut(decl).unwrap();
+                    for stmt in &mut decl.body { self.visit_stmt(stmt); }
+                }
+                HierItem::ContinuousAssign(expr) => {
+                    self.visit_expr(Rc::get_mut(expr).unwrap());
+                }
+                HierItem::Always(_, stmt) => {
+                    self.visit_stmt(Rc::get_mut(stmt).unwrap());
+                }
+                HierItem::Other(item) => {
+                    let mut renamer = RefRenamer {
+                        inliner: self,
+                    };
+                    renamer.visit_item(Rc::get_mut(item).unwrap());
+                }
+                HierItem::Instance(decl) => {
+                    for expr in Rc::get_mut(decl).unwrap().port.iter_mut().flatten() {
+                        self.visit_expr(expr);
+                    }
+                }
+                HierItem::GenBlock(genblk) => {
+                    self.xfrm_scope(&mut Rc::get_mut(genblk).unwrap().scope);
+                }
+                HierItem::LoopGenBlock(loopgenblk) => {
+                    for (_, genblk) in loopgenblk.instances.borrow_mut().iter_mut() {
+                        self.xfrm_scope(&mut Rc::get_mut(genblk).unwrap().scope);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn rename_ast_expr(&mut self, expr: &mut ast::Expr) {
+        let mut renamer = RefRenamer {
+            inliner: self,
+        };
+        renamer.visit_expr(expr);
+    }
+}
+
+impl<'a> EhtVisitor for PkgInliner<'a> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        if let expr::ExprKind::HierName(HierId::Name(ref mut scope, ref mut name)) = expr.value {
+            if let Some(Scope::Name(None, ref pkg)) = *scope {
+                **name = self.require(pkg, name);
+            } else {
+                return;
+            }
+            *scope = None;
+            return;
+        }
+        self.do_visit_expr(expr);
+    }
+}
+
+/// Rename package references within a copy of a function or task declared in a package.
+struct SubroutineRenamer<'a, 'b: 'a> {
+    inliner: &'a mut PkgInliner<'b>,
+    /// Package that the function or task is declared in.
+    pkg: &'a Ident,
+    /// Names declared locally, which hide items of the package.
+    locals: HashSet<String>,
+}
+
+impl<'a, 'b> EhtVisitor for SubroutineRenamer<'a, 'b> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        if let expr::ExprKind::HierName(HierId::Name(ref mut scope, ref mut name)) = expr.value {
+            let local = match scope {
+                Some(Scope::Name(None, pkg)) => self.inliner.require(pkg, name),
+                None if !self.locals.contains(&name.value) => {
+                    if self.inliner.pkgs[&self.pkg.value].scope.find(name).is_none() {
+                        return;
+                    }
+                    self.inliner.require(self.pkg, name)
+                }
+                _ => return,
+            };
+            *scope = None;
+            **name = local;
+            return;
+        }
+        self.do_visit_expr(expr);
+    }
+
+    fn visit_stmt(&mut self, stmt: &mut expr::Stmt) {
+        // The body is copied from the package, so declarations need to be copied before they
+        // can be modified.
+        if let expr::StmtKind::DataDecl(decl) = &mut stmt.value {
+            *decl = Rc::new(hier::DataDecl {
+                lifetime: decl.lifetime,
+                ty: decl.ty.clone(),
+                name: decl.name.clone(),
+                init: decl.init.clone(),
+            });
+        }
+        self.do_visit_stmt(stmt);
+    }
+}
+
+/// Rename package references within AST that is not elaborated.
+struct RefRenamer<'a, 'b: 'a> {
+    inliner: &'a mut PkgInliner<'b>,
+}
+
+impl<'a, 'b> RefRenamer<'a, 'b> {
+    /// Get the local name of a possibly package-scoped name, or None if it is not a package item.
+    fn rename(&mut self, scope: &Option<Scope>, name: &Ident) -> Option<Ident> {
+        match scope {
+            Some(Scope::Name(None, pkg)) => Some(self.inliner.require(pkg, name)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, 'b> AstVisitor for RefRenamer<'a, 'b> {
+    fn visit_hier_name(&mut self, id: &mut HierId) {
+        match id {
+            HierId::Name(scope, name) => {
+                if let Some(local) = self.rename(scope, name) {
+                    *scope = None;
+                    **name = local;
+                }
+            }
+            HierId::Member(id, _) => self.visit_hier_name(id),
+            HierId::Select(id, dim) => {
+                self.visit_hier_name(id);
+                self.visit_dim(dim);
+            }
+            _ => (),
+        }
+    }
+
+    fn visit_ty(&mut self, ty: &mut ast::DataType) {
+        if let DataTypeKind::HierName(scope, name, _) = &mut ty.value {
+            if let Some(local) = self.rename(scope, name) {
+                *scope = None;
+                *name = local;
+            }
+        }
+        self.do_visit_ty(ty);
+    }
+}
@@ -0,0 +1,340 @@
+//! Flatten packed structures into simple vectors. Every packed-struct-typed variable, port and
+//! parameter becomes a vector of the same width, member accesses become constant part-selects
+//! and struct-typed assignment patterns become concatenations.
+//!
+//! Members are laid out from MSB to LSB in declaration order, so the first member occupies the
+//! most significant bits.
+
+use std::rc::Rc;
+
+use source::DiagMgr;
+use number::LogicVec;
+use syntax::ast::Spanned;
+use elaborate::ty::{Ty, IntTy};
+use elaborate::expr::{self, Val};
+use elaborate::hier::{self, HierItem, HierScope};
+use elaborate::eht_visit::EhtVisitor;
+
+pub fn struct_elim(diag: &DiagMgr, source: &mut hier::Source) {
+    let mut elim = StructEliminator { diag };
+    elim.visit(source);
+}
+
+struct StructEliminator<'a> {
+    diag: &'a DiagMgr,
+}
+
+/// Replace all packed structs within an integral type with simple vectors.
+fn lower_int(ty: &IntTy) -> IntTy {
+    match ty {
+        IntTy::Struct(struc) => IntTy::SimpleVec(struc.width, struc.two_state, struc.sign),
+        IntTy::Array(element, ub, lb) => IntTy::Array(Box::new(lower_int(element)), *ub, *lb),
+        _ => ty.clone(),
+    }
+}
+
+/// Replace all packed structs within a type with simple vectors.
+fn lower_ty(ty: &Ty) -> Ty {
+    match ty {
+        Ty::Int(subty) => Ty::Int(lower_int(subty)),
+        Ty::Array(element, ub, lb) => Ty::Array(Box::new(lower_ty(element)), *ub, *lb),
+        _ => ty.clone(),
+    }
+}
+
+fn lower_val(val: &Val) -> Val {
+    match val {
+        Val::Type(ty) => Val::Type(lower_ty(ty)),
+        _ => val.clone(),
+    }
+}
+
+/// Check if an expression is a constant part-select of a simple vector, possibly with its sign
+/// changed. These are the result of lowering member accesses.
+fn is_flat_part_select(expr: &expr::Expr) -> bool {
+    match expr.value {
+        expr::ExprKind::Select(ref parent, Spanned { value: expr::DimKind::Range(..), .. }) => {
+            matches!(parent.ty, Ty::Int(IntTy::SimpleVec(..)))
+        }
+        expr::ExprKind::SignCast(_, ref inner) => is_flat_part_select(inner),
+        _ => false,
+    }
+}
+
+/// Split a part-select that satisfies `is_flat_part_select` into the vector being selected and
+/// the LSB of the part.
+fn split_part_select(expr: expr::Expr) -> (expr::Expr, i32) {
+    match expr.value {
+        expr::ExprKind::Select(parent, dim) => match dim.value {
+            expr::DimKind::Range(ub, lb) => (*parent, ::std::cmp::min(ub, lb)),
+            _ => unreachable!(),
+        },
+        expr::ExprKind::SignCast(_, inner) => split_part_select(*inner),
+        _ => unreachable!(),
+    }
+}
+
+/// Convert an expression to the width of a struct member, as the concatenation will not do so.
+fn fit_member(item: expr::Expr, ty: &IntTy) -> expr::Expr {
+    let width = ty.width();
+    let (item_width, item_sign) = match item.ty {
+        Ty::Int(ref subty) => (subty.width(), subty.sign()),
+        _ => return item,
+    };
+    let span = item.span;
+    match item.value {
+        expr::ExprKind::Const(Val::Int(ref val)) => {
+            let mut val = val.extend_or_trunc(width);
+            val.signed = ty.sign();
+            expr::Expr {
+                value: expr::ExprKind::Const(Val::Int(val)),
+                span,
+                ty: Ty::Int(lower_int(ty)),
+            }
+        }
+        _ if width == item_width => item,
+        _ if width > item_width => expr::Expr {
+            value: expr::ExprKind::WidthCast(width, Box::new(item)),
+            span,
+            ty: Ty::Int(IntTy::SimpleVec(width, ty.two_state(), item_sign)),
+        },
+        _ => expr::Expr {
+            value: expr::ExprKind::TypeCast(Box::new(Ty::Int(lower_int(ty))), Box::new(item)),
+            span,
+            ty: Ty::Int(lower_int(ty)),
+        },
+    }
+}
+
+impl<'a> StructEliminator<'a> {
+    pub fn visit(&mut self, source: &mut hier::Source) {
+        for unit in &mut source.units {
+            self.visit_scope(unit);
+        }
+        for pkg in source.pkgs.values_mut() {
+            self.visit_scope(&mut pkg.scope);
+        }
+        // All references to structs are gone now.
+        source.structs.clear();
+    }
+
+    fn visit_scope(&mut self, scope: &mut HierScope) {
+        for item in &mut scope.items {
+            self.visit_item(item);
+        }
+    }
+
+    fn visit_item(&mut self, item: &mut HierItem) {
+        match item {
+            HierItem::Param(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+                decl.init = lower_val(&decl.init);
+            }
+            HierItem::Type(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+            }
+            HierItem::DataPort(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+            }
+            HierItem::DataDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+                if let Some(expr) = &mut decl.init { self.visit_expr(expr); }
+            }
+            HierItem::NetDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+                if let Some(expr) = &mut decl.init { self.visit_expr(expr); }
+            }
+            HierItem::FuncDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+                self.lower_subroutine(&mut decl.ports, &mut decl.body);
+            }
+            HierItem::TaskDecl(decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                self.lower_subroutine(&mut decl.ports, &mut decl.body);
+            }
+            HierItem::ContinuousAssign(expr) => {
+                self.visit_expr(Rc::get_mut(expr).unwrap());
+            }
+            HierItem::Always(_, stmt) => {
+                self.visit_stmt(Rc::get_mut(stmt).unwrap());
+            }
+            HierItem::Instance(decl) => {
+                for expr in Rc::get_mut(decl).unwrap().port.iter_mut().flatten() {
+                    self.visit_expr(expr);
+                }
+            }
+            HierItem::Design(decl) => {
+                for (_, inst) in decl.instances.borrow_mut().iter_mut() {
+                    self.visit_scope(&mut Rc::get_mut(inst).unwrap().scope);
+                }
+            }
+            HierItem::GenBlock(genblk) => {
+                self.visit_scope(&mut Rc::get_mut(genblk).unwrap().scope);
+            }
+            HierItem::LoopGenBlock(loopgenblk) => {
+                for (_, genblk) in loopgenblk.instances.borrow_mut().iter_mut() {
+                    self.visit_scope(&mut Rc::get_mut(genblk).unwrap().scope);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Lower types and expressions within the signature and body of a function or task.
+    fn lower_subroutine(&mut self, ports: &mut [hier::TfPortDecl], body: &mut [expr::Stmt]) {
+        for port in ports {
+            port.ty = lower_ty(&port.ty);
+            if let Some(expr) = &mut port.init { self.visit_expr(expr); }
+        }
+        body.iter_mut().for_each(|stmt| self.visit_stmt(stmt));
+    }
+
+    /// Convert a member access into a part-select of the flattened struct.
+    fn lower_member(&mut self, expr: expr::Expr) -> expr::Expr {
+        let span = expr.span;
+        let (mut parent, name) = match expr.value {
+            expr::ExprKind::Member(parent, name) => (parent, name),
+            _ => unreachable!(),
+        };
+        let struc = match parent.ty {
+            Ty::Int(IntTy::Struct(ref struc)) => struc.clone(),
+            _ => unreachable!(),
+        };
+
+        // Find out the type and LSB of the member. Elaborator has already checked that it exists.
+        let mut lsb = struc.width;
+        let mut member = None;
+        for (ty, member_name, _) in &struc.members {
+            lsb -= ty.width();
+            if member_name.value == name.value {
+                member = Some(ty);
+                break;
+            }
+        }
+        let member = member.unwrap();
+        let width = member.width();
+
+        // If the parent is a member access itself, fold both into a single part-select.
+        self.visit_expr(&mut parent);
+        let (parent, offset) = if is_flat_part_select(&parent) {
+            split_part_select(*parent)
+        } else {
+            (*parent, 0)
+        };
+        let lsb = offset + lsb as i32;
+
+        let dim = match member {
+            IntTy::Logic(..) => {
+                let index = expr::Expr {
+                    value: expr::ExprKind::Const(Val::Int(LogicVec::from_integer(lsb))),
+                    span,
+                    ty: Ty::Int(IntTy::SimpleVec(32, true, true)),
+                };
+                expr::DimKind::Value(Box::new(index))
+            }
+            _ => expr::DimKind::Range(lsb + width as i32 - 1, lsb),
+        };
+
+        // Part-selects are always unsigned, so signed members need an explicit cast.
+        let ty = lower_int(member);
+        let sign = ty.sign();
+        let select_ty = if sign { IntTy::SimpleVec(width, ty.two_state(), false) } else { ty.clone() };
+        let select = expr::Expr {
+            value: expr::ExprKind::Select(Box::new(parent), Spanned::new(dim, span)),
+            span,
+            ty: Ty::Int(select_ty),
+        };
+        if !sign {
+            return select;
+        }
+        expr::Expr {
+            value: expr::ExprKind::SignCast(true, Box::new(select)),
+            span,
+            ty: Ty::Int(ty),
+        }
+    }
+
+    /// Convert a struct assignment pattern into a concatenation.
+    fn lower_pattern(&mut self, expr: expr::Expr) -> expr::Expr {
+        let span = expr.span;
+        let (ty, pattern) = match expr.value {
+            expr::ExprKind::AssignPattern(ty, pattern) => (ty, pattern),
+            _ => unreachable!(),
+        };
+        let struc = match *ty {
+            Ty::Int(IntTy::Struct(ref struc)) => struc.clone(),
+            _ => unreachable!(),
+        };
+        let list = match pattern {
+            expr::AssignPattern::Simple(list) => list,
+            _ => unreachable!("elaborator only produces simple assignment patterns"),
+        };
+        if list.len() != struc.members.len() {
+            self.diag.report_error(
+                format!(
+                    "assignment pattern has {} items but the struct has {} members",
+                    list.len(), struc.members.len()
+                ),
+                span
+            );
+        }
+        let list = list.into_iter().zip(&struc.members).map(|(item, (ty, ..))| fit_member(item, ty)).collect();
+        let concat = expr::Expr {
+            value: expr::ExprKind::Concat(list),
+            span,
+            ty: Ty::Int(IntTy::SimpleVec(struc.width, struc.two_state, false)),
+        };
+        if !struc.sign {
+            return concat;
+        }
+        expr::Expr {
+            value: expr::ExprKind::SignCast(true, Box::new(concat)),
+            span,
+            ty: Ty::Int(IntTy::SimpleVec(struc.width, struc.two_state, true)),
+        }
+    }
+}
+
+impl<'a> EhtVisitor for StructEliminator<'a> {
+    fn visit_expr(&mut self, expr: &mut expr::Expr) {
+        match expr.value {
+            expr::ExprKind::Member(..) => {
+                ::util::replace_with(expr, |expr| self.lower_member(expr));
+                return;
+            }
+            _ => self.do_visit_expr(expr),
+        }
+        let is_struct_pattern = match expr.value {
+            expr::ExprKind::AssignPattern(ref ty, _) => matches!(**ty, Ty::Int(IntTy::Struct(_))),
+            _ => false,
+        };
+        if is_struct_pattern {
+            ::util::replace_with(expr, |expr| self.lower_pattern(expr));
+            return;
+        }
+        match expr.value {
+            expr::ExprKind::AssignPattern(ref mut ty, _) |
+            expr::ExprKind::TypeCast(ref mut ty, _) => **ty = lower_ty(ty),
+            _ => (),
+        }
+        expr.ty = lower_ty(&expr.ty);
+    }
+
+    fn visit_stmt(&mut self, stmt: &mut expr::Stmt) {
+        match stmt.value {
+            expr::StmtKind::For { ty: Some(ref mut ty), .. } => **ty = lower_ty(ty),
+            expr::StmtKind::DataDecl(ref mut decl) => {
+                let decl = Rc::get_mut(decl).unwrap();
+                decl.ty = lower_ty(&decl.ty);
+            }
+            _ => (),
+        }
+        self.do_visit_stmt(stmt);
+    }
+}
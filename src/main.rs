@@ -8,6 +8,7 @@ extern crate lazy_static;
 extern crate getopts;
 
 mod opts;
+mod filelist;
 mod util;
 mod syntax;
 mod source;
@@ -22,6 +23,7 @@ use std::io::prelude::*;
 use source::{SrcMgr, Source, DiagMgr, Severity};
 use printer::PrettyPrint;
 use syntax::ast;
+use syntax::ast_visit::AstVisitor;
 
 // use lexer::TokenKind;
 use std::rc::Rc;
@@ -31,37 +33,133 @@ fn print_help(opts: &getopts::Options, program: &str) {
     eprint!("{}", opts.usage(&brief));
 }
 
+/// Build a Makefile rule that makes the target depend on the files. Each file is only listed
+/// once.
+fn dependency_rule(target: &str, files: &[String]) -> String {
+    // Spaces and dollar signs need to be escaped in Makefiles
+    let escape = |name: &str| name.replace('$', "$$").replace(' ', "\\ ");
+    let mut rule = format!("{}:", escape(target));
+    for (i, file) in files.iter().enumerate() {
+        if files[..i].contains(file) {
+            continue;
+        }
+        rule.push_str(" \\\n  ");
+        rule.push_str(&escape(file));
+    }
+    rule.push('\n');
+    rule
+}
+
+/// Collects names of design units that are instantiated.
+struct InstanceNames(Vec<String>);
+
+impl AstVisitor for InstanceNames {
+    fn visit_item(&mut self, item: &mut ast::Item) {
+        if let ast::Item::HierInstantiation(inst) = item {
+            self.0.push(inst.name.value.clone());
+        }
+        self.do_visit_item(item);
+    }
+}
+
+/// Find files in library directories for design units that are instantiated but not defined.
+/// A design unit is expected in a file named after it, with one of the library extensions.
+fn find_library_files(files: &mut [Vec<ast::Item>], dirs: &[String], exts: &[String]) -> Vec<String> {
+    let mut defined = Vec::new();
+    let mut instances = InstanceNames(Vec::new());
+    for item in files.iter_mut().flatten() {
+        if let ast::Item::DesignDecl(decl) = item {
+            defined.push(decl.name.value.clone());
+        }
+        instances.visit_item(item);
+    }
+    let mut found = Vec::new();
+    for name in instances.0 {
+        if defined.contains(&name) {
+            continue;
+        }
+        'search: for dir in dirs {
+            for ext in exts {
+                let path = std::path::Path::new(dir).join(format!("{}{}", name, ext));
+                if path.is_file() {
+                    found.push(path.to_string_lossy().into_owned());
+                    break 'search;
+                }
+            }
+        }
+        defined.push(name);
+    }
+    found
+}
+
 fn main() {
     //
     // Argument parsing
     //
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let program = args.remove(0);
+    let args = match filelist::expand_args(args) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("{}", err);
+            ::std::process::exit(1);
+        }
+    };
+    // getopts only supports single-letter short options, so these are accepted as long options.
+    let args: Vec<String> = args.into_iter().map(|arg| match arg.as_ref() {
+        "-MF" | "-MT" | "-isystem" => format!("-{}", arg),
+        _ => arg,
+    }).collect();
 
     let mut opts = getopts::Options::new();
     opts.optopt("o", "", "set output file name", "FILE");
     opts.optopt("t", "", "set toplevel module name", "MODULE");
+    opts.optmulti("G", "", "override a parameter of the toplevel module", "NAME=VALUE");
     opts.optmulti("b", "", "set a module to be a black box", "MODULE");
+    opts.optmulti("f", "", "read command line arguments from a file", "FILE");
+    opts.optmulti("F", "", "read command line arguments from a file, with paths relative to it", "FILE");
+    opts.optmulti("v", "", "add a library file", "FILE");
+    opts.optmulti("y", "", "add a directory searched for design units not found in input files", "DIR");
+    opts.optmulti("", "libext", "add an extension of files in library directories", "EXT");
     opts.optmulti("I", "", "add a path to the include search path", "PATH");
+    opts.optmulti("", "isystem", "add a path to the search path of `include <...>", "PATH");
+    opts.optmulti("D", "", "define a preprocessor macro", "NAME[=VALUE]");
+    opts.optmulti("U", "", "undefine a preprocessor macro", "NAME");
+    opts.optflag("E", "", "preprocess only, print the preprocessed source");
+    opts.optflag("M", "", "parse only, print a Makefile rule listing all files read");
+    opts.optopt("", "MF", "write a Makefile rule listing all files read to a file", "FILE");
+    opts.optopt("", "MT", "set the target of the Makefile rule, which defaults to the output file", "TARGET");
     opts.optflag("", "parse", "parse only, do not elaborate");
     opts.optopt("p", "", "give a prefix to all generated modules", "PREFIX");
+    opts.optopt(
+        "", "std",
+        "set the language standard of the output. Verilog-2005 has no packages, foreach loops or \
+         jump statements, so --inline-pkgs and --lower-foreach are implied, and return, break and \
+         continue are always rewritten using flag variables",
+        "systemverilog|verilog2005"
+    );
+    opts.optopt("", "keywords", "set the keywords recognised outside `begin_keywords", "VERSION");
+    opts.optflag("", "inline-pkgs", "copy package items into modules that use them and omit packages");
+    opts.optflag("", "lower-foreach", "rewrite foreach loops into for loops");
     opts.optflag("h", "help", "print this help message");
 
-    let matches = match opts.parse(&args[1..]) {
+    let matches = match opts.parse(&args) {
         Ok(m) => { m }
         Err(f) => {
             eprintln!("{}", f.to_string());
-            return print_help(&opts, &args[0])
+            return print_help(&opts, &program)
         }
     };
 
     if matches.opt_present("h") {
-        return print_help(&opts, &args[0])
+        return print_help(&opts, &program)
     }
 
     // Initailise source manager and diagnostic manager first
     let mut include_search_list: Vec<::std::path::PathBuf> = matches.opt_strs("I").into_iter().map(|x| x.into()).collect();
     include_search_list.insert(0, ::std::path::PathBuf::new());
-    let src_mgr = Rc::new(SrcMgr::new(include_search_list));
+    let system_search_list = matches.opt_strs("isystem").into_iter().map(|x| x.into()).collect();
+    let src_mgr = Rc::new(SrcMgr::new(include_search_list, system_search_list));
     let diag_mgr = DiagMgr::new(src_mgr.clone());
 
     if matches.free.is_empty() {
@@ -69,6 +167,15 @@ fn main() {
         return;
     }
 
+    let std = match matches.opt_str("std").as_ref().map(|x| &x[..]) {
+        None | Some("systemverilog") => opts::Std::SystemVerilog,
+        Some("verilog2005") => opts::Std::Verilog2005,
+        Some(v) => {
+            diag_mgr.report_span(Severity::Fatal, format!("unknown language standard {}", v), source::Span::none());
+            return;
+        }
+    };
+
     // Register a new panic handler. If the panic is caused by throwing fatal error, we mute
     // Rust's built-in error message and stack trace.
     {
@@ -80,18 +187,71 @@ fn main() {
         }));
     }
 
-    // Parse all files together
+    // Parse all files together. Macros defined in a file are visible to files after it.
     let mut files = Vec::new();
-    'outer: for filename in &matches.free {
-        let mut infile = File::open(filename).unwrap();
+    let mut preprocessor = syntax::Preprocessor::new(&src_mgr, &diag_mgr);
+    if let Some(v) = matches.opt_str("keywords") {
+        if !preprocessor.set_default_keywords(&v) {
+            diag_mgr.report_span(Severity::Fatal, format!("unknown keyword version {}", v), source::Span::none());
+            return;
+        }
+    }
+
+    // Apply -D and -U in the order they are given. The preprocessor applies them again before
+    // each file.
+    let mut macro_opts: Vec<_> = matches.opt_strs_pos("D").into_iter().map(|(pos, v)| (pos, true, v)).collect();
+    macro_opts.extend(matches.opt_strs_pos("U").into_iter().map(|(pos, v)| (pos, false, v)));
+    macro_opts.sort_by_key(|(pos, ..)| *pos);
+    for (_, define, v) in macro_opts {
+        if define {
+            preprocessor.define(&v);
+        } else {
+            preprocessor.undef(&v);
+        }
+    }
+    if diag_mgr.has_error() { ::std::process::exit(1); }
+
+    // In preprocess-only mode, the preprocessed source is printed instead of parsed. Files are
+    // still parsed when only listing dependencies, so design units can be found in libraries.
+    let deps_only = matches.opt_present("M");
+    let preprocess_only = matches.opt_present("E") && !deps_only;
+    let mut preprocessed = String::new();
+
+    // Library files are parsed after input files, and files in library directories are added
+    // when there are design units not found in the files so far.
+    let mut filenames = matches.free.clone();
+    filenames.extend(matches.opt_strs("v"));
+    let lib_dirs = matches.opt_strs("y");
+    let mut lib_exts = matches.opt_strs("libext");
+    if lib_exts.is_empty() {
+        lib_exts = vec![".sv".to_owned(), ".v".to_owned()];
+    }
+    let mut index = 0;
+    loop {
+        if index == filenames.len() {
+            if lib_dirs.is_empty() || preprocess_only { break }
+            let found = find_library_files(&mut files, &lib_dirs, &lib_exts);
+            if found.is_empty() { break }
+            filenames.extend(found);
+        }
+        let filename = filenames[index].clone();
+        index += 1;
+
         let mut contents = String::new();
-        infile.read_to_string(&mut contents).unwrap();
+        if let Err(err) = File::open(&filename).and_then(|mut file| file.read_to_string(&mut contents)) {
+            diag_mgr.report_span(Severity::Fatal, format!("cannot read {}: {}", filename, err), source::Span::none());
+            ::std::process::exit(1);
+        }
 
         let src = Rc::new(Source::new((*filename).to_owned(), contents));
         src_mgr.add_source(src.clone());
 
         let list = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            let tokens = syntax::pp(&src_mgr, &diag_mgr, &src);
+            if preprocess_only {
+                preprocessed.push_str(&preprocessor.all_text(&src));
+                return Vec::new();
+            }
+            let tokens = preprocessor.all(&src);
             let tokens = syntax::tk_tree(&diag_mgr, tokens);
             let list = syntax::parse(&diag_mgr, tokens);
             list
@@ -112,6 +272,35 @@ fn main() {
     // Abort elaboration when there are syntax errors.
     if diag_mgr.has_error() { ::std::process::exit(1); }
 
+    if deps_only || matches.opt_present("MF") {
+        let target = match matches.opt_str("MT").or_else(|| matches.opt_str("o")) {
+            Some(v) => v,
+            None => {
+                diag_mgr.report_span(
+                    Severity::Fatal, "target of the Makefile rule must be given by -MT or -o", source::Span::none()
+                );
+                ::std::process::exit(1);
+            }
+        };
+        let mut deps = filenames.clone();
+        deps.extend(src_mgr.loaded_files().into_iter().map(|path| path.to_string_lossy().into_owned()));
+        let rule = dependency_rule(&target, &deps);
+        match matches.opt_str("MF") {
+            Some(v) => write!(File::create(v).unwrap(), "{}", rule).unwrap(),
+            None => print!("{}", rule),
+        }
+        if deps_only { return; }
+    }
+
+    if preprocess_only {
+        let mut out: Box<dyn Write> = match matches.opt_str("o") {
+            None => Box::new(std::io::stdout()),
+            Some(v) => Box::new(File::create(v).unwrap()),
+        };
+        write!(out, "{}", preprocessed).unwrap();
+        return;
+    }
+
     elaborate::resolve(&diag_mgr, &mut files);
 
     // Abort elaboration when there are syntax errors.
@@ -134,10 +323,36 @@ fn main() {
         return;
     }
 
+    // Parse parameter overrides as if they are source code
+    let mut params = Vec::new();
+    for param in matches.opt_strs("G") {
+        let src = Rc::new(Source::new("<command line>".to_owned(), param));
+        src_mgr.add_source(src.clone());
+
+        let param = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let tokens = syntax::pp(&src_mgr, &diag_mgr, &src);
+            let tokens = syntax::tk_tree(&diag_mgr, tokens);
+            syntax::parse_param_override(&diag_mgr, tokens)
+        })) {
+            Ok(v) => v,
+            Err(_) => ::std::process::exit(1),
+        };
+        params.push(param);
+    }
+
+    // Abort elaboration when parameter overrides cannot be parsed.
+    if diag_mgr.has_error() { ::std::process::exit(1); }
+
     let opts = opts::Opts {
         blackbox: matches.opt_strs("b"),
         prefix: matches.opt_str("p"),
         toplevel: matches.opt_str("t").unwrap_or_else(|| "chip_top".to_owned()),
+        params,
+        std,
+        // There are no packages in Verilog-2005
+        inline_pkgs: matches.opt_present("inline-pkgs") || std == opts::Std::Verilog2005,
+        // There are no foreach loops in Verilog-2005
+        lower_foreach: matches.opt_present("lower-foreach") || std == opts::Std::Verilog2005,
     };
 
     let mut elaborated = elaborate::elaborate(&diag_mgr, &files, &opts);
@@ -149,22 +364,43 @@ fn main() {
     let elaborated = lowering::loop_gen_elim(elaborated);
     let elaborated = lowering::inst_array_elim(elaborated);
     let mut elaborated = lowering::gen_blk_elim(elaborated);
+    lowering::intf_elim(&diag_mgr, &mut elaborated);
     lowering::type_param_elim(&mut elaborated);
 
+    // Packed structs and enums are not available in Verilog-2005
+    if opts.std == opts::Std::Verilog2005 {
+        lowering::struct_elim(&diag_mgr, &mut elaborated);
+        lowering::enum_elim(&mut elaborated);
+    }
+
+    if opts.inline_pkgs {
+        lowering::pkg_inline(&diag_mgr, &mut elaborated);
+    }
+
+    // Abort when interfaces, structs or packages cannot be flattened.
+    if diag_mgr.has_error() { ::std::process::exit(1); }
+
     // If a prefix is specified from command line, do an additional transformation
     if opts.prefix.is_some() {
         elaborated = lowering::prefix(elaborated, &opts);
     }
 
-    let files = elaborate::reconstruct(&elaborated);
+    let files = match opts.std {
+        opts::Std::SystemVerilog => elaborate::reconstruct(&elaborated, opts.inline_pkgs),
+        opts::Std::Verilog2005 => elaborate::reconstruct_verilog(&diag_mgr, &elaborated),
+    };
+
+    // Abort when the design cannot be expressed in the requested standard.
+    if diag_mgr.has_error() { ::std::process::exit(1); }
 
     let mut out: Box<dyn Write> = match matches.opt_str("o") {
         None => Box::new(std::io::stdout()),
         Some(v) => Box::new(File::create(v).unwrap()),
     };
 
-    {
-        let list = files.first().unwrap();
+    // There are no packages in Verilog-2005 or when they are inlined.
+    let list = files.first().unwrap();
+    if !list.is_empty() {
         writeln!(out, "/* packages */").unwrap();
         let mut printer = PrettyPrint::new();
         for i in list {
@@ -174,7 +410,7 @@ fn main() {
         writeln!(out, "{}", printer.take()).unwrap();
     }
 
-    for (list, name) in files.iter().skip(1).zip(matches.free.iter()) {
+    for (list, name) in files.iter().skip(1).zip(filenames.iter()) {
         writeln!(out, "/* file: {} */", name).unwrap();
         if list.is_empty() {
 
@@ -173,7 +173,7 @@ impl LogicVec {
             if self.signed {
                 Some(self.value.clone().to_bigint())
             } else {
-                Some(BigInt::from_biguint(Sign::Minus, self.value.clone().to_biguint()))
+                Some(BigInt::from_biguint(Sign::Plus, self.value.clone().to_biguint()))
             }
         } else {
             None
@@ -543,12 +543,44 @@ impl LogicVec {
         val.to_bool()
     }
 
+    /// Compare bit-by-bit including X and Z, as done by case statements. X or Z bits in either
+    /// operand are ignored if `ignore_x` or `ignore_z` is set, for casex and casez respectively.
+    pub fn case_eq(&self, rhs: &Self, ignore_x: bool, ignore_z: bool) -> bool {
+        let mut diff = self.value.clone();
+        diff ^= &rhs.value;
+        let mut xz_diff = self.xz.clone();
+        xz_diff ^= &rhs.xz;
+        diff |= &xz_diff;
+
+        let mut mask = Int::zero(self.width());
+        for val in &[self, rhs] {
+            if ignore_x {
+                let mut x = val.xz.clone();
+                x &= &val.value;
+                mask |= &x;
+            }
+            if ignore_z {
+                let mut z = !val.value.clone();
+                z &= &val.xz;
+                mask |= &z;
+            }
+        }
+        diff &= &!mask;
+        diff.is_zero()
+    }
+
 
     /// Convert to boolean (single LogicValue)
     pub fn to_bool(&self) -> LogicValue {
         // This is equivalent to a reduction or.
         self.reduce_or()
     }
+
+    /// Count the number of bits that are one. X and Z bits are not counted.
+    pub fn count_ones(&self) -> usize {
+        let two_state = self.clone().force_two_state();
+        (0..self.width()).filter(|&i| two_state.value.bit_at(i)).count()
+    }
 }
 
 //
@@ -580,6 +612,66 @@ impl LogicVec {
     }
 }
 
+// Formatting
+impl LogicVec {
+    /// Format the number in given radix as done by `$display`. A digit is shown as `x` or `z` if
+    /// all of its bits are X or Z, and as `X` or `Z` if only some of its bits are. In decimal the
+    /// entire number is treated as a single digit.
+    pub fn to_radix_string(&self, radix: u32) -> String {
+        let width = self.width();
+        let digit_bits = match radix {
+            2 => 1,
+            8 => 3,
+            16 => 4,
+            10 => {
+                return match self.get_two_state() {
+                    Some(v) => v.to_string(),
+                    None => self.xz_digit(0, width).unwrap().to_string(),
+                };
+            }
+            _ => panic!("unsupported radix {}", radix),
+        };
+        let digits = width.div_ceil(digit_bits);
+        let mut str = String::with_capacity(digits);
+        for digit in (0..digits).rev() {
+            let lsb = digit * digit_bits;
+            let msb = cmp::min(lsb + digit_bits, width);
+            match self.xz_digit(lsb, msb) {
+                Some(ch) => str.push(ch),
+                None => {
+                    let value = (lsb..msb).rev().fold(0, |acc, i| acc * 2 + self.value.bit_at(i) as u32);
+                    str.push(::std::char::from_digit(value, radix).unwrap());
+                }
+            }
+        }
+        str
+    }
+
+    /// Get the character to display for bits from `lsb` to `msb` (exclusive) if any of them is X
+    /// or Z.
+    fn xz_digit(&self, lsb: usize, msb: usize) -> Option<char> {
+        let mut x = 0;
+        let mut z = 0;
+        for i in lsb..msb {
+            if self.xz.bit_at(i) {
+                if self.value.bit_at(i) { x += 1 } else { z += 1 }
+            }
+        }
+        let len = msb - lsb;
+        if x == len {
+            Some('x')
+        } else if z == len {
+            Some('z')
+        } else if x != 0 {
+            Some('X')
+        } else if z != 0 {
+            Some('Z')
+        } else {
+            None
+        }
+    }
+}
+
 impl fmt::Debug for LogicVec {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         let width = self.width();
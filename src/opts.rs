@@ -1,6 +1,22 @@
+use syntax::ast::{Ident, Expr};
+
 /// Global options for this tool
 pub struct Opts {
     pub blackbox: Vec<String>,
     pub prefix: Option<String>,
     pub toplevel: String,
+    /// Overrides of parameters of the toplevel module
+    pub params: Vec<(Ident, Expr)>,
+    pub std: Std,
+    /// Copy package items into design units that use them instead of emitting packages
+    pub inline_pkgs: bool,
+    /// Rewrite foreach loops into nested for loops
+    pub lower_foreach: bool,
+}
+
+/// Language standard of the output
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Std {
+    SystemVerilog,
+    Verilog2005,
 }
@@ -1,9 +1,28 @@
 use syntax::ast::*;
 use syntax::tokens::{TokenKind, Keyword};
+use syntax::is_keyword;
 
 use util::IdentifyFirstLast;
 
 use std::convert::AsRef;
+use std::fmt;
+
+/// An identifier as printed. It is escaped if it would otherwise be read as a keyword of any
+/// standard, or if it is not a simple identifier.
+struct Id<'a>(&'a str);
+
+impl<'a> fmt::Display for Id<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut chars = self.0.chars();
+        let simple = chars.next().is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_') &&
+            chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '$');
+        if simple && !is_keyword(self.0) {
+            f.write_str(self.0)
+        } else {
+            write!(f, "\\{} ", self.0)
+        }
+    }
+}
 
 pub struct PrettyPrint {
     output: String,
@@ -101,7 +120,7 @@ impl PrettyPrint {
     }
 
     fn print_decl_assign(&mut self, obj: &DeclAssign) {
-        self.append(format!("{}", obj.name));
+        self.append(format!("{}", Id(&obj.name)));
         for dim in &obj.dim {
             self.print_dim(dim);
         }
@@ -135,10 +154,12 @@ impl PrettyPrint {
                     NetPortType::Default => (),
                     _ => unimplemented!(),
                 }
-                self.print_type(&ty);
-                self.append(" ");
+                if !Self::is_empty_type(ty) {
+                    self.print_type(&ty);
+                    self.append(" ");
+                }
                 for (item, _, last) in list.iter().identify_first_last() {
-                    self.append(format!("{}", item.name));
+                    self.append(format!("{}", Id(&item.name)));
                     for dim in &item.dim {
                         self.print_dim(dim);
                     }
@@ -153,15 +174,15 @@ impl PrettyPrint {
             }
             PortDecl::Interface(intf, modport, list) => {
                 if let Some(intf) = intf {
-                    self.indent_append(format!("{}", intf));
+                    self.indent_append(format!("{}", Id(intf)));
                 } else {
                     self.indent_append("interface");
                 }
                 if let Some(modport) = modport {
-                    self.append(format!(".{}", modport));
+                    self.append(format!(".{}", Id(modport)));
                 }
                 for (item, _, last) in list.iter().identify_first_last() {
-                    self.append(format!(" {}", item.name));
+                    self.append(format!(" {}", Id(&item.name)));
                     for dim in &item.dim {
                         self.print_dim(dim);
                     }
@@ -182,20 +203,32 @@ impl PrettyPrint {
     }
 
     fn print_module_decl(&mut self, obj: &DesignDecl) {
+        // The directive is reset after the design unit so it does not affect others
+        let default_nettype = match obj.default_nettype {
+            Some(NetTy::Wire) => None,
+            Some(netty) => Some(format!("{}", netty)),
+            None => Some("none".to_owned()),
+        };
+        if let Some(netty) = &default_nettype {
+            self.append(format!("`default_nettype {}\n", netty));
+        }
+        if let Some(timescale) = &obj.timescale {
+            self.append(format!("`timescale {}\n", timescale));
+        }
         self.append(format!("{}", obj.kw));
         self.indent();
         if obj.lifetime == Lifetime::Automatic {
             self.append(format!(" automatic"));
         }
-        self.append(format!(" {}", obj.name));
+        self.append(format!(" {}", Id(&obj.name)));
         for items in &obj.pkg_import {
             self.append("\nimport ");
             self.print_comma_list(items, |this, PkgImportItem(pkg, item)| {
-                this.append(format!("{}", pkg));
+                this.append(format!("{}", Id(pkg)));
                 this.append("::");
                 match item {
                     None => this.append("*"),
-                    Some(v) => this.append(format!("{}", v)),
+                    Some(v) => this.append(format!("{}", Id(v))),
                 }
             });
             self.append(";");
@@ -224,6 +257,9 @@ impl PrettyPrint {
         }
         self.unindent();
         self.indent_append(format!("end{}", obj.kw));
+        if default_nettype.is_some() {
+            self.append("\n`default_nettype wire");
+        }
     }
 
     fn print_pkg_decl(&mut self, obj: &PkgDecl) {
@@ -232,7 +268,7 @@ impl PrettyPrint {
         if obj.lifetime == Lifetime::Automatic {
             self.append(" automatic");
         }
-        self.append(format!(" {};\n", obj.name));
+        self.append(format!(" {};\n", Id(&obj.name)));
         for item in &obj.items {
             self.indent_append("");
             self.print_item(item);
@@ -248,7 +284,7 @@ impl PrettyPrint {
             self.append("automatic ");
         }
         self.print_type(&obj.ty);
-        self.append(format!(" {}", obj.name));
+        self.append(format!(" {}", Id(&obj.name)));
         if obj.ports.len() != 0 {
             self.append(format!(" (\n"));
             self.indent();
@@ -275,7 +311,7 @@ impl PrettyPrint {
         if obj.lifetime == Lifetime::Automatic {
             self.append("automatic ");
         }
-        self.append(format!("{}", obj.name));
+        self.append(format!("{}", Id(&obj.name)));
         if obj.ports.len() != 0 {
             self.append(format!(" (\n"));
             self.indent();
@@ -316,7 +352,7 @@ impl PrettyPrint {
     }
 
     fn print_hier_instantiation(&mut self, obj: &HierInstantiation) {
-        self.append(format!("{}", obj.name));
+        self.append(format!("{}", Id(&obj.name)));
         if let Some(v) = &obj.param {
             self.append(" #");
             self.print_args(v, false);
@@ -325,7 +361,7 @@ impl PrettyPrint {
             if !first {
                 self.append(",");
             }
-            self.append(format!(" {}", item.name));
+            self.append(format!(" {}", Id(&item.name)));
             for dim in &item.dim {
                 self.print_dim(dim);
             }
@@ -347,14 +383,14 @@ impl PrettyPrint {
                     for ((_, conn), _, last) in list.iter().identify_first_last() {
                         match conn {
                             NamedPortConn::Explicit(name, expr) => {
-                                self.indent_append(format!(".{}(", name));
+                                self.indent_append(format!(".{}(", Id(name)));
                                 if let Some(v) = expr {
                                     self.print_expr(v);
                                 }
                                 self.append(")");
                             }
                             NamedPortConn::Implicit(name) => {
-                                self.indent_append(format!(".{}", name));
+                                self.indent_append(format!(".{}", Id(name)));
                             }
                             NamedPortConn::Wildcard => {
                                 self.indent_append(".*");
@@ -390,7 +426,7 @@ impl PrettyPrint {
         }
         for ((id, v), _, last) in obj.named.iter().identify_first_last() {
             if !inline { self.indent_append(""); }
-            self.append(format!(".{}(", id));
+            self.append(format!(".{}(", Id(id)));
             if let Some(v) = v {
                 self.print_expr(v);
             }
@@ -416,11 +452,11 @@ impl PrettyPrint {
             Item::PkgImport(items) => {
                 self.append("import ");
                 self.print_comma_list(items, |this, PkgImportItem(pkg, item)| {
-                    this.append(format!("{}", pkg));
+                    this.append(format!("{}", Id(pkg)));
                     this.append("::");
                     match item {
                         None => this.append("*"),
-                        Some(v) => this.append(format!("{}", v)),
+                        Some(v) => this.append(format!("{}", Id(v))),
                     }
                 });
                 self.append(";");
@@ -460,7 +496,7 @@ impl PrettyPrint {
                 if gen.genvar {
                     self.append("genvar ");
                 }
-                self.append(format!("{} = ", gen.id));
+                self.append(format!("{} = ", Id(&gen.id)));
                 self.print_expr(&gen.init);
                 self.append("; ");
                 self.print_expr(&gen.cond);
@@ -484,6 +520,27 @@ impl PrettyPrint {
                     self.print_gen_block(&v);
                 }
             }
+            Item::CaseGen(gen) => {
+                self.append("case (");
+                self.print_expr(&gen.expr);
+                self.append(")\n");
+                self.indent();
+                for (cond, block) in &gen.items {
+                    self.indent_append("");
+                    if cond.is_empty() {
+                        self.append("default: ");
+                    } else {
+                        self.print_comma_list(cond, |this, expr| {
+                            this.print_expr(expr);
+                        });
+                        self.append(": ");
+                    }
+                    self.print_gen_block(block);
+                    self.append("\n");
+                }
+                self.unindent();
+                self.indent_append("endcase");
+            }
             Item::SysTfCall(tf) => {
                 self.print_sys_tf_call(tf);
                 self.append(";");
@@ -505,8 +562,10 @@ impl PrettyPrint {
             Item::DataDecl(decl) => self.print_data_decl(decl),
             Item::NetDecl(decl) => {
                 self.append(format!("{} ", decl.net));
-                self.print_type(&decl.ty);
-                self.append(" ");
+                if !Self::is_empty_type(&decl.ty) {
+                    self.print_type(&decl.ty);
+                    self.append(" ");
+                }
                 for (item, _, last) in decl.list.iter().identify_first_last() {
                     self.print_decl_assign(item);
                     if !last {
@@ -518,12 +577,12 @@ impl PrettyPrint {
             Item::TypedefIntf(_attr, intf, ty, id) => {
                 self.append("typedef ");
                 self.print_hier_id(intf);
-                self.append(format!(".{} {};", ty, id));
+                self.append(format!(".{} {};", Id(ty), Id(id)));
             }
             Item::Typedef(_attr, ty, id, dim) => {
                 self.append("typedef ");
                 self.print_type(&ty);
-                self.append(format!(" {}", id));
+                self.append(format!(" {}", Id(id)));
                 for dim in dim {
                     self.print_dim(dim);
                 }
@@ -532,7 +591,7 @@ impl PrettyPrint {
             Item::ModportDecl(_attr, decl) => {
                 self.append("modport ");
                 self.print_comma_list(decl, |this, decl| {
-                    this.append(format!("{} (\n", decl.0));
+                    this.append(format!("{} (\n", Id(&decl.0)));
                     this.indent();
                     this.print_comma_list_newline(&decl.1, |this, item| {
                         match item {
@@ -540,9 +599,9 @@ impl PrettyPrint {
                                 this.append(format!("{} ", dir));
                                 this.print_comma_list(list, |this, item| {
                                     match item {
-                                        ModportSimplePort::Named(name) => this.append(format!("{}", name)),
+                                        ModportSimplePort::Named(name) => this.append(format!("{}", Id(name))),
                                         ModportSimplePort::Explicit(name, expr) => {
-                                            this.append(format!(".{}(", name));
+                                            this.append(format!(".{}(", Id(name)));
                                             this.print_expr(expr);
                                             this.append(")");
                                         }
@@ -568,7 +627,7 @@ impl PrettyPrint {
     fn print_gen_block(&mut self, obj: &GenBlock) {
         self.append("begin");
         if let Some(v) = &obj.name {
-            self.append(format!(": {}", v));
+            self.append(format!(": {}", Id(v)));
         }
         self.append("\n");
         self.indent();
@@ -593,6 +652,9 @@ impl PrettyPrint {
                 if sign == &Signing::Signed {
                     self.append(" signed");
                 }
+                if !dim.is_empty() {
+                    self.append(" ");
+                }
                 for dim in dim {
                     self.print_dim(dim);
                 }
@@ -600,7 +662,7 @@ impl PrettyPrint {
             DataTypeKind::IntAtom(ty, sign) => {
                 self.append(format!("{}", ty));
                 if let Some(v) = sign {
-                    self.append(format!("{}", v));
+                    self.append(format!(" {}", v));
                 }
             }
             DataTypeKind::String => self.append("string"),
@@ -645,6 +707,9 @@ impl PrettyPrint {
             DataTypeKind::Implicit(sign, dim) => {
                 if sign == &Signing::Signed {
                     self.append("signed");
+                    if !dim.is_empty() {
+                        self.append(" ");
+                    }
                 }
                 for dim in dim {
                     self.print_dim(dim);
@@ -655,7 +720,7 @@ impl PrettyPrint {
                     self.append(Self::get_scope(scope));
                     self.append("::");
                 }
-                self.append(format!("{}", name));
+                self.append(format!("{}", Id(name)));
                 for dim in dim {
                     self.print_dim(dim);
                 }
@@ -672,15 +737,23 @@ impl PrettyPrint {
         }
     }
 
+    /// Check if a type prints to nothing, i.e. an implicit type without signing or dimensions.
+    fn is_empty_type(obj: &DataType) -> bool {
+        match &**obj {
+            DataTypeKind::Implicit(Signing::Unsigned, dim) => dim.is_empty(),
+            _ => false,
+        }
+    }
+
     fn get_scope(obj: &Scope) -> String {
         match obj {
             Scope::Unit => "$unit".to_owned(),
             Scope::Local => "local".to_owned(),
             Scope::Name(parent, this) => {
                 if let Some(parent) = parent {
-                    format!("{}::{}", Self::get_scope(parent), this)
+                    format!("{}::{}", Self::get_scope(parent), Id(this))
                 } else {
-                    this.to_string()
+                    Id(this).to_string()
                 }
             }
         }
@@ -693,11 +766,11 @@ impl PrettyPrint {
                     self.append(Self::get_scope(scope));
                     self.append("::");
                 }
-                self.append(format!("{}", id));
+                self.append(format!("{}", Id(id)));
             }
             HierId::Member(name, id) => {
                 self.print_hier_id(name);
-                self.append(format!(".{}", id));
+                self.append(format!(".{}", Id(id)));
             }
             HierId::Select(name, sel) => {
                 self.print_hier_id(name);
@@ -721,8 +794,20 @@ impl PrettyPrint {
                 match &**v {
                     TokenKind::IntegerLiteral(num) => self.append(format!("{}", num)),
                     TokenKind::UnbasedLiteral(val) => self.append(format!("'{}", val)),
-                    // TODO: Escape
-                    TokenKind::StringLiteral(str) => self.append(format!("\"{}\"", str)),
+                    TokenKind::StringLiteral(str) => {
+                        let mut text = String::from("\"");
+                        for ch in str.chars() {
+                            match ch {
+                                '"' => text.push_str("\\\""),
+                                '\\' => text.push_str("\\\\"),
+                                '\n' => text.push_str("\\n"),
+                                '\t' => text.push_str("\\t"),
+                                _ => text.push(ch),
+                            }
+                        }
+                        text.push('"');
+                        self.append(text);
+                    }
                     _ => {
                         eprintln!("{:?}", v);
                         unimplemented!();
@@ -938,6 +1023,48 @@ impl PrettyPrint {
                 self.append(") ");
                 self.print_stmt(body);
             }
+            StmtKind::While(cond, body) => {
+                self.append("while (");
+                self.print_expr(cond);
+                self.append(") ");
+                self.print_stmt(body);
+            }
+            StmtKind::DoWhile(body, cond) => {
+                self.append("do ");
+                self.print_stmt(body);
+                self.append(" while (");
+                self.print_expr(cond);
+                self.append(");");
+            }
+            StmtKind::Repeat(count, body) => {
+                self.append("repeat (");
+                self.print_expr(count);
+                self.append(") ");
+                self.print_stmt(body);
+            }
+            StmtKind::Forever(body) => {
+                self.append("forever ");
+                self.print_stmt(body);
+            }
+            StmtKind::Foreach(array, vars, body) => {
+                self.append("foreach (");
+                self.print_expr(array);
+                self.append("[");
+                let vars: Vec<_> = vars.iter().map(|var| var.as_ref().map_or("", |var| &var.value[..])).collect();
+                self.append(vars.join(", "));
+                self.append("]) ");
+                self.print_stmt(body);
+            }
+            StmtKind::Return(_, expr) => {
+                self.append("return");
+                if let Some(expr) = expr {
+                    self.append(" ");
+                    self.print_expr(expr);
+                }
+                self.append(";");
+            }
+            StmtKind::Break(_) => self.append("break;"),
+            StmtKind::Continue(_) => self.append("continue;"),
             StmtKind::Assert { kind: (), expr, success, failure } => {
                 self.append("assert (");
                 self.print_expr(expr);
@@ -953,7 +1080,7 @@ impl PrettyPrint {
             StmtKind::SeqBlock(list) => {
                 self.append("begin");
                 if let Some(v) = &obj.label {
-                    self.append(format!(": {}", v));
+                    self.append(format!(": {}", Id(v)));
                 }
                 self.append("\n");
                 self.indent();
@@ -189,6 +189,22 @@ impl Diagnostic {
             msg = msg.bold().to_string();
         }
 
+        // Show the chain of `include directives leading to the file
+        let mut pos = self.notes[0].span.start;
+        let mut first = true;
+        while let Some(from) = mgr.included_from(pos) {
+            let from_span = match mgr.find_span(from) {
+                None => break,
+                Some(v) => v,
+            };
+            let from_line = from_span.source.linemap().line_number(from_span.start);
+            let prefix = if first { "In file included from" } else { "                 from" };
+            let last = mgr.included_from(from.start).is_none();
+            eprintln!("{} {}:{}{}", prefix, from_span.source.filename(), from_line + 1, if last { ":" } else { "," });
+            first = false;
+            pos = from.start;
+        }
+
         // Allocate char vectors to hold indicators and hints
         // Make this 1 longer for possibility to point to the line break character.
         let mut indicators = vec![' '; vstr.visual_length() + 1];
@@ -7,7 +7,7 @@ use std::cmp;
 use std::usize;
 
 /// Represent a unique position within all source managed files.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Pos(pub usize);
 
 impl Pos {
@@ -18,7 +18,7 @@ impl Pos {
 }
 
 /// Represent a span within all source managed files.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Span {
     pub start: Pos,
     pub end: Pos
@@ -132,27 +132,40 @@ struct SrcMgrMut {
     end: Vec<usize>,
     /// Paths to search
     search_path: Vec<PathBuf>,
+    /// Paths to search for files included with `include <...>
+    system_search_path: Vec<PathBuf>,
+    /// Location of the `include directive that loaded each file, if any
+    included_from: Vec<Option<Span>>,
+    /// Paths of all files loaded from search paths
+    loaded: Vec<PathBuf>,
+    /// Positions from which locations are attributed to a different `Source`, as requested by
+    /// `line directives. Sorted by position.
+    remaps: Vec<(usize, Rc<Source>)>,
 }
 
 impl SrcMgrMut {
-    /// Lookup a file, load it, add it to source manager and return a Rc to it.
-    fn load_source(&mut self, filename: &str) -> Result<Rc<Source>, io::Error> {
-        let mut file = 'find_file: loop {
+    /// Lookup a file, load it, add it to source manager and return a Rc to it. `system` selects
+    /// the search path for `include <...>, and `from` is the location of the `include directive.
+    fn load_source(&mut self, filename: &str, system: bool, from: Span) -> Result<Rc<Source>, io::Error> {
+        let search_path = if system { &self.system_search_path } else { &self.search_path };
+        let (mut file, path) = 'find_file: loop {
             let mut err = None;
-            for path in &self.search_path {
+            for path in search_path {
                 let newpath = path.join(filename);
-                match ::std::fs::File::open(newpath) {
-                    Ok(f) => break 'find_file Ok(f),
+                match ::std::fs::File::open(&newpath) {
+                    Ok(f) => break 'find_file Ok((f, newpath)),
                     Err(e) => err = Some(e),
                 }
             }
-            break 'find_file Err(err.unwrap())
+            break 'find_file Err(err.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "search path is empty")))
         }?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
 
         let src = Rc::new(Source::new((*filename).to_owned(), contents));
         self.add_source(src.clone());
+        *self.included_from.last_mut().unwrap() = Some(from);
+        self.loaded.push(path);
         Ok(src)
     }
 
@@ -163,6 +176,14 @@ impl SrcMgrMut {
         let new_end = self.end.last().unwrap_or(&0) + src.content().len() + 1;
         self.end.push(new_end);
         self.files.push(src);
+        self.included_from.push(None);
+    }
+
+    /// Attribute positions starting from `pos` until the end of its file to another source,
+    /// which must share the same content.
+    fn remap_source(&mut self, pos: Pos, src: Rc<Source>) {
+        let index = self.remaps.partition_point(|(start, _)| *start <= pos.0);
+        self.remaps.insert(index, (pos.0, src));
     }
 
     /// Find the start and end index of a source file.
@@ -177,11 +198,9 @@ impl SrcMgrMut {
         None
     }
 
-    /// Find the source file and offset from position. If the position is out of bound,
-    /// calling find_pos will cause panic.
-    fn find_pos(&self, pos: Pos) -> FatPos {
-        let Pos(ipos) = pos;
-        let file_id = match self.end.binary_search(&ipos) {
+    /// Find the index of the file containing the position. Panic if the position is out of bound.
+    fn find_file_id(&self, pos: usize) -> usize {
+        let file_id = match self.end.binary_search(&pos) {
             // When we hit exactly, this is the start of next file
             Ok(result) => result + 1,
             // Otherwise it is in the middle of a file (or end of a file)
@@ -190,7 +209,20 @@ impl SrcMgrMut {
         if file_id == self.end.len() {
             panic!("position out of bound");
         }
+        file_id
+    }
+
+    /// Find the source file and offset from position. If the position is out of bound,
+    /// calling find_pos will cause panic.
+    fn find_pos(&self, pos: Pos) -> FatPos {
+        let Pos(ipos) = pos;
+        let file_id = self.find_file_id(ipos);
         let file_begin = if file_id == 0 { 0 } else { self.end[file_id - 1] };
+        // Use the last remapping of this file before the position, if any
+        let index = self.remaps.partition_point(|(start, _)| *start <= ipos);
+        if index > 0 && self.remaps[index - 1].0 >= file_begin {
+            return FatPos::new(self.remaps[index - 1].1.clone(), ipos - file_begin);
+        }
         FatPos::new(self.files[file_id].clone(), ipos - file_begin)
     }
 
@@ -217,24 +249,43 @@ pub struct SrcMgr {
 }
 
 impl SrcMgr {
-    pub fn new(search_path: Vec<PathBuf>) -> SrcMgr {
+    pub fn new(search_path: Vec<PathBuf>, system_search_path: Vec<PathBuf>) -> SrcMgr {
         SrcMgr {
             mutable: RefCell::new(SrcMgrMut {
                 files: Vec::new(),
                 end: Vec::new(),
                 search_path,
+                system_search_path,
+                included_from: Vec::new(),
+                loaded: Vec::new(),
+                remaps: Vec::new(),
             }),
         }
     }
 
-    pub fn load_source(&self, filename: &str) -> Result<Rc<Source>, io::Error> {
-        self.mutable.borrow_mut().load_source(filename)
+    pub fn load_source(&self, filename: &str, system: bool, from: Span) -> Result<Rc<Source>, io::Error> {
+        self.mutable.borrow_mut().load_source(filename, system, from)
+    }
+
+    /// Get the location of the `include directive that loaded the file containing the position.
+    pub fn included_from(&self, pos: Pos) -> Option<Span> {
+        let m = self.mutable.borrow();
+        m.included_from[m.find_file_id(pos.0)]
+    }
+
+    /// Get paths of all files loaded by `include so far.
+    pub fn loaded_files(&self) -> Vec<PathBuf> {
+        self.mutable.borrow().loaded.clone()
     }
 
     pub fn add_source(&self, src: Rc<Source>) {
         self.mutable.borrow_mut().add_source(src);
     }
 
+    pub fn remap_source(&self, pos: Pos, src: Rc<Source>) {
+        self.mutable.borrow_mut().remap_source(pos, src);
+    }
+
     pub fn find_src(&self, src: &Rc<Source>) -> Option<Span> {
         self.mutable.borrow().find_src(src)
     }
@@ -145,11 +145,17 @@ impl fmt::Display for IntAtomTy {
 pub enum IntVecTy {
     Bit,
     Logic,
+    /// The parser treats `reg` as `logic`, but this is needed when generating Verilog-2005.
+    Reg,
 }
 
 impl fmt::Display for IntVecTy {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", if self == &IntVecTy::Bit { "bit" } else { "logic" })
+        write!(f, "{}", match self {
+            IntVecTy::Bit => "bit",
+            IntVecTy::Logic => "logic",
+            IntVecTy::Reg => "reg",
+        })
     }
 }
 
@@ -451,7 +457,8 @@ pub enum Item {
     GenRegion(Vec<Item>),
     LoopGen(Box<LoopGen>),
     IfGen(Box<IfGen>),
-    SysTfCall(Box<SysTfCall>),
+    CaseGen(Box<CaseGen>),
+    SysTfCall(Box<Spanned<SysTfCall>>),
 
     ModportDecl(Option<Box<AttrInst>>, Vec<(Ident, Vec<ModportPortDecl>)>),
 
@@ -474,6 +481,62 @@ pub struct DesignDecl {
     pub param: Option<Vec<ParamDecl>>,
     pub port: Vec<PortDecl>,
     pub items: Vec<Item>,
+    /// Type of implicitly declared nets, as set by `default_nettype. None if implicit nets are
+    /// not allowed.
+    pub default_nettype: Option<NetTy>,
+    /// Time unit and precision set by `timescale, if any.
+    pub timescale: Option<Timescale>,
+}
+
+/// Time unit and precision, each represented as a power of 10 in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timescale {
+    pub unit: i32,
+    pub precision: i32,
+}
+
+impl Timescale {
+    /// Parse a time value such as `10ns`. Returns None if the magnitude is not 1, 10 or 100 or
+    /// the unit is unknown.
+    pub fn parse_time(text: &str) -> Option<i32> {
+        let split = text.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(text.len());
+        let magnitude = match &text[..split] {
+            "1" => 0,
+            "10" => 1,
+            "100" => 2,
+            _ => return None,
+        };
+        let unit = match text[split..].trim_start() {
+            "s" => 0,
+            "ms" => -3,
+            "us" => -6,
+            "ns" => -9,
+            "ps" => -12,
+            "fs" => -15,
+            _ => return None,
+        };
+        Some(unit + magnitude)
+    }
+
+    fn fmt_time(f: &mut fmt::Formatter, time: i32) -> fmt::Result {
+        let unit = if time >= 0 { 0 } else { (time - 2) / 3 * 3 };
+        write!(f, "1{}{}", "0".repeat((time - unit) as usize), match unit {
+            0 => "s",
+            -3 => "ms",
+            -6 => "us",
+            -9 => "ns",
+            -12 => "ps",
+            _ => "fs",
+        })
+    }
+}
+
+impl fmt::Display for Timescale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Self::fmt_time(f, self.unit)?;
+        write!(f, " / ")?;
+        Self::fmt_time(f, self.precision)
+    }
 }
 
 /// Declaration of package
@@ -780,6 +843,14 @@ pub struct IfGen {
     pub else_block: Option<Box<GenBlock>>,
 }
 
+#[derive(Debug, Clone)]
+pub struct CaseGen {
+    pub attr: Option<Box<AttrInst>>,
+    pub expr: Expr,
+    /// Case items. Default item has an empty list of expressions.
+    pub items: Vec<(Vec<Expr>, GenBlock)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct GenBlock {
     pub name: Option<Box<Ident>>,
@@ -808,6 +879,16 @@ pub enum StmtKind {
         update: Vec<Expr>,
         body: Box<Stmt>,
     },
+    While(Box<Expr>, Box<Stmt>),
+    DoWhile(Box<Stmt>, Box<Expr>),
+    Repeat(Box<Expr>, Box<Stmt>),
+    Forever(Box<Stmt>),
+    /// A foreach loop over an array. Loop variables of skipped dimensions are None.
+    Foreach(Box<Expr>, Vec<Option<Ident>>, Box<Stmt>),
+    /// Jump statements. The span is that of the keyword.
+    Return(Span, Option<Box<Expr>>),
+    Break(Span),
+    Continue(Span),
     Assert {
         /// Kind of assertion. Currently we only support simple_immediate_assertion so we leasve
         /// here (), but in the future we will extend it.
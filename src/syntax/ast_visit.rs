@@ -121,6 +121,17 @@ pub trait AstVisitor {
                     }
                 }
             }
+            Item::CaseGen(gen) => {
+                self.visit_expr(&mut gen.expr);
+                for (arm, block) in &mut gen.items {
+                    for expr in arm {
+                        self.visit_expr(expr);
+                    }
+                    for item in &mut block.items {
+                        self.visit_item(item);
+                    }
+                }
+            }
             Item::SysTfCall(call) => self.visit_sys_tf_call(call),
             Item::ModportDecl(_, decl) => {
                 for decl in decl {
@@ -288,12 +299,7 @@ pub trait AstVisitor {
         match &mut stmt.value {
             StmtKind::Empty => (),
             StmtKind::TimingCtrl(timing, substmt) => {
-                match timing {
-                    TimingCtrl::DelayCtrl(expr) => self.visit_expr(expr),
-                    TimingCtrl::ExprEventCtrl(_expr) => (), // TODO
-                    TimingCtrl::NameEventCtrl(..) => (),
-                    TimingCtrl::ImplicitEventCtrl => (),
-                }
+                self.visit_timing_ctrl(timing);
                 self.visit_stmt(substmt);
             }
             StmtKind::If(_, cond, tst, fst) => {
@@ -315,6 +321,22 @@ pub trait AstVisitor {
                 for expr in update { self.visit_expr(expr); }
                 self.visit_stmt(body);
             }
+            StmtKind::While(cond, body) |
+            StmtKind::Repeat(cond, body) |
+            StmtKind::DoWhile(body, cond) => {
+                self.visit_expr(cond);
+                self.visit_stmt(body);
+            }
+            StmtKind::Forever(body) => self.visit_stmt(body),
+            StmtKind::Foreach(array, _, body) => {
+                self.visit_expr(array);
+                self.visit_stmt(body);
+            }
+            StmtKind::Return(_, expr) => {
+                if let Some(expr) = expr { self.visit_expr(expr); }
+            }
+            StmtKind::Break(_) |
+            StmtKind::Continue(_) => (),
             StmtKind::Assert { expr, success, failure, .. } => {
                 self.visit_expr(expr);
                 if let Some(stmt) = success { self.visit_stmt(stmt) }
@@ -336,13 +358,33 @@ pub trait AstVisitor {
         self.do_visit_stmt(stmt);
     }
 
+    fn visit_timing_ctrl(&mut self, timing: &mut TimingCtrl) {
+        match timing {
+            TimingCtrl::DelayCtrl(expr) => self.visit_expr(expr),
+            TimingCtrl::ExprEventCtrl(expr) => self.visit_event_expr(expr),
+            TimingCtrl::NameEventCtrl(id) => self.visit_hier_name(id),
+            TimingCtrl::ImplicitEventCtrl => (),
+        }
+    }
+
+    fn visit_event_expr(&mut self, expr: &mut EventExpr) {
+        match expr {
+            EventExpr::Item(item) => {
+                self.visit_expr(&mut item.expr);
+                if let Some(iff) = &mut item.iff { self.visit_expr(iff); }
+            }
+            EventExpr::List(list) => for expr in list { self.visit_event_expr(expr) },
+            EventExpr::Paren(expr) => self.visit_event_expr(expr),
+        }
+    }
+
     fn visit_sys_tf_call(&mut self, call: &mut SysTfCall) {
         if let Some(v) = &mut call.args {
             self.visit_args(v);
         }
     }
 
-    fn visit_hier_name(&mut self, id: &mut HierId) {
+    fn do_visit_hier_name(&mut self, id: &mut HierId) {
         match id {
             HierId::Member(id, _) => {
                 self.visit_hier_name(id);
@@ -355,12 +397,16 @@ pub trait AstVisitor {
         }
     }
 
+    fn visit_hier_name(&mut self, id: &mut HierId) {
+        self.do_visit_hier_name(id);
+    }
+
     fn do_visit_expr(&mut self, expr: &mut Expr) {
         match &mut expr.value {
             ExprKind::Type(ty) => self.visit_ty(ty),
             ExprKind::Literal(_) => (),
             ExprKind::HierName(id) => self.visit_hier_name(id),
-            // EmptyQueue,
+            ExprKind::EmptyQueue => (),
             ExprKind::Concat(list, select) => {
                 for expr in list { self.visit_expr(expr); }
                 if let Some(select) = select {
@@ -402,7 +448,7 @@ pub trait AstVisitor {
                     self.visit_args(v);
                 }
             }
-            // ConstCast(Box<Expr>),
+            ExprKind::ConstCast(expr) => self.visit_expr(expr),
             ExprKind::SignCast(_, expr) => self.visit_expr(expr),
             ExprKind::TypeCast(target, expr) => {
                 self.visit_expr(target);
@@ -422,18 +468,21 @@ pub trait AstVisitor {
                 self.visit_expr(lhs);
                 self.visit_expr(rhs);
             }
-            // BinaryAssign(Box<Expr>, BinaryOp, Box<Expr>),
+            ExprKind::BinaryAssign(lhs, _, rhs) => {
+                self.visit_expr(lhs);
+                self.visit_expr(rhs);
+            }
             ExprKind::Paren(expr) => self.visit_expr(expr),
-            // MinTypMax(Box<Expr>, Box<Expr>, Box<Expr>),
+            ExprKind::MinTypMax(min, typ, max) => {
+                self.visit_expr(min);
+                self.visit_expr(typ);
+                self.visit_expr(max);
+            }
             ExprKind::Cond(cond, _, t, f) => {
                 self.visit_expr(cond);
                 self.visit_expr(t);
                 self.visit_expr(f);
             }
-            v => {
-                eprintln!("{:?}", v);
-                unimplemented!();
-            }
         }
     }
 
@@ -2,6 +2,29 @@ use std::collections::HashMap;
 use super::tokens::{Keyword, TokenKind};
 use super::ast::*;
 
+/// Keyword set that contains all keywords.
+pub const LATEST: u8 = 8;
+
+/// Get the keyword set for a version specifier used by `begin_keywords.
+pub fn keyword_set(version: &str) -> Option<u8> {
+    Some(match version {
+        "1364-1995" => 1,
+        "1364-2001-noconfig" => 2,
+        "1364-2001" => 3,
+        "1364-2005" => 4,
+        "1800-2005" => 5,
+        "1800-2009" => 6,
+        "1800-2012" => 7,
+        "1800-2017" => 8,
+        _ => return None,
+    })
+}
+
+/// Check if a name is a keyword in any keyword set.
+pub fn is_keyword(name: &str) -> bool {
+    HASHMAP.contains_key(name)
+}
+
 lazy_static!{
 pub static ref HASHMAP: HashMap<&'static str, (TokenKind, u8)> = {
     let mut m = HashMap::new();
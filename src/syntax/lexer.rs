@@ -1,7 +1,7 @@
 use super::tokens::*;
 use super::ast::*;
 
-use super::kw_map::HASHMAP;
+use super::kw_map::{self, HASHMAP};
 use super::super::source::{Source, SrcMgr, Diagnostic, DiagMgr, Severity, Pos};
 use super::super::number::{LogicValue, LogicVec, LogicNumber};
 
@@ -21,7 +21,7 @@ pub struct Lexer<'a> {
     // Start of current token
     start: usize,
     // 0 -> nothing should be treated as keyword
-    // 1, 2, 3, 4 -> Verilog 95, 01, 01-noconfig, 05
+    // 1, 2, 3, 4 -> Verilog 95, 01-noconfig, 01, 05
     // 5, 6, 7, 8 -> SystemVerilog 05, 09, 12, 17
     keyword: u8,
     keyword_stack: Vec<u8>,
@@ -37,12 +37,17 @@ impl<'a> Lexer<'a> {
             mgr: mgr,
             pos: 0,
             start: 0,
-            keyword: 8,
+            keyword: kw_map::LATEST,
             keyword_stack: Vec::new(),
             attr: false,
         }
     }
 
+    /// Select the set of keywords to recognise
+    pub fn set_keywords(&mut self, v: u8) {
+        self.keyword = v;
+    }
+
     /// Enter a new keyword scope
     pub fn enter_kw_scope(&mut self, v: u8) {
         self.keyword_stack.push(self.keyword);
@@ -729,7 +734,19 @@ impl<'a> Lexer<'a> {
                 }
             }
             '\\' => {
-                TokenKind::Id(self.parse_esc_id())
+                match self.peekch() {
+                    // Line continuation, as used in macro definitions
+                    Some('\n') => {
+                        self.nextch();
+                        TokenKind::Whitespace
+                    }
+                    Some('\r') => {
+                        self.nextch();
+                        self.skip_crlf();
+                        TokenKind::Whitespace
+                    }
+                    _ => TokenKind::Id(self.parse_esc_id()),
+                }
             }
             '`' => {
                 match self.peekch().unwrap_or(' ') {
@@ -744,7 +761,8 @@ impl<'a> Lexer<'a> {
                     }
                     '\\' => {
                         self.nextch();
-                        if self.peekch() == Some('`') && self.peekch() == Some('"') {
+                        if self.src_text[self.pos..].starts_with("`\"") {
+                            self.pos += 2;
                             TokenKind::Directive("\\`\"".to_owned())
                         } else {
                             self.pos = self.start + 1;
@@ -7,7 +7,8 @@ mod pp;
 mod tk_tree;
 mod parser;
 
-pub use self::pp::pp;
+pub use self::pp::{pp, Preprocessor};
 pub use self::tk_tree::tk_tree;
-pub use self::parser::parse;
+pub use self::parser::{parse, parse_param_override};
+pub use self::kw_map::is_keyword;
 
@@ -10,11 +10,27 @@ pub fn parse<'a>(diag: &'a DiagMgr, lexer: VecDeque<Token>) -> Vec<Item> {
     Parser::new(diag, lexer).parse_source()
 }
 
+/// Parse a parameter override of form `NAME=VALUE`, as given on the command line.
+pub fn parse_param_override(diag: &DiagMgr, lexer: VecDeque<Token>) -> (Ident, Expr) {
+    let mut parser = Parser::new(diag, lexer);
+    let name = parser.expect_id();
+    parser.expect(TokenKind::Assign);
+    let value = parser.parse_expr();
+    parser.expect_eof();
+    (name, value)
+}
+
 struct Parser<'a> {
     diag: &'a DiagMgr,
     lexer: VecDeque<Token>,
     eof: Token,
     leq_as_assign: bool,
+    /// Net type set by `default_nettype, or None if implicit nets are not allowed.
+    default_nettype: Option<NetTy>,
+    /// Timescale set by `timescale.
+    timescale: Option<Timescale>,
+    /// Whether a design element is being parsed.
+    in_design: bool,
 }
 
 //
@@ -81,6 +97,9 @@ impl<'a> Parser<'a> {
             lexer: lexer,
             eof: Spanned::new(TokenKind::Eof, last_pos.span_to(last_pos)),
             leq_as_assign: false,
+            default_nettype: Some(NetTy::Wire),
+            timescale: None,
+            in_design: false,
         }
     }
 
@@ -113,7 +132,7 @@ impl<'a> Parser<'a> {
     /// Parse a delimited group of tokens. After calling the callback, the token stream must
     /// be empty.
     fn delim_group<T, F: FnMut(&mut Self) -> T>(
-        &mut self, mut stream: Box<DelimGroup>, mut f: F
+        &mut self, mut stream: DelimGroup, mut f: F
     ) -> T {
         let mut delim_eof = Spanned::new(TokenKind::Eof, stream.close.span);
         mem::swap(&mut self.lexer, &mut stream.tokens);
@@ -186,10 +205,8 @@ impl<'a> Parser<'a> {
                 let span = self.peek().span.clone();
                 self.diag.report_span(Severity::Error, format!("expected open delimiter {:#?}", expected), span.clone());
                 // Error recovery
-                let fake_open = Spanned::new(TokenKind::Unknown, span);
                 let fake_close = Spanned::new(TokenKind::Unknown, span);
                 Box::new(DelimGroup {
-                    open: fake_open,
                     close: fake_close,
                     tokens: VecDeque::new(),
                 })
@@ -277,7 +294,7 @@ impl<'a> Parser<'a> {
         &mut self, delim: Delim, f: F
     ) -> T {
         let delim = self.expect_delim(delim);
-        self.delim_group(delim, f)
+        self.delim_group(*delim, f)
     }
 
     /// Expect the next token tree to be a delimited group, parse it with given function.
@@ -286,7 +303,7 @@ impl<'a> Parser<'a> {
     ) -> Spanned<T> {
         let span = self.peek().span;
         let delim = self.expect_delim(delim);
-        Spanned::new(self.delim_group(delim, f), span)
+        Spanned::new(self.delim_group(*delim, f), span)
     }
 
 
@@ -297,7 +314,7 @@ impl<'a> Parser<'a> {
     ) -> Option<T> {
         match self.consume_if_delim(delim) {
             None => None,
-            Some(v) => Some(self.delim_group(v, f)),
+            Some(v) => Some(self.delim_group(*v, f)),
         }
     }
 
@@ -312,7 +329,7 @@ impl<'a> Parser<'a> {
         }
         let token = self.consume();
         if let TokenKind::DelimGroup(_, grp) = token.value {
-            Some(Spanned::new(self.delim_group(grp, f), token.span))
+            Some(Spanned::new(self.delim_group(*grp, f), token.span))
         } else {
             unreachable!();
         }
@@ -534,6 +551,9 @@ impl<'a> Parser<'a> {
     /// extern primitive
     /// ```
     fn parse_item_opt(&mut self) -> Option<Item> {
+        while let TokenKind::Directive(_) = **self.peek() {
+            self.parse_directive();
+        }
         let attr = self.parse_attr_inst_opt();
         match self.peek().value {
             TokenKind::Eof |
@@ -624,10 +644,7 @@ impl<'a> Parser<'a> {
             // if_generate_construct
             TokenKind::Keyword(Keyword::If) => Some(self.parse_if_gen(attr)),
             // case_generate_construct
-            TokenKind::CaseKw(CaseKw::Case) => {
-                let span = self.peek().span;
-                self.diag.report_fatal("case_generate_construct is not supported", span);
-            }
+            TokenKind::CaseKw(CaseKw::Case) => Some(self.parse_case_gen(attr)),
             // elaboration_system_task
             TokenKind::SystemTask(_) => {
                 // First parse as a standard system tf call.
@@ -725,6 +742,42 @@ impl<'a> Parser<'a> {
         list
     }
 
+    /// Parse a compiler directive passed through by the preprocessor, which is `resetall,
+    /// `default_nettype followed by its argument, or `timescale followed by time literals of
+    /// the unit and precision.
+    fn parse_directive(&mut self) {
+        let (name, span) = match self.consume() {
+            Spanned{value: TokenKind::Directive(name), span} => (name, span),
+            _ => unreachable!(),
+        };
+        let (mut netty, mut timescale) = (self.default_nettype, self.timescale);
+        match name.as_ref() {
+            "resetall" => {
+                netty = Some(NetTy::Wire);
+                timescale = None;
+            }
+            // The preprocessor has checked that the argument is either a net type or none
+            "default_nettype" => netty = match self.consume().value {
+                TokenKind::NetTy(netty) => Some(netty),
+                _ => None,
+            },
+            "timescale" => {
+                let mut time = || match self.consume().value {
+                    TokenKind::TimeLiteral(v) => v.log10().round() as i32,
+                    _ => unreachable!(),
+                };
+                timescale = Some(Timescale { unit: time(), precision: time() });
+            }
+            _ => unreachable!(),
+        }
+        if self.in_design {
+            self.diag.report_error(format!("`{} cannot be used within design elements", name), span);
+            return;
+        }
+        self.default_nettype = netty;
+        self.timescale = timescale;
+    }
+
     /// Parse a end identifier annotation. Raises error for mismatch
     fn parse_end_annotation(&mut self, exp: Option<&Ident>) {
         if self.check(TokenKind::Colon) {
@@ -780,15 +833,17 @@ impl<'a> Parser<'a> {
     /// TODO: We will need to check if items can legally appear in here.
     fn parse_design_unit(&mut self, attr: Option<Box<AttrInst>>, kw: Keyword, end_kw: Keyword) -> DesignDecl {
         self.consume();
+        let in_design = mem::replace(&mut self.in_design, true);
         let lifetime = self.parse_lifetime();
         let name = self.expect_id();
         let pkg_import = self.parse_list(Self::parse_pkg_import_decl_opt);
         let param = self.parse_param_port_list();
-        let port = self.parse_port_list();
+        let port = self.parse_port_list(false);
         self.expect(TokenKind::Semicolon);
         let items = self.parse_list(Self::parse_item_opt);
         self.expect(TokenKind::Keyword(end_kw));
         self.parse_end_annotation(Some(&name));
+        self.in_design = in_design;
         DesignDecl {
             attr,
             kw,
@@ -798,6 +853,8 @@ impl<'a> Parser<'a> {
             param,
             port: port.unwrap_or_else(|| Vec::new()),
             items: items,
+            default_nettype: self.default_nettype,
+            timescale: self.timescale,
         }
     }
 
@@ -908,7 +965,16 @@ impl<'a> Parser<'a> {
     /// | [ variable_port_header ] port_identifier { variable_dimension } [ = constant_expression ]
     /// | [ port_direction ] . port_identifier ( [ expression ] )
     /// ```
-    fn parse_port_list(&mut self) -> Option<Vec<PortDecl>> {
+    ///
+    /// If `tf` is set, this parses the port list of a function or task instead, where ports
+    /// default to input and are never interface ports.
+    /// ```bnf
+    /// tf_port_list ::= tf_port_item { , tf_port_item }
+    /// tf_port_item ::=
+    ///   { attribute_instance } [ tf_port_direction ] [ var ] data_type_or_implicit
+    ///     [ port_identifier { variable_dimension } [ = expression ] ]
+    /// ```
+    fn parse_port_list(&mut self, tf: bool) -> Option<Vec<PortDecl>> {
         self.parse_if_delim(Delim::Paren, |this| {
             if let Some(v) = this.consume_if(TokenKind::WildPattern) {
                 this.diag.report_fatal("(.*) port declaration is not supported", v.span);
@@ -932,7 +998,7 @@ impl<'a> Parser<'a> {
                 let dir = this.parse_port_dir();
 
                 // Could only appear in non-ANSI declaration
-                if prev.is_none() {
+                if prev.is_none() && !tf {
                     match this.peek().value {
                         TokenKind::DelimGroup(Delim::Brace, _) => {
                             ansi = false;
@@ -942,8 +1008,8 @@ impl<'a> Parser<'a> {
                     }
                 }
 
-                // Explicit port declaration
-                if let Some(_) = this.consume_if(TokenKind::Dot) {
+                // Explicit port declaration, which cannot appear in subroutines
+                if !tf && this.consume_if(TokenKind::Dot).is_some() {
                     let name = Box::new(this.expect_id());
                     let expr = Box::new(this.parse_unwrap(|this| {
                         this.parse_delim(Delim::Paren, Self::parse_expr_opt)
@@ -969,7 +1035,9 @@ impl<'a> Parser<'a> {
                 // there is no direction, as we can easily convert if our guess is incorrect. The
                 // otherway around is a bit harder.
                 // If both none, then there is a chance that this is an interface port
-                let is_intf = if let TokenKind::Keyword(Keyword::Interface) = **this.peek() {
+                let is_intf = if tf {
+                    None
+                } else if let TokenKind::Keyword(Keyword::Interface) = **this.peek() {
                     // Okay, this is definitely an interface port
                     this.consume();
                     if this.consume_if(TokenKind::Dot).is_some() {
@@ -1035,7 +1103,7 @@ impl<'a> Parser<'a> {
                 let (dtype, assign) = this.parse_data_type_decl_assign();
 
                 // If they are all none, it means this is an ANSI port.
-                if dir.is_none() && net.is_none() && dtype.is_none() && prev.is_none() {
+                if dir.is_none() && net.is_none() && dtype.is_none() && prev.is_none() && !tf {
                     ansi = false;
                     return false;
                 }
@@ -1053,10 +1121,10 @@ impl<'a> Parser<'a> {
                     }
                 }
 
-                // If not specified, default to inout
+                // If not specified, default to inout, or input for subroutines
                 let dir = dir.unwrap_or_else(|| {
                     match prev {
-                        None | Some(PortDecl::Interface(..)) => PortDir::Inout,
+                        None | Some(PortDecl::Interface(..)) => if tf { PortDir::Input } else { PortDir::Inout },
                         Some(PortDecl::Data(dir, ..)) | Some(PortDecl::Explicit(dir, ..)) => dir,
                     }
                 });
@@ -1910,8 +1978,7 @@ impl<'a> Parser<'a> {
         let ty = self.parse_data_type();
         // TODO: [ interface_identifier . | class_scope ]
         let name = self.expect_id();
-        // TODO: tf_port is different from module port
-        let ports = self.parse_port_list();
+        let ports = self.parse_port_list(true);
         self.expect(TokenKind::Semicolon);
         let stmts = self.parse_list(Self::parse_stmt_opt);
         self.expect(TokenKind::Keyword(Keyword::Endfunction));
@@ -1935,8 +2002,7 @@ impl<'a> Parser<'a> {
         let lifetime = self.parse_lifetime();
         // TODO: [ interface_identifier . | class_scope ]
         let name = self.expect_id();
-        // TODO: tf_port is different from module port
-        let ports = self.parse_port_list();
+        let ports = self.parse_port_list(true);
         self.expect(TokenKind::Semicolon);
         let stmts = self.parse_list(Self::parse_stmt_opt);
         self.expect(TokenKind::Keyword(Keyword::Endtask));
@@ -2311,6 +2377,45 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parse a case_generate_construct
+    /// ```bnf
+    /// case_generate_construct ::=
+    ///   case ( constant_expression ) case_generate_item { case_generate_item } endcase
+    /// case_generate_item ::=
+    ///   constant_expression { , constant_expression } : generate_block
+    /// | default [ : ] generate_block
+    /// ```
+    fn parse_case_gen(&mut self, attr: Option<Box<AttrInst>>) -> Item {
+        // Eat the case keyword
+        self.consume();
+        let expr = self.parse_delim(Delim::Paren, Self::parse_expr);
+        let items = self.parse_list(|this| {
+            let arm = match **this.peek() {
+                TokenKind::Keyword(Keyword::Endcase) => return None,
+                TokenKind::Keyword(Keyword::Default) => {
+                    // Default match arm, colon is optional
+                    this.consume();
+                    this.check(TokenKind::Colon);
+                    Vec::new()
+                }
+                _ => {
+                    // Expression match arms
+                    let arm = this.parse_comma_list(false, false, Self::parse_expr_opt);
+                    this.expect(TokenKind::Colon);
+                    arm
+                }
+            };
+            let block = this.parse_gen_block();
+            Some((arm, block))
+        });
+        self.expect(TokenKind::Keyword(Keyword::Endcase));
+        Item::CaseGen(Box::new(CaseGen {
+            attr,
+            expr,
+            items,
+        }))
+    }
+
     fn parse_gen_block(&mut self) -> GenBlock {
         // A generate-block may begin with a label. It is treated as same as label after begin.
         let label = if let TokenKind::Id(_) = **self.peek() {
@@ -2530,14 +2635,14 @@ impl<'a> Parser<'a> {
             // loop_statement
             TokenKind::Keyword(Keyword::Forever) |
             TokenKind::Keyword(Keyword::Repeat) |
-            TokenKind::Keyword(Keyword::While) => self.unimplemented(),
+            TokenKind::Keyword(Keyword::While) => self.parse_loop(),
             TokenKind::Keyword(Keyword::For) => self.parse_for(),
-            TokenKind::Keyword(Keyword::Do) |
-            TokenKind::Keyword(Keyword::Foreach) => self.unimplemented(),
+            TokenKind::Keyword(Keyword::Do) => self.parse_do_while(),
+            TokenKind::Keyword(Keyword::Foreach) => self.parse_foreach(),
             // jump_statement
             TokenKind::Keyword(Keyword::Return) |
             TokenKind::Keyword(Keyword::Break) |
-            TokenKind::Keyword(Keyword::Continue) => self.unimplemented(),
+            TokenKind::Keyword(Keyword::Continue) => self.parse_jump(),
             // par_block
             TokenKind::Keyword(Keyword::Fork) => self.unimplemented(),
             // procedural_timing_control_statement
@@ -2707,6 +2812,22 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // ```bnf
+    /// jump_statement ::=
+    ///   return [ expression ] ; | break ; | continue ;
+    // ```
+    fn parse_jump(&mut self) -> StmtKind {
+        let token = self.consume();
+        let kind = match *token {
+            TokenKind::Keyword(Keyword::Return) => StmtKind::Return(token.span, self.parse_expr_opt().map(Box::new)),
+            TokenKind::Keyword(Keyword::Break) => StmtKind::Break(token.span),
+            TokenKind::Keyword(Keyword::Continue) => StmtKind::Continue(token.span),
+            _ => unreachable!(),
+        };
+        self.expect(TokenKind::Semicolon);
+        kind
+    }
+
     //
     // A.6.6 Conditional statements
     //
@@ -2864,6 +2985,78 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parse `while`, `repeat` and `forever` loops, whose body follows an optional condition.
+    fn parse_loop(&mut self) -> StmtKind {
+        let kw = if let TokenKind::Keyword(kw) = *self.consume() { kw } else { unreachable!() };
+        let cond = match kw {
+            Keyword::Forever => None,
+            _ => Some(Box::new(self.parse_delim(Delim::Paren, Self::parse_expr))),
+        };
+        let body = Box::new(self.parse_stmt());
+        match kw {
+            Keyword::While => StmtKind::While(cond.unwrap(), body),
+            Keyword::Repeat => StmtKind::Repeat(cond.unwrap(), body),
+            _ => StmtKind::Forever(body),
+        }
+    }
+
+    // ```bnf
+    /// loop_statement ::=
+    ///   ... | foreach ( ps_or_hierarchical_array_identifier [ loop_variables ] ) statement
+    /// loop_variables ::=
+    ///   [ index_variable_identifier ] { , [ index_variable_identifier ] }
+    // ```
+    fn parse_foreach(&mut self) -> StmtKind {
+        self.consume();
+        let (array, vars) = self.parse_delim(Delim::Paren, |this| {
+            let scope = this.parse_scope();
+            let span = this.peek().span;
+            let mut id = Spanned::new(HierId::Name(scope, Box::new(this.expect_id())), span);
+            // Loop variables look like a select, so only brackets not at the end are selects.
+            loop {
+                match **this.peek() {
+                    TokenKind::DelimGroup(Delim::Bracket, _) => {
+                        if let TokenKind::Eof = **this.peek_n(1) { break }
+                        let sel = this.parse_dim_opt().unwrap();
+                        let span = id.span.merge(sel.span);
+                        id = Spanned::new(HierId::Select(Box::new(id), Box::new(sel)), span);
+                    }
+                    TokenKind::Dot => {
+                        this.consume();
+                        let subid = this.expect_id();
+                        let span = id.span.merge(subid.span);
+                        id = Spanned::new(HierId::Member(Box::new(id), Box::new(subid)), span);
+                    }
+                    _ => break,
+                }
+            }
+            let array = Spanned::new(ExprKind::HierName(id.value), id.span);
+            let vars = this.parse_delim(Delim::Bracket, |this| {
+                let mut vars = Vec::new();
+                loop {
+                    vars.push(match **this.peek() {
+                        TokenKind::Id(_) => Some(this.expect_id()),
+                        _ => None,
+                    });
+                    if !this.check(TokenKind::Comma) { break }
+                }
+                vars
+            });
+            (array, vars)
+        });
+        let body = Box::new(self.parse_stmt());
+        StmtKind::Foreach(Box::new(array), vars, body)
+    }
+
+    fn parse_do_while(&mut self) -> StmtKind {
+        self.consume();
+        let body = Box::new(self.parse_stmt());
+        self.expect(TokenKind::Keyword(Keyword::While));
+        let cond = Box::new(self.parse_delim(Delim::Paren, Self::parse_expr));
+        self.expect(TokenKind::Semicolon);
+        StmtKind::DoWhile(body, cond)
+    }
+
     //
     // A.6.7.1 Patterns
     //
@@ -2931,7 +3124,7 @@ impl<'a> Parser<'a> {
     // A.8.2 Subroutine calls
     //
 
-    fn parse_sys_tf_call(&mut self) -> SysTfCall {
+    fn parse_sys_tf_call(&mut self) -> Spanned<SysTfCall> {
         let task = {
             let token = self.consume();
             match token.value {
@@ -2939,11 +3132,16 @@ impl<'a> Parser<'a> {
                 _ => unreachable!(),
             }
         };
+        // The call spans from the task name to the closing parenthesis, if any.
+        let span = match **self.peek() {
+            TokenKind::DelimGroup(Delim::Paren, _) => task.span.merge(self.peek().span),
+            _ => task.span,
+        };
         let args = self.parse_args_opt(false);
-        SysTfCall {
+        Spanned::new(SysTfCall {
             task,
             args,
-        }
+        }, span)
     }
 
     //
@@ -3105,8 +3303,9 @@ impl<'a> Parser<'a> {
 
         loop {
 
+            let leq_as_assign = self.leq_as_assign;
             let (op, new_prec) = match **self.peek() {
-                TokenKind::BinaryOp(BinaryOp::Leq) if self.leq_as_assign => break,
+                TokenKind::BinaryOp(BinaryOp::Leq) if leq_as_assign => break,
                 TokenKind::BinaryOp(op) => {
                     let new_prec = Self::get_bin_op_prec(op);
                     // Can only proceed if precedence is higher
@@ -3343,9 +3542,7 @@ impl<'a> Parser<'a> {
             // system_tf_call
             TokenKind::SystemTask(_) => {
                 let tf = self.parse_sys_tf_call();
-                // IMP: better span
-                let span = tf.task.span.start.span_to(self.peek().span.start);
-                Some(Spanned::new(ExprKind::SysTfCall(Box::new(tf)), span))
+                Some(Spanned::new(ExprKind::SysTfCall(Box::new(tf.value)), tf.span))
             }
             // The left-over possibilities are:
             // [ class_qualifier | package_scope ] hierarchical_identifier select
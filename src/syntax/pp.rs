@@ -1,37 +1,133 @@
 use super::tokens::*;
 use super::ast::*;
 
-use source::{Source, SrcMgr, DiagMgr, Severity, Span};
+use source::{Source, SrcMgr, DiagMgr, Severity, Pos, Span};
 use super::lexer::Lexer;
+use super::kw_map;
 
 use std::rc::Rc;
 use std::collections::VecDeque;
 use std::collections::HashMap;
 
+/// A macro definition: location of the name, formal arguments with their defaults, and body.
+type Macro = (Span, Option<Vec<(Spanned<String>, Option<Vec<Token>>)>>, VecDeque<Token>);
+
 pub fn pp<'a>(mgr: &'a SrcMgr, diag: &'a DiagMgr, src: &Rc<Source>) -> VecDeque<Token> {
     Preprocessor::new(mgr, diag).all(src)
 }
 
-struct Preprocessor<'a> {
+/// Preprocessor state. Macro definitions persist across all files processed by the same
+/// preprocessor, as they all belong to the same compilation unit.
+pub struct Preprocessor<'a> {
     mgr: &'a SrcMgr,
     diag: &'a DiagMgr,
     /// Buffer for tokens pushed back
     pushback: Vec<Token>,
     stacks: Vec<Lexer<'a>>,
-    macros: HashMap<String, (Span, Option<Vec<(Spanned<String>, Option<Vec<Token>>)>>, VecDeque<Token>)>,
+    macros: HashMap<String, Macro>,
+    /// Macros defined and undefined on the command line in order, which are applied again at
+    /// the start of each file.
+    cmdline_macros: Vec<(String, Option<Macro>)>,
     /// A branch stack indicating whether previous branch is taken and whether an else is encountered
     branch_stack: Vec<(bool, bool)>,
+    /// Whether the output is turned back into text. If set, tokens from macro expansion keep
+    /// their original spans and are enclosed in a pair of empty directives.
+    text_mode: bool,
+    /// Whether only whitespace is seen since the last newline
+    after_newline: bool,
+    /// Stack of keyword sets selected by `begin_keywords. The bottom is the default set.
+    keywords: Vec<u8>,
+    /// The last `default_nettype directive and its argument, which still applies. It is passed
+    /// to the parser again at the start of each file.
+    default_nettype: Option<(Token, Token)>,
+    /// The last `timescale directive, which also persists across files.
+    timescale: Option<(Span, Timescale)>,
 }
 
 impl<'a> Preprocessor<'a> {
-    fn new(mgr: &'a SrcMgr, diag: &'a DiagMgr) -> Preprocessor<'a> {
+    pub fn new(mgr: &'a SrcMgr, diag: &'a DiagMgr) -> Preprocessor<'a> {
         Preprocessor {
             mgr,
             diag,
             pushback: Vec::new(),
             stacks: Vec::new(),
             macros: HashMap::new(),
+            cmdline_macros: Vec::new(),
             branch_stack: Vec::new(),
+            text_mode: false,
+            after_newline: true,
+            keywords: vec![kw_map::LATEST],
+            default_nettype: None,
+            timescale: None,
+        }
+    }
+
+    /// Set the keyword set used outside `begin_keywords, given as a version specifier such as
+    /// "1364-2001". Returns false if the version is not recognised.
+    pub fn set_default_keywords(&mut self, version: &str) -> bool {
+        match kw_map::keyword_set(version) {
+            Some(v) => {
+                self.keywords[0] = v;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Create a lexer that uses the current keyword set.
+    fn new_lexer(&self, src: &Rc<Source>) -> Lexer<'a> {
+        let mut lexer = Lexer::new(self.mgr, self.diag, src);
+        lexer.set_keywords(*self.keywords.last().unwrap());
+        lexer
+    }
+
+    /// Define a macro given on the command line in the form of `NAME[=VALUE]`. The name may be
+    /// followed by a formal argument list.
+    pub fn define(&mut self, def: &str) {
+        // Find the = that is not within the formal argument list
+        let mut level = 0;
+        let mut split = def.len();
+        for (i, ch) in def.char_indices() {
+            match ch {
+                '(' => level += 1,
+                ')' => level -= 1,
+                '=' if level == 0 => {
+                    split = i;
+                    break;
+                }
+                _ => (),
+            }
+        }
+        let value = def.get(split + 1..).unwrap_or("");
+        let src = Rc::new(Source::new("<command line>".to_owned(), format!("{} {}", &def[..split], value)));
+        self.mgr.add_source(src.clone());
+        let span = self.mgr.find_src(&src).unwrap();
+        let span = Span::new(span.start, Pos(span.end.0 - 1));
+
+        // Parse it as the text following `define
+        self.stacks.push(self.new_lexer(&src));
+        if let Some(name) = self.parse_define(span) {
+            let def = self.macros[&name].clone();
+            self.cmdline_macros.push((name, Some(def)));
+        }
+        self.stacks.clear();
+        self.pushback.clear();
+    }
+
+    /// Undefine a macro given on the command line.
+    pub fn undef(&mut self, name: &str) {
+        self.macros.remove(name);
+        self.cmdline_macros.push((name.to_owned(), None));
+    }
+
+    /// Apply macros given on the command line again, so they are not affected by `define and
+    /// `undef in earlier files.
+    fn apply_cmdline_macros(&mut self) {
+        for (name, def) in &self.cmdline_macros {
+            match def {
+                Some(def) => { self.macros.insert(name.clone(), def.clone()); }
+                None => { self.macros.remove(name); }
+            }
         }
     }
 
@@ -95,7 +191,6 @@ impl<'a> Preprocessor<'a> {
     }
 
     fn process(&mut self) -> Option<Token> {
-        let mut after_newline = false;
         loop {
             let (name, span) = match self.next_raw() {
                 // Found a directive
@@ -103,16 +198,24 @@ impl<'a> Preprocessor<'a> {
                 // Newline token, set after_newline and continue
                 Some(Spanned{value: TokenKind::NewLine, ..}) |
                 Some(Spanned{value: TokenKind::LineComment, ..}) => {
-                    after_newline = true;
+                    self.after_newline = true;
                     continue;
                 }
                 // Not a directive, just return as-is
-                v => return v,
+                v => {
+                    self.after_newline = false;
+                    return v;
+                }
             };
 
+            let after_newline = ::std::mem::replace(&mut self.after_newline, false);
             match name.as_ref() {
+                // `resetall does not affect macro definitions. The parser needs to see it to
+                // reset the default net type and timescale.
                 "resetall" => {
-                    self.diag.report_span(Severity::Warning, "compiler directive not yet supported", span);
+                    self.default_nettype = None;
+                    self.timescale = None;
+                    return Some(Spanned::new(TokenKind::Directive(name), span));
                 }
                 "include" => {
                     if !after_newline {
@@ -120,156 +223,409 @@ impl<'a> Preprocessor<'a> {
                     }
                     self.parse_include(span);
                 }
-                "define" => self.parse_define(span),
-                "undef" |
-                "undefineall" => {
-                    self.diag.report_span(Severity::Warning, "compiler directive not yet supported", span);
-                }
+                "define" => { self.parse_define(span); }
+                "undef" => self.parse_undef(span),
+                "undefineall" => self.macros.clear(),
                 "ifdef" => self.parse_ifdef(span, true),
                 "ifndef" => self.parse_ifdef(span, false),
                 "else" => self.parse_else(span),
                 "elsif" => self.parse_elsif(span),
                 "endif" => self.parse_endif(span),
-                "timescale" |
-                "default_nettype" |
+                // The directive and its argument are passed to the parser
+                "default_nettype" => {
+                    let valid = match self.peek_raw() {
+                        Some(Spanned{value: TokenKind::NetTy(NetTy::Supply0), ..}) |
+                        Some(Spanned{value: TokenKind::NetTy(NetTy::Supply1), ..}) => false,
+                        Some(Spanned{value: TokenKind::NetTy(_), ..}) => true,
+                        Some(Spanned{value: TokenKind::Id(id), ..}) => id == "none",
+                        _ => false,
+                    };
+                    if !valid {
+                        self.diag.report_error("expected net type or none after `default_nettype", span);
+                        self.read_until_newline();
+                        continue;
+                    }
+                    let tok = Spanned::new(TokenKind::Directive(name), span);
+                    let arg = self.peek_raw().unwrap().clone();
+                    self.default_nettype = Some((tok.clone(), arg));
+                    return Some(tok);
+                }
+                "timescale" => {
+                    let list: Vec<_> = self.read_until_newline().into_iter().collect();
+                    let timescale = match self.parse_timescale(span, &list) {
+                        Some(v) => v,
+                        None => continue,
+                    };
+                    self.timescale = Some((span, timescale));
+                    if self.text_mode {
+                        // Keep the directive as written
+                        for tok in list.into_iter().rev() {
+                            self.pushback.push(tok);
+                        }
+                        return Some(Spanned::new(TokenKind::Directive(name), span));
+                    }
+                    let mut list = Self::timescale_tokens(span, timescale);
+                    while list.len() > 1 {
+                        self.pushback.push(list.pop().unwrap());
+                    }
+                    return list.pop();
+                }
+                "begin_keywords" => self.parse_begin_keywords(span),
+                "end_keywords" => {
+                    if self.keywords.len() == 1 {
+                        self.diag.report_error("`end_keywords without matching `begin_keywords", span);
+                    } else {
+                        self.keywords.pop();
+                        let v = *self.keywords.last().unwrap();
+                        self.stacks.last_mut().unwrap().set_keywords(v);
+                    }
+                }
                 "unconnected_drive" |
                 "nounconnected_drive" |
                 "celldefine" |
                 "endcelldefine" |
-                "pragma" |
-                "line" |
-                "__FILE__" |
-                "__LINE__" |
-                "begin_keywords" |
-                "end_keywords" => {
+                "pragma" => {
                     self.diag.report_span(Severity::Warning, "compiler directive not yet supported", span);
                 }
+                "line" => {
+                    if !after_newline {
+                        self.diag.report_error("`line must be on its own line", span);
+                    }
+                    self.parse_line(span);
+                }
+                "__FILE__" |
+                "__LINE__" => {
+                    let list = self.expand_builtin(&name, span);
+                    self.push_expansion(list, span, span);
+                }
+                // Markers of macro expansion in text mode
+                "" if self.text_mode => return Some(Spanned::new(TokenKind::Directive(name), span)),
+                "\"" |
+                "`" |
+                "\\`\"" => {
+                    self.diag.report_error(format!("`{} can only be used within macro text", name), span);
+                }
                 _ => {
-                    let function_like = match self.macros.get(&name) {
-                        None => {
-                            self.diag.report_error(
-                                format!("cannot find macro {}", name),
-                                span
-                            );
-                            continue
-                        }
-                        Some((_, Some(_), _)) => true,
-                        _ => false,
-                    };
-                    if function_like {
-                        // For function-like macro also get its argument list
-                        let args = self.parse_macro_args();
-                        // TODO: Replace macro within macro and handle `", ``, etc
-                        match self.macros.get(&name) {
-                            Some((_, Some(params), list)) => {
-                                let newlist: Vec<_> = list.iter().flat_map(|x| {
-                                    match x.value {
-                                        TokenKind::Id(ref id) => {
-                                            if let Some(pos) = params.iter().position(|(x, _)| &x.value == id) {
-                                                return args[pos].clone().into_iter()
-                                            }
-                                        }
-                                        _ => (),
-                                    }
-                                    let mut x = x.clone();
-                                    x.span = span;
-                                    vec![x].into_iter()
-                                }).collect();
-                                for tok in newlist.into_iter().rev() {
-                                    self.pushback.push(tok);
-                                }
-                            }
-                            _ => unreachable!(),
-                        }
-                    }else {
-                        // TODO: Replace macro within macro and handle `", ``, etc
-                        match self.macros.get(&name) {
-                            Some((_, _, list)) => {
-                                for tok in list.iter().rev() {
-                                    let mut tok = tok.clone();
-                                    tok.span = span;
-                                    self.pushback.push(tok);
-                                }
-                            }
-                            _ => unreachable!(),
-                        }
+                    if !self.macros.contains_key(&name) {
+                        self.diag.report_error(
+                            format!("cannot find macro {}", name),
+                            span
+                        );
+                        continue
                     }
+                    let mut rest = VecDeque::new();
+                    let (list, call_span) = self.expand_macro(&name, span, &mut rest, &mut Vec::new());
+                    // Tokens read ahead while looking for actual arguments
+                    while let Some(tok) = rest.pop_back() {
+                        self.pushback.push(tok);
+                    }
+                    self.push_expansion(list, span, call_span);
                 }
             }
+        }
+    }
+
+    /// Push back the result of a macro expansion, so it is processed again. `span` is the span
+    /// of the directive and `call_span` also includes actual arguments.
+    fn push_expansion(&mut self, list: Vec<Token>, span: Span, call_span: Span) {
+        // `__FILE__ and `__LINE__ refer to the location of the macro usage
+        let list: Vec<_> = list.into_iter().flat_map(|tok| match tok.value {
+            TokenKind::Directive(ref name) if name == "__FILE__" || name == "__LINE__" => {
+                self.expand_builtin(name, span)
+            }
+            _ => vec![tok],
+        }).collect();
+
+        if self.text_mode {
+            self.pushback.push(Spanned::new(TokenKind::Directive(String::new()), call_span));
+            self.pushback.extend(list.into_iter().rev());
+            self.pushback.push(Spanned::new(TokenKind::Directive(String::new()), call_span));
+            return;
+        }
+        for mut tok in list.into_iter().rev() {
+            if let TokenKind::Whitespace = tok.value {
+                continue;
+            }
+            // Tokens not coming from actual arguments are attributed to the macro usage
+            if tok.span.start.0 < call_span.start.0 || tok.span.end.0 > call_span.end.0 {
+                tok.span = span;
+            }
+            self.pushback.push(tok);
+        }
+    }
+
+    /// Expand `__FILE__ or `__LINE__ used at the given location.
+    fn expand_builtin(&self, name: &str, span: Span) -> Vec<Token> {
+        let pos = self.mgr.find_pos(span.start);
+        let text = if name == "__FILE__" {
+            format!("\"{}\"", pos.source.filename().replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            (pos.source.linemap().line_number(pos.pos) + 1).to_string()
+        };
+        self.lex_text(text)
+    }
 
-            after_newline = false;
+    /// Retrieve next raw token, taking tokens from `rest` first.
+    fn next_from(&mut self, rest: &mut VecDeque<Token>) -> Option<Token> {
+        match rest.pop_front() {
+            Some(tok) => Some(tok),
+            None => self.next_raw(),
         }
     }
 
-    /// Parse a actual argument list of macro
-    fn parse_macro_args(&mut self) -> Vec<Vec<Token>> {
+    /// Parse a actual argument list of macro. Tokens are taken from `rest` first and from the
+    /// input when it runs out. Returns the arguments and the span of the entire macro usage.
+    fn parse_macro_args(&mut self, span: Span, rest: &mut VecDeque<Token>) -> (Vec<Vec<Token>>, Span) {
         // Expect to see a opening paranthesis
-        match self.peek_raw() {
+        match self.next_from(rest) {
             Some(Spanned{value: TokenKind::OpenDelim(Delim::Paren), ..}) => (),
-            _ => {
-                self.diag.report_error("Expected actual arguments for function-like macro", self.peek_raw().unwrap().span);
+            tok => {
+                let err_span = tok.as_ref().map_or(span, |tok| tok.span);
+                self.diag.report_error("expected actual arguments for function-like macro", err_span);
                 // Error recovery
-                return Vec::new();
-            },
+                if let Some(tok) = tok {
+                    rest.push_front(tok);
+                }
+                return (Vec::new(), span);
+            }
         }
-        self.next_raw();
 
         let mut list = Vec::new();
+        let mut tokens = Vec::new();
+        let mut level = 0;
+
+        // Read all tokens, skipping over delimited parenthesis, brackets and braces.
         loop {
-            let mut tokens = Vec::new();
-            let mut level = 0;
-
-            // Read all tokens, skipping over delimited parenthesis.
-            loop {
-                let tok = self.next_raw().unwrap();
-                let end = match tok.value {
-                    TokenKind::OpenDelim(Delim::Paren) => {
-                        level += 1;
-                        false
+            let tok = match self.next_from(rest) {
+                Some(v) => v,
+                None => {
+                    self.diag.report_error("unterminated macro argument list", span);
+                    list.push(tokens);
+                    return (list, span);
+                }
+            };
+            match tok.value {
+                // Actual arguments can span multiple lines
+                TokenKind::NewLine |
+                TokenKind::LineComment => continue,
+                TokenKind::OpenDelim(_) => level += 1,
+                TokenKind::CloseDelim(Delim::Paren) if level == 0 => {
+                    list.push(tokens);
+                    return (list, span.merge(tok.span));
+                }
+                TokenKind::CloseDelim(_) if level > 0 => level -= 1,
+                TokenKind::Comma if level == 0 => {
+                    list.push(::std::mem::take(&mut tokens));
+                    continue;
+                }
+                _ => (),
+            }
+            tokens.push(tok);
+        }
+    }
+
+    /// Match actual arguments of a macro usage with its formal arguments. Arguments that are
+    /// empty or omitted take their default values if there are any.
+    fn bind_macro_args(
+        &self, name: &str, params: &[(Spanned<String>, Option<Vec<Token>>)],
+        mut args: Vec<Vec<Token>>, span: Span,
+    ) -> Vec<Vec<Token>> {
+        // A macro without arguments can still be used with an empty argument list.
+        if params.is_empty() && args.len() == 1 && args[0].is_empty() {
+            args.clear();
+        }
+        if args.len() > params.len() {
+            self.diag.report_error(
+                format!("too many arguments for macro {}: expected {}, found {}", name, params.len(), args.len()),
+                span
+            );
+        }
+        // If the argument list is missing entirely, the error is already reported.
+        let report = !args.is_empty();
+        let mut args = args.into_iter();
+        params.iter().map(|(param, default)| {
+            match (args.next(), default) {
+                (Some(arg), _) if !arg.is_empty() => arg,
+                (_, Some(default)) => default.clone(),
+                (Some(arg), None) => arg,
+                (None, None) => {
+                    if report {
+                        self.diag.report_error(
+                            format!("missing argument {} for macro {}", param.value, name),
+                            span
+                        );
+                    }
+                    Vec::new()
+                }
+            }
+        }).collect()
+    }
+
+    /// Expand a macro usage. Actual arguments of function-like macros are parsed from `rest` or
+    /// the input. `active` contains macros currently being expanded, for detecting recursion.
+    /// Returns the fully expanded tokens and the span of the entire macro usage.
+    fn expand_macro(
+        &mut self, name: &str, span: Span, rest: &mut VecDeque<Token>, active: &mut Vec<String>,
+    ) -> (Vec<Token>, Span) {
+        let (params, body) = match self.macros.get(name) {
+            Some((_, params, body)) => (params.clone(), body.clone()),
+            None => unreachable!(),
+        };
+        let body = Self::mark_spaces(body);
+        let (body, call_span) = match params {
+            None => (body, span),
+            Some(params) => {
+                let (args, call_span) = self.parse_macro_args(span, rest);
+                let args = self.bind_macro_args(name, &params, args, call_span);
+                // Expand actual arguments before substitution, so they may use the same macro.
+                let args: Vec<_> = args.into_iter().map(|arg| {
+                    self.expand_list(Self::mark_spaces(arg), active)
+                }).collect();
+                let body = body.into_iter().flat_map(|tok| {
+                    if let TokenKind::Id(ref id) = tok.value {
+                        if let Some(pos) = params.iter().position(|(x, _)| &x.value == id) {
+                            return args[pos].clone();
+                        }
+                    }
+                    vec![tok]
+                }).collect();
+                (body, call_span)
+            }
+        };
+        active.push(name.to_owned());
+        let list = self.expand_list(body, active);
+        active.pop();
+        (list, call_span)
+    }
+
+    /// Insert whitespace tokens between tokens that are not adjacent in source. They are needed
+    /// by `" and removed after expansion.
+    fn mark_spaces(tokens: impl IntoIterator<Item=Token>) -> VecDeque<Token> {
+        let mut list = VecDeque::new();
+        let mut prev: Option<Span> = None;
+        for tok in tokens {
+            if let Some(prev) = prev {
+                if prev.end != tok.span.start {
+                    list.push_back(Spanned::new(TokenKind::Whitespace, Span::new(prev.end, tok.span.start)));
+                }
+            }
+            prev = Some(tok.span);
+            list.push_back(tok);
+        }
+        list
+    }
+
+    /// Process macro usages, `", `\`" and `` within macro text.
+    fn expand_list(&mut self, mut list: VecDeque<Token>, active: &mut Vec<String>) -> Vec<Token> {
+        let mut out = Vec::new();
+        while let Some(tok) = list.pop_front() {
+            let name = match tok.value {
+                TokenKind::Directive(ref name) => name.clone(),
+                _ => {
+                    out.push(tok);
+                    continue;
+                }
+            };
+            match name.as_ref() {
+                "\"" => {
+                    // Find the closing `" and turn everything in between into a string literal
+                    let mut region = VecDeque::new();
+                    loop {
+                        match list.pop_front() {
+                            Some(Spanned{value: TokenKind::Directive(ref name), ..}) if name == "\"" => break,
+                            Some(v) => region.push_back(v),
+                            None => {
+                                self.diag.report_error("unterminated `\" in macro text", tok.span);
+                                break;
+                            }
+                        }
+                    }
+                    let region = self.expand_list(region, active);
+                    out.extend(self.stringify(&region));
+                }
+                "`" => {
+                    // Whitespace around `` is ignored
+                    while let Some(Spanned{value: TokenKind::Whitespace, ..}) = out.last() {
+                        out.pop();
                     }
-                    TokenKind::CloseDelim(Delim::Paren) => {
-                        if level > 0 {
-                            level -= 1;
-                            false
-                        } else {
-                            true
+                    while let Some(Spanned{value: TokenKind::Whitespace, ..}) = list.front() {
+                        list.pop_front();
+                    }
+                    match (out.pop(), list.pop_front()) {
+                        (Some(lhs), Some(rhs)) => {
+                            let text = self.token_text(&lhs) + &self.token_text(&rhs);
+                            out.extend(self.lex_text(text));
+                        }
+                        (lhs, rhs) => {
+                            self.diag.report_error("`` must be placed between two tokens", tok.span);
+                            // Error recovery: ignore the ``
+                            out.extend(lhs);
+                            if let Some(rhs) = rhs {
+                                list.push_front(rhs);
+                            }
                         }
                     }
-                    TokenKind::Comma => {
-                        level == 0
+                }
+                _ if self.macros.contains_key(&name) => {
+                    if active.contains(&name) {
+                        self.diag.report_error(format!("macro {} is expanded recursively", name), tok.span);
+                        continue;
                     }
-                    _ => false,
-                };
-                if end {
-                    self.pushback_raw(tok);
-                    break;
+                    let (tokens, _) = self.expand_macro(&name, tok.span, &mut list, active);
+                    out.extend(tokens);
                 }
-                tokens.push(tok);
+                // Other directives are processed after expansion
+                _ => out.push(tok),
             }
+        }
+        out
+    }
 
-            list.push(tokens);
+    /// Get the source text of a token.
+    fn token_text(&self, tok: &Token) -> String {
+        match self.mgr.find_span(tok.span) {
+            Some(span) => span.source.content()[span.start..span.end].to_owned(),
+            None => String::new(),
+        }
+    }
 
-            // Break out from the loop if not comma
-            match self.peek_raw() {
-                Some(Spanned{value: TokenKind::Comma, ..}) => (),
-                _ => break,
+    /// Turn tokens into a string literal. Whitespace between tokens becomes a single space.
+    fn stringify(&self, tokens: &[Token]) -> Vec<Token> {
+        let mut text = String::from("\"");
+        let mut space = false;
+        for tok in tokens {
+            match tok.value {
+                TokenKind::Whitespace => {
+                    space = true;
+                    continue;
+                }
+                _ if space && text.len() > 1 => text.push(' '),
+                _ => (),
+            }
+            space = false;
+            match tok.value {
+                TokenKind::Directive(ref name) if name == "\\`\"" => text.push_str("\\\""),
+                _ => text.push_str(&self.token_text(tok)),
             }
-            // Consume the comma
-            self.next_raw();
         }
+        text.push('"');
+        self.lex_text(text)
+    }
 
-        // Expecting to see a closing parenthesis
-        match self.peek_raw() {
-            Some(Spanned{value: TokenKind::CloseDelim(Delim::Paren), ..}) => (),
-            _ => {
-                self.diag.report_error("Expected closing parenthesis", self.peek_raw().unwrap().span);
-                // Error recovery
-                return list;
-            },
+    /// Lex text created during macro expansion.
+    fn lex_text(&self, text: String) -> Vec<Token> {
+        let src = Rc::new(Source::new("<macro expansion>".to_owned(), text));
+        self.mgr.add_source(src.clone());
+        let mut lexer = self.new_lexer(&src);
+        let mut list = Vec::new();
+        while let Some(tok) = lexer.next_span() {
+            match tok.value {
+                TokenKind::NewLine |
+                TokenKind::LineComment => (),
+                _ => list.push(tok),
+            }
         }
-        self.next_raw();
-
-        return list;
+        list
     }
 
     /// Read all tokens until the next newline (new line will be consumed but not returned)
@@ -283,7 +639,10 @@ impl<'a> Preprocessor<'a> {
             };
             match tok.value {
                 TokenKind::NewLine |
-                TokenKind::LineComment => break,
+                TokenKind::LineComment => {
+                    self.after_newline = true;
+                    break;
+                }
                 _ => (),
             }
             list.push_back(tok);
@@ -303,9 +662,9 @@ impl<'a> Preprocessor<'a> {
         }
     }
 
-    /// Parse a macro definition
+    /// Parse a macro definition, returning the name of the macro defined.
     /// The span here is only for diagnostic purposes.
-    fn parse_define(&mut self, span: Span) {
+    fn parse_define(&mut self, span: Span) -> Option<String> {
         // The identifier after define can be a keyword
         self.stacks.last_mut().unwrap().enter_kw_scope(0);
         let token = self.expect_id();
@@ -317,7 +676,7 @@ impl<'a> Preprocessor<'a> {
                 self.diag.report_error("expected identifier name after `define", span);
                 // Error recovery: Discard until newline
                 self.read_until_newline();
-                return;
+                return None;
             }
         };
 
@@ -325,13 +684,13 @@ impl<'a> Preprocessor<'a> {
             self.diag.report_error("directive name cannot be used as macro names", span);
             // Error recovery: Discard until newline
             self.read_until_newline();
-            return;
+            return None;
         }
 
         // Check if this macro is function-like.
-        let paren = match self.peek_raw().unwrap() {
+        let paren = match self.peek_raw() {
             // If this is a parenthesis that immediately follows the name
-            Spanned{value: TokenKind::OpenDelim(Delim::Paren), span: p_span} if p_span.start == span.end => true,
+            Some(Spanned{value: TokenKind::OpenDelim(Delim::Paren), span: p_span}) if p_span.start == span.end => true,
             _ => false,
         };
 
@@ -343,10 +702,55 @@ impl<'a> Preprocessor<'a> {
 
         let list = self.read_until_newline();
 
-        // Insert it to the global definitions list and report error for duplicate definition
-        if let Some((old_span, ..)) = self.macros.insert(name, (span, args, list)) {
-            self.diag.report_error("duplicate macro definitions", span);
-            self.diag.report_span(Severity::Remark, "previous declared here", old_span);
+        // Insert it to the global definitions list and warn about redefinition with different text
+        if let Some((old_span, old_args, old_list)) = self.macros.get(&name) {
+            let same_args = match (old_args, &args) {
+                (None, None) => true,
+                (Some(old_args), Some(args)) => {
+                    old_args.len() == args.len() &&
+                    old_args.iter().zip(args).all(|((old_name, old_default), (name, default))| {
+                        old_name.value == name.value && match (old_default, default) {
+                            (None, None) => true,
+                            (Some(old), Some(new)) => Self::same_tokens(old, new),
+                            _ => false,
+                        }
+                    })
+                }
+                _ => false,
+            };
+            if !same_args || !Self::same_tokens(old_list, &list) {
+                self.diag.report_span(Severity::Warning, format!("macro {} is redefined", name), span);
+                self.diag.report_span(Severity::Remark, "previous declared here", *old_span);
+            }
+        }
+        self.macros.insert(name.clone(), (span, args, list));
+        Some(name)
+    }
+
+    /// Check if two token lists are the same, ignoring whitespace.
+    fn same_tokens<'b>(a: impl IntoIterator<Item=&'b Token>, b: impl IntoIterator<Item=&'b Token>) -> bool {
+        a.into_iter().map(|tok| &tok.value).eq(b.into_iter().map(|tok| &tok.value))
+    }
+
+    /// Parse an undef directive
+    fn parse_undef(&mut self, span: Span) {
+        // The identifier after undef can be a keyword
+        self.stacks.last_mut().unwrap().enter_kw_scope(0);
+        let token = self.expect_id();
+        self.stacks.last_mut().unwrap().leave_kw_scope();
+        let name = match token {
+            Some(v) => v,
+            None => {
+                self.diag.report_error("expected identifier name after `undef", span);
+                return;
+            }
+        };
+        if self.macros.remove(&name.value).is_none() {
+            self.diag.report_span(
+                Severity::Warning,
+                format!("macro {} is not defined", name.value),
+                name.span
+            );
         }
     }
 
@@ -355,26 +759,31 @@ impl<'a> Preprocessor<'a> {
         // Discard the parenthesis
         self.next_raw();
         let mut list = Vec::new();
+        // An empty formal argument list
+        if let Some(Spanned{value: TokenKind::CloseDelim(Delim::Paren), ..}) = self.peek_raw() {
+            self.next_raw();
+            return list;
+        }
         loop {
             let arg_name = match self.expect_id() {
                 Some(v) => v,
                 None => {
-                    self.diag.report_error("expected identifier in macro formal argument list", self.peek_raw().unwrap().span);
+                    let span = self.peek_raw().map_or(Span::none(), |tok| tok.span);
+                    self.diag.report_error("expected identifier in macro formal argument list", span);
                     break;
                 }
             };
 
-            let has_default = match self.peek_raw() {
-                Some(Spanned{value: TokenKind::BinaryOp(BinaryOp::Eq), ..}) => true,
-                _ => false,
+            let default = match self.peek_raw() {
+                Some(Spanned{value: TokenKind::Assign, ..}) => {
+                    // Discard the eq symbol
+                    self.next_raw();
+                    Some(self.read_default_arg())
+                }
+                _ => None,
             };
-            if has_default {
-                // Discard the eq symbol
-                self.next_raw();
-                self.diag.report_fatal("default macro argument is not yet supported", self.peek_raw().unwrap().span);
-            }
 
-            list.push((arg_name, None));
+            list.push((arg_name, default));
 
             // Break out from the loop if not comma
             match self.peek_raw() {
@@ -386,8 +795,9 @@ impl<'a> Preprocessor<'a> {
         }
         match self.peek_raw() {
             Some(Spanned{value: TokenKind::CloseDelim(Delim::Paren), ..}) => (),
-            _ => {
-                self.diag.report_error("Expected closing parenthesis", self.peek_raw().unwrap().span);
+            tok => {
+                let span = tok.map_or(Span::none(), |tok| tok.span);
+                self.diag.report_error("Expected closing parenthesis", span);
                 // Error recovery
                 return list;
             },
@@ -397,6 +807,126 @@ impl<'a> Preprocessor<'a> {
         return list;
     }
 
+    /// Read the default text of a formal argument, up to the next comma or closing parenthesis
+    /// that is not nested.
+    fn read_default_arg(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut level = 0;
+        loop {
+            match self.peek_raw() {
+                None |
+                Some(Spanned{value: TokenKind::NewLine, ..}) |
+                Some(Spanned{value: TokenKind::LineComment, ..}) => break,
+                Some(Spanned{value: TokenKind::Comma, ..}) |
+                Some(Spanned{value: TokenKind::CloseDelim(Delim::Paren), ..}) if level == 0 => break,
+                Some(Spanned{value: TokenKind::OpenDelim(_), ..}) => level += 1,
+                Some(Spanned{value: TokenKind::CloseDelim(_), ..}) if level > 0 => level -= 1,
+                _ => (),
+            }
+            tokens.push(self.next_raw().unwrap());
+        }
+        tokens
+    }
+
+    /// Parse a begin_keywords directive
+    fn parse_begin_keywords(&mut self, span: Span) {
+        let v = match self.next_raw() {
+            Some(Spanned{value: TokenKind::StringLiteral(version), span}) => {
+                match kw_map::keyword_set(&version) {
+                    Some(v) => v,
+                    None => {
+                        self.diag.report_error(format!("unknown version specifier \"{}\"", version), span);
+                        return;
+                    }
+                }
+            }
+            _ => {
+                self.diag.report_error("expecting version specifier after `begin_keywords", span);
+                return;
+            }
+        };
+        self.keywords.push(v);
+        self.stacks.last_mut().unwrap().set_keywords(v);
+    }
+
+    /// Parse the time unit and precision following a timescale directive.
+    fn parse_timescale(&self, span: Span, list: &[Token]) -> Option<Timescale> {
+        let split = list.iter().position(|tok| matches!(tok.value, TokenKind::BinaryOp(BinaryOp::Div)));
+        let (unit, precision) = match split {
+            Some(split) if split > 0 && split + 1 < list.len() => (&list[..split], &list[split + 1..]),
+            _ => {
+                self.diag.report_error("expected time unit and precision after `timescale", span);
+                return None;
+            }
+        };
+        // A time value is either a time literal or a number followed by a unit
+        let parse_time = |list: &[Token]| {
+            let text: Vec<_> = list.iter().map(|tok| self.token_text(tok)).collect();
+            let time = match list.len() {
+                1 | 2 => Timescale::parse_time(&text.concat()),
+                _ => None,
+            };
+            if time.is_none() {
+                let span = list[0].span.start.span_to(list[list.len() - 1].span.end);
+                self.diag.report_error("time value must be 1, 10 or 100 followed by s, ms, us, ns, ps or fs", span);
+            }
+            time
+        };
+        let unit = parse_time(unit)?;
+        let precision = parse_time(precision)?;
+        if precision > unit {
+            self.diag.report_error("time precision must be at least as precise as time unit", span);
+            return None;
+        }
+        Some(Timescale { unit, precision })
+    }
+
+    /// Tokens passed to the parser to indicate the timescale. The time unit and precision are
+    /// given as time literals.
+    fn timescale_tokens(span: Span, timescale: Timescale) -> Vec<Token> {
+        vec![
+            Spanned::new(TokenKind::Directive("timescale".to_owned()), span),
+            Spanned::new(TokenKind::TimeLiteral(10f64.powi(timescale.unit)), span),
+            Spanned::new(TokenKind::TimeLiteral(10f64.powi(timescale.precision)), span),
+        ]
+    }
+
+    /// Parse a line directive
+    fn parse_line(&mut self, span: Span) {
+        let list: Vec<_> = self.read_until_newline().into_iter().collect();
+        let (line, file, level) = match &list[..] {
+            [
+                line @ Spanned{value: TokenKind::IntegerLiteral(_), ..},
+                Spanned{value: TokenKind::StringLiteral(file), ..},
+                level @ Spanned{value: TokenKind::IntegerLiteral(_), ..},
+            ] => (line, file.clone(), level),
+            _ => {
+                self.diag.report_error("expected line number, file name and level after `line", span);
+                return;
+            }
+        };
+        let line = match self.token_text(line).parse::<i32>() {
+            Ok(v) if v > 0 => v,
+            _ => {
+                self.diag.report_error("line number must be a positive integer", line.span);
+                return;
+            }
+        };
+        match self.token_text(level).as_ref() {
+            "0" | "1" | "2" => (),
+            _ => {
+                self.diag.report_error("level must be 0, 1 or 2", level.span);
+                return;
+            }
+        }
+
+        // Make the line following this directive have the given line number. Line numbers in
+        // line maps start from 0.
+        let pos = self.mgr.find_pos(span.start);
+        let src = pos.source.fake_source(pos.pos, file, line - 2);
+        self.mgr.remap_source(span.start, Rc::new(src));
+    }
+
     /// Parse an ifdef directive
     fn parse_ifdef(&mut self, span: Span, cond: bool) {
         // If this block is nested within a untaken branch, just skip everything
@@ -523,27 +1053,58 @@ impl<'a> Preprocessor<'a> {
     }
 
     /// Parse an include directive
-    fn parse_include(&mut self, span: Span) {
-        let (filename, span) = match self.next_raw() {
-            // TODO: Also handle <xxx>?
-            Some(Spanned{value: TokenKind::StringLiteral(str), span}) => (str, span),
-            Some(Spanned{value: TokenKind::BinaryOp(BinaryOp::Lt), ..}) => {
-                self.diag.report_error("<filename> style include is not yet supported", span);
-                return;
+    fn parse_include(&mut self, directive_span: Span) {
+        let (filename, span, system) = match self.next_raw() {
+            Some(Spanned{value: TokenKind::StringLiteral(str), span}) => (str, span, false),
+            Some(Spanned{value: TokenKind::BinaryOp(BinaryOp::Lt), span}) => {
+                match self.parse_system_include(span) {
+                    Some((filename, span)) => (filename, span, true),
+                    None => {
+                        self.diag.report_error("expecting > after file name", directive_span.start.span_to(span.end));
+                        return;
+                    }
+                }
             }
             _ => {
-                self.diag.report_error("expecting file name after `include", span);
+                self.diag.report_error("expecting file name after `include", directive_span);
                 return;
             },
         };
-        let file = match self.mgr.load_source(&filename) {
+        let file = match self.mgr.load_source(&filename, system, directive_span) {
             Ok(file) => file,
             Err(err) => {
                 self.diag.report_error(format!("failed when loading file {}: {}", filename, err), span);
                 return;
             }
         };
-        self.stacks.push(Lexer::new(self.mgr, self.diag, &file));
+        self.stacks.push(self.new_lexer(&file));
+        self.after_newline = true;
+    }
+
+    /// Read the file name of an `include <...> directive, given the span of <. The file name is
+    /// taken verbatim from the source apart from surrounding whitespace, as it need not be made
+    /// of valid tokens.
+    fn parse_system_include(&mut self, lt_span: Span) -> Option<(String, Span)> {
+        loop {
+            match self.next_raw() {
+                Some(Spanned{value: TokenKind::BinaryOp(BinaryOp::Gt), span}) => {
+                    let name_span = lt_span.end.span_to(span.start);
+                    let fat = self.mgr.find_span(name_span).unwrap();
+                    let filename = fat.source.content()[fat.start..fat.end].trim();
+                    if filename.is_empty() {
+                        return None;
+                    }
+                    return Some((filename.to_owned(), lt_span.start.span_to(span.end)));
+                }
+                None => return None,
+                Some(tok @ Spanned{value: TokenKind::NewLine, ..}) |
+                Some(tok @ Spanned{value: TokenKind::LineComment, ..}) => {
+                    self.pushback_raw(tok);
+                    return None;
+                }
+                Some(_) => (),
+            }
+        }
     }
 
     /// Skip tokens until next branching directive or eof
@@ -577,9 +1138,25 @@ impl<'a> Preprocessor<'a> {
         }
     }
 
-    fn all(&mut self, src: &Rc<Source>) -> VecDeque<Token> {
-        self.stacks.push(Lexer::new(self.mgr, self.diag, src));
+    /// Preprocess a file. Macros defined in previous files remain visible, but macros given on
+    /// the command line are restored first.
+    pub fn all(&mut self, src: &Rc<Source>) -> VecDeque<Token> {
+        // Discard state left behind by a previous file aborted by a fatal error
+        self.pushback.clear();
+        self.stacks.clear();
+        self.branch_stack.clear();
+        self.after_newline = true;
+        self.apply_cmdline_macros();
+        self.stacks.push(self.new_lexer(src));
         let mut vec = VecDeque::new();
+        // `default_nettype and `timescale persist across files
+        if let Some((tok, arg)) = self.default_nettype.clone() {
+            vec.push_back(tok);
+            vec.push_back(arg);
+        }
+        if let Some((span, timescale)) = self.timescale {
+            vec.extend(Self::timescale_tokens(span, timescale));
+        }
         loop {
             match self.process() {
                 None => break,
@@ -592,4 +1169,107 @@ impl<'a> Preprocessor<'a> {
         }
         vec
     }
+
+    /// Preprocess a file and turn the result back into text. Line structure is kept where
+    /// possible, and `line directives are inserted when the file changes or lines are skipped.
+    pub fn all_text(&mut self, src: &Rc<Source>) -> String {
+        self.pushback.clear();
+        self.stacks.clear();
+        self.branch_stack.clear();
+        self.after_newline = true;
+        self.apply_cmdline_macros();
+        self.stacks.push(self.new_lexer(src));
+        self.text_mode = true;
+
+        let mut out = format!("`line 1 \"{}\" 0\n", src.filename());
+        // Files that are being processed, and the current line in the innermost one
+        let mut files = vec![src.clone()];
+        let mut line = 0;
+        // End of the previous token, to decide whether a space is needed
+        let mut prev_end = None;
+        // Whether we are within a macro expansion, and whether a space is pending within it
+        let mut expansion = false;
+        let mut space = false;
+        loop {
+            let tok = match self.process() {
+                None => break,
+                Some(v) => v,
+            };
+            match tok.value {
+                TokenKind::NewLine |
+                TokenKind::LineComment => continue,
+                TokenKind::Directive(ref name) if name.is_empty() => {
+                    expansion = !expansion;
+                    if !expansion {
+                        prev_end = Some(tok.span.end);
+                        continue;
+                    }
+                    space = false;
+                }
+                TokenKind::Whitespace => {
+                    space = true;
+                    continue;
+                }
+                _ if expansion => {
+                    if space {
+                        out.push(' ');
+                        space = false;
+                    }
+                    out.push_str(&self.token_text(&tok));
+                    continue;
+                }
+                _ => (),
+            }
+
+            // Place the token according to its location in source
+            let fat = self.mgr.find_span(tok.span).unwrap();
+            let linemap = fat.source.linemap();
+            let tok_line = linemap.line_number(fat.start);
+            // `line gives a file a different name while content is shared
+            let same_content = |file: &Rc<Source>| Rc::ptr_eq(file.content(), fat.source.content());
+            let same_file = files.last().is_some_and(|file| {
+                same_content(file) && file.filename() == fat.source.filename()
+            });
+            if same_file && tok_line == line {
+                if prev_end.is_some_and(|end| end != tok.span.start) {
+                    out.push(' ');
+                }
+            } else {
+                if same_file && tok_line > line && tok_line - line <= 8 {
+                    for _ in line..tok_line {
+                        out.push('\n');
+                    }
+                } else {
+                    // Level 1 indicates entering an included file and 2 indicates returning to it
+                    let level = if same_file || same_content(files.last().unwrap()) {
+                        0
+                    } else if let Some(pos) = files.iter().position(same_content) {
+                        files.truncate(pos + 1);
+                        2
+                    } else {
+                        files.push(fat.source.clone());
+                        1
+                    };
+                    *files.last_mut().unwrap() = fat.source.clone();
+                    if !out.ends_with('\n') {
+                        out.push('\n');
+                    }
+                    out.push_str(&format!("`line {} \"{}\" {}\n", tok_line + 1, fat.source.filename(), level));
+                }
+                line = tok_line;
+                // Keep indentation
+                let indent = &fat.source.content()[linemap.line_start_pos(tok_line)..fat.start];
+                out.extend(indent.chars().map(|ch| if ch == '\t' { '\t' } else { ' ' }));
+            }
+            if !expansion {
+                out.push_str(&self.token_text(&tok));
+            }
+            prev_end = Some(tok.span.end);
+        }
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        self.text_mode = false;
+        out
+    }
 }
@@ -66,7 +66,6 @@ impl<'a> TkTree<'a> {
         let overall_span = tok.span.merge(close_tok.span);
         Spanned::new(
             TokenKind::DelimGroup(delim, Box::new(DelimGroup {
-                open: tok,
                 close: close_tok,
                 tokens: vec
             })),
@@ -387,7 +387,6 @@ pub type Token = Spanned<TokenKind>;
 /// A delimited group of token
 #[derive(Debug, Clone)]
 pub struct DelimGroup {
-    pub open: Token,
     pub close: Token,
     pub tokens: VecDeque<Token>,
 }
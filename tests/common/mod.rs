@@ -0,0 +1,103 @@
+//! Helpers for running the elaborator on small designs written to a temporary directory.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Result of running the elaborator.
+pub struct Output {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// A temporary directory holding input files, which is removed when dropped.
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    /// Create a directory containing the given files, each given by its relative path and content.
+    pub fn new(files: &[(&str, &str)]) -> TestDir {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("sv-elaborator-test-{}-{}", process::id(), id));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        let dir = TestDir { path };
+        for (name, content) in files {
+            dir.write(name, content);
+        }
+        dir
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&self, name: &str, content: &str) {
+        let path = self.path.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.path.join(name)).unwrap()
+    }
+
+    /// Run the elaborator with the directory as the working directory.
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.run_with_env(args, &[])
+    }
+
+    /// Run the elaborator with the directory as the working directory and the given environment
+    /// variables set.
+    pub fn run_with_env(&self, args: &[&str], vars: &[(&str, &str)]) -> Output {
+        let output = Command::new(env!("CARGO_BIN_EXE_sv-elaborator"))
+            .args(args)
+            .envs(vars.iter().cloned())
+            .current_dir(&self.path)
+            .output()
+            .unwrap();
+        Output {
+            success: output.status.success(),
+            stdout: String::from_utf8(output.stdout).unwrap(),
+            stderr: String::from_utf8(output.stderr).unwrap(),
+        }
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Elaborate `top.sv` containing the given source with `top` as the toplevel module, and return
+/// the output, which must be produced without errors.
+pub fn elaborate(source: &str, args: &[&str]) -> String {
+    let dir = TestDir::new(&[("top.sv", source)]);
+    let mut all_args = vec!["-t", "top"];
+    all_args.extend(args);
+    all_args.push("top.sv");
+    let output = dir.run(&all_args);
+    assert!(output.success, "elaboration failed:\n{}", output.stderr);
+    output.stdout
+}
+
+/// Elaborate `top.sv` containing the given source with `top` as the toplevel module, and return
+/// the diagnostics, which must include an error.
+pub fn elaborate_err(source: &str, args: &[&str]) -> String {
+    let dir = TestDir::new(&[("top.sv", source)]);
+    let mut all_args = vec!["-t", "top"];
+    all_args.extend(args);
+    all_args.push("top.sv");
+    let output = dir.run(&all_args);
+    assert!(!output.success, "elaboration unexpectedly succeeded:\n{}", output.stdout);
+    output.stderr
+}
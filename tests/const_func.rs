@@ -0,0 +1,123 @@
+//! Evaluation of constant function calls during elaboration.
+
+mod common;
+
+use common::{elaborate, elaborate_err};
+
+#[test]
+fn recursion_and_package_functions() {
+    let out = elaborate(r#"
+package util;
+  function automatic int clog2(input int value);
+    int result = 0;
+    while ((1 << result) < value) result++;
+    return result;
+  endfunction
+endpackage
+
+module top;
+  function automatic int fact(input int n);
+    if (n <= 1) return 1;
+    return n * fact(n - 1);
+  endfunction
+  localparam int A = fact(5);
+  localparam int B = util::clog2(33);
+endmodule
+"#, &[]);
+    assert!(out.contains("localparam int A = int'(120);"), "{}", out);
+    assert!(out.contains("localparam int B = int'(6);"), "{}", out);
+}
+
+#[test]
+fn statements() {
+    let out = elaborate(r#"
+module top;
+  function automatic logic [7:0] fill(input logic [7:0] x);
+    fill = x;
+    fill[3:0] = 4'hf;
+    case (x)
+      8'd1: fill = 8'd99;
+      default: ;
+    endcase
+  endfunction
+  function automatic int sum_even(input int n);
+    sum_even = 0;
+    for (int i = 0; i < 100; i++) begin
+      if (i == n) break;
+      if (i % 2) continue;
+      sum_even += i;
+    end
+  endfunction
+  function automatic int count(input int n);
+    int c = 0;
+    repeat (n) c += 2;
+    do c--; while (c > 5);
+    return c;
+  endfunction
+  localparam logic [7:0] A = fill(8'h20);
+  localparam logic [7:0] B = fill(1);
+  localparam int C = sum_even(10);
+  localparam int D = count(10);
+endmodule
+"#, &[]);
+    assert!(out.contains("localparam logic [7:0] A = 8'd47;"), "{}", out);
+    assert!(out.contains("localparam logic [7:0] B = 8'd99;"), "{}", out);
+    assert!(out.contains("localparam int C = int'(20);"), "{}", out);
+    assert!(out.contains("localparam int D = int'(5);"), "{}", out);
+}
+
+#[test]
+fn recursion_limit() {
+    let err = elaborate_err(r#"
+module top;
+  function automatic int f(input int n);
+    return f(n + 1);
+  endfunction
+  localparam int A = f(0);
+endmodule
+"#, &[]);
+    assert!(err.contains("error: constant function f exceeds maximum recursion depth of 256"), "{}", err);
+    assert!(err.contains("info: in call to constant function f"), "{}", err);
+}
+
+#[test]
+fn iteration_limit() {
+    let err = elaborate_err(r#"
+module top;
+  function automatic int f(input int n);
+    while (n >= 0) n++;
+    return n;
+  endfunction
+  localparam int A = f(0);
+endmodule
+"#, &[]);
+    assert!(err.contains("error: loop in constant function exceeds 1048576 iterations"), "{}", err);
+}
+
+#[test]
+fn output_port() {
+    let err = elaborate_err(r#"
+module top;
+  function automatic int f(output int y);
+    y = 1;
+    return 0;
+  endfunction
+  localparam int A = f(1);
+endmodule
+"#, &[]);
+    assert!(err.contains("error: constant functions can only have input ports"), "{}", err);
+}
+
+#[test]
+fn non_constant_variable() {
+    let err = elaborate_err(r#"
+module top;
+  logic [3:0] v;
+  function automatic int f(input int x);
+    return v;
+  endfunction
+  localparam int A = f(1);
+endmodule
+"#, &[]);
+    assert!(err.contains("error: v is not a constant"), "{}", err);
+}
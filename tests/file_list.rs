@@ -0,0 +1,96 @@
+//! Command line arguments read from file lists given by `-f` and `-F`.
+
+mod common;
+
+use common::TestDir;
+
+/// A design whose file list refers to an include directory, a macro and a library directory,
+/// all relative to the file list.
+fn design() -> TestDir {
+    TestDir::new(&[
+        ("all.f", "-F rtl/rtl.f\n-t top\n"),
+        ("rtl/rtl.f", "// comment\n# another comment\n+incdir+inc\n+define+W=4\n-y lib\ntop.sv /* block\ncomment */\n"),
+        ("rtl/top.sv", "`include \"defs.svh\"\nmodule top;\n  logic [`W + `D - 1:0] x;\n  leaf u(.a(x[0]));\nendmodule\n"),
+        ("rtl/inc/defs.svh", "`define D 2\n"),
+        ("rtl/lib/leaf.sv", "module leaf(input logic a);\nendmodule\n"),
+    ])
+}
+
+#[test]
+fn nested_lists() {
+    let dir = design();
+    let output = dir.run(&["-f", "all.f"]);
+    assert!(output.success, "{}", output.stderr);
+    assert_eq!(output.stdout, "\
+/* file: rtl/top.sv */
+module top;
+    logic [5:0] x;
+    leaf u (
+        x[0]
+    );
+endmodule
+
+/* file: rtl/lib/leaf.sv */
+module leaf (
+    input wire logic a
+);
+endmodule
+
+");
+}
+
+#[test]
+fn paths_relative_to_working_directory() {
+    // Unlike -F, paths in lists given by -f are not relative to the list.
+    let dir = design();
+    dir.write("sub/list.f", "rtl/inc/defs.svh\n");
+    let output = dir.run(&["-E", "-f", "sub/list.f"]);
+    assert!(output.success, "{}", output.stderr);
+    assert_eq!(output.stdout, "`line 1 \"rtl/inc/defs.svh\" 0\n");
+
+    // A list written for -F cannot be given by -f from another directory.
+    let output = dir.run(&["-E", "-f", "rtl/rtl.f"]);
+    assert!(!output.success);
+    assert!(output.stderr.contains("fatal error: cannot read top.sv"), "{}", output.stderr);
+}
+
+#[test]
+fn environment_variables() {
+    let dir = design();
+    dir.write("env.f", "$RTL/inc/defs.svh ${RTL}/inc/defs.svh\n");
+    let output = dir.run_with_env(&["-M", "-MT", "out", "-f", "env.f"], &[("RTL", "rtl")]);
+    assert!(output.success, "{}", output.stderr);
+    assert_eq!(output.stdout, "out: \\\n  rtl/inc/defs.svh\n");
+}
+
+#[test]
+fn includes_itself() {
+    let dir = TestDir::new(&[("self.f", "-f self.f\n")]);
+    let output = dir.run(&["-f", "self.f"]);
+    assert!(!output.success);
+    assert_eq!(output.stderr, "file list self.f includes itself\n");
+}
+
+#[test]
+fn includes_itself_indirectly() {
+    let dir = TestDir::new(&[("a.f", "-f b.f\n"), ("b.f", "-F a.f\n")]);
+    let output = dir.run(&["-f", "a.f"]);
+    assert!(!output.success);
+    assert_eq!(output.stderr, "file list a.f includes itself\n");
+}
+
+#[test]
+fn missing_list() {
+    let dir = TestDir::new(&[]);
+    let output = dir.run(&["-f", "missing.f"]);
+    assert!(!output.success);
+    assert!(output.stderr.starts_with("failed when reading file list missing.f"), "{}", output.stderr);
+}
+
+#[test]
+fn unknown_plus_option() {
+    let dir = TestDir::new(&[("list.f", "+foo+bar\n")]);
+    let output = dir.run(&["-f", "list.f"]);
+    assert!(!output.success);
+    assert_eq!(output.stderr, "unknown option +foo+bar\n");
+}
@@ -0,0 +1,79 @@
+//! Foreach loops and their lowering into for loops.
+
+mod common;
+
+use common::{elaborate, elaborate_err};
+
+const DESIGN: &str = r#"
+module top(input logic [3:0][1:0] a, output logic [7:0] y);
+  logic [7:0] m [2][4:1];
+  always_comb begin
+    y = 0;
+    foreach (a[i, j]) y += a[i][j];
+    foreach (m[, k]) y += k;
+  end
+endmodule
+"#;
+
+#[test]
+fn kept_by_default() {
+    let out = elaborate(DESIGN, &[]);
+    assert!(out.contains("        foreach (a[i, j]) y += a[i][j];\n"), "{}", out);
+    assert!(out.contains("        foreach (m[, k]) y += k;\n"), "{}", out);
+}
+
+#[test]
+fn lowered() {
+    let out = elaborate(DESIGN, &["--lower-foreach"]);
+    assert!(
+        out.contains("        for (int i = int'(3); i >= int'(0); i--) for (int j = int'(1); j >= int'(0); j--) y += a[i][j];\n"),
+        "{}", out
+    );
+    // Skipped dimensions have no loop.
+    assert!(out.contains("        for (int k = int'(4); k >= int'(1); k--) y += k;\n"), "{}", out);
+}
+
+#[test]
+fn lowered_for_verilog_2005() {
+    let out = elaborate(DESIGN, &["--std", "verilog2005"]);
+    assert!(out.contains("            for (i = 3; i >= 0; i = i - 1) begin: unnamed_blk_0\n"), "{}", out);
+    assert!(out.contains("                for (j = 1; j >= 0; j = j - 1) y = y + (a[(i * 2) + j]);\n"), "{}", out);
+    assert!(out.contains("            for (k = 4; k >= 1; k = k - 1) y = y + (k);\n"), "{}", out);
+}
+
+#[test]
+fn constant_function() {
+    let out = elaborate(r#"
+module top;
+  function automatic int f();
+    logic [3:0][1:0] a;
+    int c = 0;
+    foreach (a[i, j]) c += i * 10 + j;
+    return c;
+  endfunction
+  localparam int A = f();
+endmodule
+"#, &[]);
+    assert!(out.contains("localparam int A = int'(124);"), "{}", out);
+}
+
+#[test]
+fn too_many_loop_variables() {
+    let err = elaborate_err(r#"
+module top(input logic [3:0] a, output logic y);
+  always_comb foreach (a[i, j]) y = a[i];
+endmodule
+"#, &[]);
+    assert!(err.contains("error: foreach loop has 2 loop variables but the array only has 1 dimensions"), "{}", err);
+}
+
+#[test]
+fn not_an_array() {
+    let err = elaborate_err(r#"
+module top(output logic y);
+  real r;
+  always_comb foreach (r[i]) y = 1;
+endmodule
+"#, &[]);
+    assert!(err.contains("error: foreach loop has 1 loop variables but the array only has 0 dimensions"), "{}", err);
+}
@@ -0,0 +1,78 @@
+//! Include files, system include paths and dependency output.
+
+mod common;
+
+use common::TestDir;
+
+/// A design including a header by a quoted name, which includes another header, and a header by
+/// an angle-bracketed name.
+fn design() -> TestDir {
+    TestDir::new(&[
+        ("top.sv", "`include \"a.svh\"\n`include <s.svh>\nmodule top;\n  logic [`A + `B + `S:0] x;\nendmodule\n"),
+        ("inc/a.svh", "`include \"b.svh\"\n`define A 1\n"),
+        ("inc/b.svh", "`define B 2\n"),
+        ("sys/s.svh", "`define S 3\n"),
+    ])
+}
+
+#[test]
+fn search_paths() {
+    let dir = design();
+    let output = dir.run(&["-I", "inc", "--isystem", "sys", "-t", "top", "top.sv"]);
+    assert!(output.success, "{}", output.stderr);
+    assert_eq!(output.stdout, "/* file: top.sv */\nmodule top;\n    logic [6:0] x;\nendmodule\n\n");
+}
+
+#[test]
+fn dependencies() {
+    let dir = design();
+    let output = dir.run(&["-M", "-MT", "out.sv", "-I", "inc", "-isystem", "sys", "top.sv"]);
+    assert!(output.success, "{}", output.stderr);
+    assert_eq!(output.stdout, "out.sv: \\\n  top.sv \\\n  inc/a.svh \\\n  inc/b.svh \\\n  sys/s.svh\n");
+}
+
+#[test]
+fn dependency_file() {
+    // The rule is written next to the output, and the target defaults to the output file.
+    let dir = design();
+    let output = dir.run(&["-MF", "out.d", "-o", "out.sv", "-I", "inc", "--isystem", "sys", "-t", "top", "top.sv"]);
+    assert!(output.success, "{}", output.stderr);
+    assert_eq!(dir.read("out.d"), "out.sv: \\\n  top.sv \\\n  inc/a.svh \\\n  inc/b.svh \\\n  sys/s.svh\n");
+    assert!(dir.read("out.sv").contains("logic [6:0] x;"));
+}
+
+#[test]
+fn dependency_target_required() {
+    let dir = design();
+    let output = dir.run(&["-M", "-I", "inc", "--isystem", "sys", "top.sv"]);
+    assert!(!output.success);
+    assert!(output.stderr.contains("fatal error: target of the Makefile rule must be given by -MT or -o"), "{}", output.stderr);
+}
+
+#[test]
+fn system_include_ignores_include_path() {
+    let dir = design();
+    let output = dir.run(&["-E", "-I", "inc", "-I", "sys", "top.sv"]);
+    assert!(!output.success);
+    assert!(output.stderr.contains("error: failed when loading file s.svh: search path is empty"), "{}", output.stderr);
+}
+
+#[test]
+fn missing_include() {
+    let dir = TestDir::new(&[("top.sv", "`include \"missing.svh\"\n")]);
+    let output = dir.run(&["-E", "top.sv"]);
+    assert!(!output.success);
+    assert!(output.stderr.contains("error: failed when loading file missing.svh"), "{}", output.stderr);
+}
+
+#[test]
+fn include_chain() {
+    let dir = design();
+    dir.write("inc/b.svh", "`UNDEFINED\n");
+    let output = dir.run(&["-E", "-I", "inc", "--isystem", "sys", "top.sv"]);
+    assert!(!output.success);
+    assert!(
+        output.stderr.starts_with("In file included from a.svh:1,\n                 from top.sv:1:\nb.svh:1: error: cannot find macro UNDEFINED\n"),
+        "{}", output.stderr
+    );
+}
@@ -0,0 +1,230 @@
+//! Return, break and continue statements, and their lowering into flag variables.
+
+mod common;
+
+use common::{elaborate, elaborate_err};
+
+/// Get the declaration of a function or task from the output.
+fn subroutine<'a>(out: &'a str, header: &str) -> &'a str {
+    let start = out.find(header).unwrap_or_else(|| panic!("{} not found in\n{}", header, out));
+    let end = start + out[start..].find("\n    end").unwrap();
+    let end = end + out[end + 1..].find('\n').unwrap() + 2;
+    &out[start..end]
+}
+
+#[test]
+fn kept_by_default() {
+    let out = elaborate(r#"
+module top(input int x, output int y);
+  function automatic int sgn(input int a);
+    if (a < 0) return -1;
+    return 1;
+  endfunction
+  always_comb y = sgn(x);
+endmodule
+"#, &[]);
+    assert_eq!(subroutine(&out, "function automatic int sgn"), "\
+function automatic int sgn (
+        input int a
+    );
+        if (a < 0) return -1;
+        return 1;
+    endfunction
+");
+}
+
+#[test]
+fn trailing_return() {
+    // Returns that skip no statements are plain assignments.
+    let out = elaborate(r#"
+module top(input int x, output int y);
+  function automatic int last(input int a);
+    a = a + 1;
+    return a * 2;
+  endfunction
+  function automatic int pick(input int a);
+    if (a > 0) return 1; else return 2;
+  endfunction
+  task automatic t(output int o);
+    o = 1;
+    return;
+  endtask
+  always_comb y = last(x) + pick(x);
+endmodule
+"#, &["--std", "verilog2005"]);
+    assert_eq!(subroutine(&out, "function automatic signed [31:0] last"), "\
+function automatic signed [31:0] last (
+        input signed [31:0] a
+    );
+        begin
+            a = a + 1;
+            last = a * 2;
+        end
+    endfunction
+");
+    assert_eq!(subroutine(&out, "function automatic signed [31:0] pick"), "\
+function automatic signed [31:0] pick (
+        input signed [31:0] a
+    );
+        if (a > 0) pick = 1; else pick = 2;
+    endfunction
+");
+    assert_eq!(subroutine(&out, "task automatic t"), "\
+task automatic t (
+        output signed [31:0] o
+    );
+        o = 1;
+    endtask
+");
+}
+
+#[test]
+fn early_return() {
+    let out = elaborate(r#"
+module top(input int x, output int y);
+  function automatic int sgn(input int a);
+    if (a < 0) return -1;
+    else if (a == 0) return 0;
+    return 1;
+  endfunction
+  always_comb y = sgn(x);
+endmodule
+"#, &["--std", "verilog2005"]);
+    assert_eq!(subroutine(&out, "function automatic signed [31:0] sgn"), "\
+function automatic signed [31:0] sgn (
+        input signed [31:0] a
+    );
+        reg return_flag;
+        begin
+            return_flag = 0;
+            if (a < 0) begin
+                sgn = -1;
+                return_flag = 1;
+            end else if (a == 0) begin
+                sgn = 0;
+                return_flag = 1;
+            end
+            if (!return_flag) begin
+                sgn = 1;
+            end
+        end
+    endfunction
+");
+}
+
+#[test]
+fn loops() {
+    let out = elaborate(r#"
+module top(input logic [7:0] x, output int y);
+  function automatic int find(input logic [7:0] v);
+    for (int i = 0; i < 8; i++) begin
+      if (!v[i]) continue;
+      if (i > 5) return i;
+    end
+    return -1;
+  endfunction
+  function automatic int count(input logic [7:0] v);
+    count = 0;
+    for (int i = 0; i < 8; i++) begin
+      if (v[i]) count++;
+      else continue;
+    end
+  endfunction
+  always_comb y = find(x) + count(x);
+endmodule
+"#, &["--std", "verilog2005"]);
+    assert_eq!(subroutine(&out, "function automatic signed [31:0] find"), "\
+function automatic signed [31:0] find (
+        input [7:0] v
+    );
+        reg return_flag, continue_flag;
+        begin
+            return_flag = 0;
+            continue_flag = 0;
+            begin: unnamed_blk_0
+                reg signed [31:0] i;
+                i = 0;
+                while (!return_flag && (i < 8)) begin
+                    begin
+                        begin
+                            if (!v[i]) continue_flag = 1;
+                            if (!continue_flag) begin
+                                if (i > 5) begin
+                                    find = i;
+                                    return_flag = 1;
+                                end
+                            end
+                        end
+                        continue_flag = 0;
+                    end
+                    if (!return_flag) begin
+                        i = i + 1;
+                    end
+                end
+            end
+            if (!return_flag) begin
+                find = -1;
+            end
+        end
+    endfunction
+");
+    // A continue at the end of the body skips nothing, so the loop is kept.
+    assert_eq!(subroutine(&out, "function automatic signed [31:0] count"), "\
+function automatic signed [31:0] count (
+        input [7:0] v
+    );
+        begin
+            count = 0;
+            begin: unnamed_blk_1
+                reg signed [31:0] i;
+                for (i = 0; i < 8; i = i + 1) begin
+                    if (v[i]) count = count + 1; else ;
+                end
+            end
+        end
+    endfunction
+");
+}
+
+#[test]
+fn break_in_procedural_block() {
+    let out = elaborate(r#"
+module top(input logic [7:0] x, output logic [7:0] y);
+  always_comb begin
+    y = 0;
+    for (int i = 0; i < 8; i++) begin
+      if (x[i]) break;
+      y++;
+    end
+  end
+endmodule
+"#, &["--std", "verilog2005"]);
+    assert!(out.contains("\
+    always @* begin: unnamed_blk_1
+        reg break_flag;
+        break_flag = 0;
+"), "{}", out);
+    assert!(out.contains("while (!break_flag && (i < 8)) begin"), "{}", out);
+}
+
+#[test]
+fn misplaced_jumps() {
+    let err = elaborate_err(r#"
+module top;
+  function automatic int f(input int a);
+    if (a) return;
+    continue;
+  endfunction
+  function automatic void g();
+    return 1;
+  endfunction
+  always_comb return;
+  always_comb break;
+endmodule
+"#, &[]);
+    assert!(err.contains("error: return statement must have a value in a non-void function"), "{}", err);
+    assert!(err.contains("error: continue statement must be within a loop"), "{}", err);
+    assert!(err.contains("error: void functions and tasks cannot return a value"), "{}", err);
+    assert!(err.contains("error: return statement must be within a function or task"), "{}", err);
+    assert!(err.contains("error: break statement must be within a loop"), "{}", err);
+}
@@ -0,0 +1,125 @@
+//! Macro expansion by the preprocessor, checked using the output of `-E`.
+
+mod common;
+
+use common::TestDir;
+
+/// Preprocess a single file with the given extra arguments, and return the output.
+fn preprocess(source: &str, args: &[&str]) -> String {
+    let dir = TestDir::new(&[("top.sv", source)]);
+    let mut all_args = vec!["-E"];
+    all_args.extend(args);
+    all_args.push("top.sv");
+    let output = dir.run(&all_args);
+    assert!(output.success, "preprocessing failed:\n{}", output.stderr);
+    output.stdout
+}
+
+/// Preprocess a single file, and return the diagnostics, which must include an error.
+fn preprocess_err(source: &str) -> String {
+    let dir = TestDir::new(&[("top.sv", source)]);
+    let output = dir.run(&["-E", "top.sv"]);
+    assert!(!output.success, "preprocessing unexpectedly succeeded:\n{}", output.stdout);
+    output.stderr
+}
+
+#[test]
+fn expansion() {
+    let out = preprocess(r#"`define WIDTH 8
+`define DOUBLE(x) (2 * (x))
+`define NEST(x) `DOUBLE(`DOUBLE(x))
+`define REG(name, w = `WIDTH) logic [w-1:0] name;
+`define CAT(a, b) a``b
+module top;
+  `REG(a)
+  `REG(b, 4)
+  logic [`NEST(1)-1:0] `CAT(my_, sig);
+endmodule
+"#, &[]);
+    assert_eq!(out, r#"`line 1 "top.sv" 0
+
+
+
+
+
+module top;
+  logic [8-1:0] a;
+  logic [4-1:0] b;
+  logic [(2 * ((2 * ( 1 ))))-1:0] my_sig;
+endmodule
+"#);
+}
+
+#[test]
+fn stringification() {
+    let out = preprocess(r#"`define STR(x) `"x`"
+`define QUOTE(x) `"say `\`"x`\`"`"
+localparam string S = `STR(hello world);
+localparam string Q = `QUOTE(hi);
+"#, &[]);
+    assert_eq!(out, r#"`line 1 "top.sv" 0
+
+
+localparam string S = "hello world";
+localparam string Q = "say \"hi\"";
+"#);
+}
+
+#[test]
+fn empty_and_default_arguments() {
+    let out = preprocess(r#"`define ADD(a, b = 1) a + b
+`define ALL(a, b, c = 3) {a, b, c}
+x = `ADD(3);
+x = `ADD(3, );
+x = `ADD(3, 2);
+x = `ALL(1, , );
+"#, &[]);
+    assert_eq!(out, r#"`line 1 "top.sv" 0
+
+
+x = 3 + 1;
+x = 3 + 1;
+x = 3 + 2;
+x = {1, , 3};
+"#);
+}
+
+#[test]
+fn command_line_macros() {
+    let source = "logic [`W-1:0] a;\n`ifdef X\nlogic x;\n`endif\n";
+    let out = preprocess(source, &["-D", "W=16", "-D", "X"]);
+    assert_eq!(out, "`line 1 \"top.sv\" 0\nlogic [16-1:0] a;\n\nlogic x;\n");
+    // -U applies after the -D before it
+    let out = preprocess(source, &["-D", "W=16", "-D", "X", "-U", "X"]);
+    assert_eq!(out, "`line 1 \"top.sv\" 0\nlogic [16-1:0] a;\n");
+}
+
+#[test]
+fn recursive_macro() {
+    let err = preprocess_err("`define A `B\n`define B `A\nlogic [`A:0] x;\n");
+    assert!(err.contains("error: macro A is expanded recursively"), "{}", err);
+}
+
+#[test]
+fn missing_argument() {
+    let err = preprocess_err("`define F(a, b) a b\n`F(1)\n");
+    assert!(err.contains("error: missing argument b for macro F"), "{}", err);
+}
+
+#[test]
+fn missing_argument_list() {
+    let err = preprocess_err("`define F(a) a\n`F\n");
+    assert!(err.contains("error: expected actual arguments for function-like macro"), "{}", err);
+}
+
+#[test]
+fn undefined_macro() {
+    let err = preprocess_err("`UNDEFINED\n");
+    assert!(err.contains("error: cannot find macro UNDEFINED"), "{}", err);
+}
+
+#[test]
+fn misplaced_token_pasting() {
+    let err = preprocess_err("`define P(a) a``\n`P(1)\n");
+    assert!(err.contains("error: `` must be placed between two tokens"), "{}", err);
+}